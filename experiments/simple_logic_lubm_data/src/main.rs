@@ -1,14 +1,30 @@
-use reasoning_service::encoder::{BiMapEncoder, EncoderUnit, NTriplesParser, SimpleLogic};
+use reasoning_service::encoder::{
+    BiMapEncoder, EncoderUnit, NTriplesParser, PackedTriple, SimpleLogic,
+};
+use reasoning_service::reasoners::Reasoner;
+use structopt::StructOpt;
+
+// The encoded triple type is a type parameter of the whole computation, so each choice gets its
+// own instance of it
+macro_rules! materialize {
+    ($encoder:ty, $args:expr) => {{
+        let parser = NTriplesParser::new();
+        let encoding_logic = SimpleLogic::new(0);
+        let encoder: EncoderUnit<_, _, $encoder, _, _> = EncoderUnit::new(parser, encoding_logic);
+
+        let reasoner = Reasoner::from_args($args).expect("Invalid arguments");
+        reasoning_service::run_materialization(encoder, reasoner)
+            .expect("Could not run computation");
+    }};
+}
 
 fn main() {
     env_logger::init();
 
-    let parser = NTriplesParser::new();
-    let encoding_logic = SimpleLogic::new(0);
-    let encoder: EncoderUnit<_, _, BiMapEncoder, _, _> = EncoderUnit::new(parser, encoding_logic);
-
-    reasoning_service::run_materialization(encoder, move |data_input, mut probe, rdfs_keywords| {
-        simple_logic_lubm_data::full_materialization(&data_input, &mut probe, &rdfs_keywords)
-    })
-    .expect("Could not run computation");
+    let args = reasoning_service::Args::from_args();
+    if args.packed {
+        materialize!(BiMapEncoder<PackedTriple>, &args);
+    } else {
+        materialize!(BiMapEncoder, &args);
+    }
 }
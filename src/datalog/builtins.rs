@@ -0,0 +1,171 @@
+use crate::model::{AggregateFunction, BuiltinPredicate, Literal};
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// The value of an argument of a builtin: a literal when the encoding is a known literal, the
+/// encoding itself otherwise.
+pub(crate) enum Value<'a, V> {
+    Term(&'a V),
+    Literal(&'a Literal),
+}
+
+// Numbers compare by value whatever their datatype, strings by lexical form. Other terms are
+// only equal to themselves and have no order.
+fn compare<V: Eq>(left: &Value<V>, right: &Value<V>) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Literal(a), Value::Literal(b)) => match (a.as_number(), b.as_number()) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ if a.is_string() && b.is_string() => Some(a.lexical.cmp(&b.lexical)),
+            _ if a == b => Some(Ordering::Equal),
+            _ => None,
+        },
+        (Value::Term(a), Value::Term(b)) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+// Whether a comparison holds given the order of its arguments, `None` if they are incomparable
+fn ordered(predicate: BuiltinPredicate, ordering: Option<Ordering>) -> bool {
+    match predicate {
+        BuiltinPredicate::Equal => ordering == Some(Ordering::Equal),
+        BuiltinPredicate::NotEqual => ordering != Some(Ordering::Equal),
+        BuiltinPredicate::Less => ordering == Some(Ordering::Less),
+        BuiltinPredicate::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        BuiltinPredicate::Greater => ordering == Some(Ordering::Greater),
+        BuiltinPredicate::GreaterOrEqual => {
+            matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+        }
+        _ => false,
+    }
+}
+
+fn strings<'a, V>(left: &Value<'a, V>, right: &Value<'a, V>) -> Option<(&'a str, &'a str)> {
+    match (left, right) {
+        (Value::Literal(a), Value::Literal(b)) => Some((&a.lexical, &b.lexical)),
+        _ => None,
+    }
+}
+
+/// Whether `predicate` holds on the two values. `pattern` is the compiled second argument of a
+/// regex.
+pub(crate) fn holds<V: Eq>(
+    predicate: BuiltinPredicate,
+    left: Value<V>,
+    right: Value<V>,
+    pattern: Option<&Regex>,
+) -> bool {
+    match predicate {
+        BuiltinPredicate::Contains => strings(&left, &right).is_some_and(|(a, b)| a.contains(b)),
+        BuiltinPredicate::StartsWith => {
+            strings(&left, &right).is_some_and(|(a, b)| a.starts_with(b))
+        }
+        BuiltinPredicate::EndsWith => strings(&left, &right).is_some_and(|(a, b)| a.ends_with(b)),
+        BuiltinPredicate::Regex => match (left, pattern) {
+            (Value::Literal(literal), Some(pattern)) => pattern.is_match(&literal.lexical),
+            _ => false,
+        },
+        comparison => ordered(comparison, compare(&left, &right)),
+    }
+}
+
+/// Whether `predicate` holds between the value of an aggregate and a term, which must be a
+/// numeric literal to be comparable
+pub(crate) fn holds_for_aggregate<V>(
+    predicate: BuiltinPredicate,
+    total: f64,
+    bound: Value<V>,
+) -> bool {
+    let bound = match bound {
+        Value::Literal(literal) => literal.as_number(),
+        Value::Term(_) => None,
+    };
+    ordered(predicate, bound.and_then(|bound| total.partial_cmp(&bound)))
+}
+
+/// Aggregates the numbers, given with their multiplicity. `None` when there is nothing to
+/// aggregate, but for a count which is then 0.
+pub(crate) fn aggregate<I>(function: AggregateFunction, numbers: I) -> Option<f64>
+where
+    I: IntoIterator<Item = (f64, isize)>,
+{
+    let mut numbers = numbers.into_iter().peekable();
+    if function != AggregateFunction::Count {
+        numbers.peek()?;
+    }
+    Some(match function {
+        AggregateFunction::Count => numbers.map(|(_, count)| count as f64).sum(),
+        AggregateFunction::Sum => numbers.map(|(n, count)| n * count as f64).sum(),
+        AggregateFunction::Min => numbers.map(|(n, _)| n).fold(f64::INFINITY, f64::min),
+        AggregateFunction::Max => numbers.map(|(n, _)| n).fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, holds, holds_for_aggregate, Value};
+    use crate::model::{AggregateFunction, BuiltinPredicate, Literal};
+
+    #[test]
+    fn compares_numbers_by_value_and_strings_lexically() {
+        let integer = Literal::parse("\"18\"^^<http://www.w3.org/2001/XMLSchema#integer>").unwrap();
+        let decimal =
+            Literal::parse("\"9.5\"^^<http://www.w3.org/2001/XMLSchema#decimal>").unwrap();
+        let name = Literal::parse("\"Smith\"@en").unwrap();
+        let check = |predicate, left: &Literal, right: &Literal| {
+            holds::<u64>(predicate, Value::Literal(left), Value::Literal(right), None)
+        };
+
+        assert!(check(BuiltinPredicate::Greater, &integer, &decimal));
+        assert!(!check(BuiltinPredicate::Less, &name, &integer));
+        assert!(check(BuiltinPredicate::NotEqual, &name, &integer));
+        assert!(check(
+            BuiltinPredicate::StartsWith,
+            &name,
+            &Literal::parse("\"Sm\"").unwrap()
+        ));
+        assert!(holds(
+            BuiltinPredicate::Equal,
+            Value::Term(&1),
+            Value::Term(&1),
+            None
+        ));
+        assert!(!holds(
+            BuiltinPredicate::LessOrEqual,
+            Value::Term(&1),
+            Value::Term(&2),
+            None
+        ));
+    }
+
+    #[test]
+    fn aggregates_numbers() {
+        let numbers = vec![(2.0, 1), (5.5, 1), (-1.0, 2)];
+        assert_eq!(
+            aggregate(AggregateFunction::Count, numbers.clone()),
+            Some(4.0)
+        );
+        assert_eq!(
+            aggregate(AggregateFunction::Sum, numbers.clone()),
+            Some(5.5)
+        );
+        assert_eq!(
+            aggregate(AggregateFunction::Min, numbers.clone()),
+            Some(-1.0)
+        );
+        assert_eq!(aggregate(AggregateFunction::Max, numbers), Some(5.5));
+        assert_eq!(aggregate(AggregateFunction::Max, vec![]), None);
+        assert_eq!(aggregate(AggregateFunction::Count, vec![]), Some(0.0));
+
+        let fifty = Literal::parse("\"50\"^^<http://www.w3.org/2001/XMLSchema#int>").unwrap();
+        assert!(holds_for_aggregate::<u64>(
+            BuiltinPredicate::Greater,
+            51.0,
+            Value::Literal(&fifty)
+        ));
+        assert!(!holds_for_aggregate(
+            BuiltinPredicate::Greater,
+            51.0,
+            Value::Term(&50)
+        ));
+    }
+}
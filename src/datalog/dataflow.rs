@@ -0,0 +1,481 @@
+use crate::datalog::builtins::{aggregate, holds, holds_for_aggregate, Value};
+use crate::datalog::{CompiledAggregate, CompiledBuiltin, CompiledRule, Operand, Term};
+use crate::encoder::Triple;
+use crate::model::{AggregateFunction, BuiltinPredicate, Literal};
+use crate::operators::{JoinStrategy, Partitions, SkewedJoin};
+use crate::reasoners::explanation::Support;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::join::Join;
+use differential_dataflow::operators::reduce::{Count, Reduce, Threshold};
+use differential_dataflow::{Collection, ExchangeData};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use timely::dataflow::Scope;
+
+// Checks a triple against an atom and returns the values of the variables of the atom, in order
+// of first appearance. A variable repeated in the atom must match the same value.
+pub(crate) fn match_atom<V, T>(atom: &[Term<V>; 3], triple: &T) -> Option<Vec<V>>
+where
+    V: Eq + Clone,
+    T: Triple<V>,
+{
+    let mut seen: Vec<usize> = vec![];
+    let mut values = vec![];
+    let terms = [triple.s(), triple.p(), triple.o()];
+    for (term, value) in atom.iter().zip(terms.iter().map(|term| &**term)) {
+        match term {
+            Term::Constant(c) => {
+                if c != value {
+                    return None;
+                }
+            }
+            Term::Variable(v) => match seen.iter().position(|x| x == v) {
+                Some(i) => {
+                    if &values[i] != value {
+                        return None;
+                    }
+                }
+                None => {
+                    seen.push(*v);
+                    values.push(value.clone());
+                }
+            },
+        }
+    }
+    Some(values)
+}
+
+pub(crate) fn atom_variables<V>(atom: &[Term<V>; 3]) -> Vec<usize> {
+    let mut variables = vec![];
+    for term in atom.iter() {
+        if let Term::Variable(v) = term {
+            if !variables.contains(v) {
+                variables.push(*v);
+            }
+        }
+    }
+    variables
+}
+
+fn project<V: Clone>(values: &[V], positions: &[usize]) -> Vec<V> {
+    positions.iter().map(|&i| values[i].clone()).collect()
+}
+
+/// Bindings of the variables in `variables`, one vector of values per satisfying assignment
+pub(crate) struct Bindings<G: Scope, V> {
+    pub(crate) variables: Vec<usize>,
+    pub(crate) collection: Collection<G, Vec<V>>,
+}
+
+pub(crate) fn atom_bindings<G, T, V>(
+    atom: &[Term<V>; 3],
+    facts: &Partitions<G, V, T>,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let pattern = atom.clone();
+    Bindings {
+        variables: atom_variables(atom),
+        collection: matching(atom, facts).flat_map(move |triple| match_atom(&pattern, &triple)),
+    }
+}
+
+// The partition of `facts` holding the triples that can match the atom
+fn matching<'a, G, T, V>(
+    atom: &[Term<V>; 3],
+    facts: &'a Partitions<G, V, T>,
+) -> &'a Collection<G, T>
+where
+    G: Scope,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    match (&atom[1], &atom[2]) {
+        (Term::Constant(p), Term::Constant(o)) => facts.matching(p, Some(o)),
+        (Term::Constant(p), _) => facts.matching(p, None),
+        _ => facts.all(),
+    }
+}
+
+/// Joins two sets of bindings on their shared variables
+pub(crate) fn join_bindings<G, V>(
+    left: Bindings<G, V>,
+    right: Bindings<G, V>,
+    joins: JoinStrategy,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+{
+    let shared: Vec<usize> = right
+        .variables
+        .iter()
+        .filter(|v| left.variables.contains(v))
+        .cloned()
+        .collect();
+    let left_key: Vec<usize> = shared
+        .iter()
+        .map(|v| left.variables.iter().position(|x| x == v).unwrap())
+        .collect();
+    let right_key: Vec<usize> = shared
+        .iter()
+        .map(|v| right.variables.iter().position(|x| x == v).unwrap())
+        .collect();
+    let right_rest: Vec<usize> = (0..right.variables.len())
+        .filter(|i| !right_key.contains(i))
+        .collect();
+
+    let mut variables = left.variables;
+    variables.extend(right_rest.iter().map(|&i| right.variables[i]));
+
+    let keyed_left = left
+        .collection
+        .map(move |values| (project(&values, &left_key), values));
+    let keyed_right = right
+        .collection
+        .map(move |values| (project(&values, &right_key), project(&values, &right_rest)));
+    let extend = |_key: &Vec<V>, values: &Vec<V>, rest: &Vec<V>| {
+        let mut joined = values.clone();
+        joined.extend(rest.iter().cloned());
+        joined
+    };
+    let collection = match joins {
+        JoinStrategy::Hash => keyed_left.join_map(&keyed_right, extend),
+        JoinStrategy::Skewed { threshold } => {
+            keyed_left.join_skewed(&keyed_right, threshold, extend)
+        }
+    };
+
+    Bindings {
+        variables,
+        collection,
+    }
+}
+
+/// Evaluates the body of the rule on `facts` and returns the bindings of its variables
+pub(crate) fn body_bindings<G, T, V>(
+    body: &[[Term<V>; 3]],
+    facts: &Partitions<G, V, T>,
+    joins: JoinStrategy,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let mut atoms = body.iter();
+    let first = atoms
+        .next()
+        .expect("A rule has at least one atom in its body");
+    let mut bindings = atom_bindings(first, facts);
+    for atom in atoms {
+        bindings = join_bindings(bindings, atom_bindings(atom, facts), joins);
+    }
+    bindings
+}
+
+fn position(variables: &[usize], variable: usize) -> Option<usize> {
+    variables.iter().position(|x| *x == variable)
+}
+
+fn value<'a, V>(
+    operand: &'a Operand<V>,
+    values: &'a [V],
+    literals: &'a HashMap<V, Literal>,
+) -> Value<'a, V>
+where
+    V: Eq + std::hash::Hash,
+{
+    let term = match operand {
+        Operand::Literal(literal) => return Value::Literal(literal),
+        Operand::Term(Term::Variable(i)) => &values[*i],
+        Operand::Term(Term::Constant(c)) => c,
+    };
+    literals.get(term).map_or(Value::Term(term), Value::Literal)
+}
+
+/// Keeps the bindings that satisfy `filter`, looking up the values of the literals in `literals`
+pub(crate) fn filter_bindings<G, V>(
+    bindings: Bindings<G, V>,
+    filter: &CompiledBuiltin<V>,
+    literals: Arc<HashMap<V, Literal>>,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+{
+    let predicate = filter.predicate();
+    let arguments: Vec<Operand<V>> = filter
+        .arguments()
+        .iter()
+        .map(|operand| remap(operand, &bindings.variables))
+        .collect();
+    let pattern = match (predicate, &arguments[1]) {
+        (BuiltinPredicate::Regex, Operand::Literal(pattern)) => {
+            Some(Regex::new(&pattern.lexical).expect("Patterns are validated with the rules"))
+        }
+        _ => None,
+    };
+
+    let collection = bindings.collection.filter(move |values| {
+        holds(
+            predicate,
+            value(&arguments[0], values, &literals),
+            value(&arguments[1], values, &literals),
+            pattern.as_ref(),
+        )
+    });
+
+    Bindings {
+        variables: bindings.variables,
+        collection,
+    }
+}
+
+fn remap<V: Clone>(operand: &Operand<V>, variables: &[usize]) -> Operand<V> {
+    match operand {
+        Operand::Term(Term::Variable(v)) => Operand::Term(Term::Variable(
+            position(variables, *v).expect("Compared variables are bound by the body"),
+        )),
+        other => other.clone(),
+    }
+}
+
+/// Keeps the bindings whose group satisfies `aggregate`. The aggregate is maintained per group
+/// with `count` or `reduce`, so it follows the updates of the triples it is computed from.
+pub(crate) fn aggregate_bindings<G, T, V>(
+    bindings: Bindings<G, V>,
+    aggregate_of: &CompiledAggregate<V>,
+    facts: &Partitions<G, V, T>,
+    literals: Arc<HashMap<V, Literal>>,
+    joins: JoinStrategy,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let inner = body_bindings(aggregate_of.body(), facts, joins);
+    let mut inner_key = vec![];
+    let mut outer_key = vec![];
+    for (i, v) in inner.variables.iter().enumerate() {
+        if let Some(p) = position(&bindings.variables, *v) {
+            inner_key.push(i);
+            outer_key.push(p);
+        }
+    }
+    let aggregated = position(&inner.variables, aggregate_of.variable())
+        .expect("The aggregated variable appears in the atoms of the aggregate");
+    // Each binding of the atoms counts once, whatever the number of derivations of its triples
+    let groups = inner
+        .collection
+        .distinct()
+        .map(move |values| (project(&values, &inner_key), values[aggregated].clone()));
+
+    // The aggregates travel as the bits of an f64 as they need to be hashable
+    let function = aggregate_of.function();
+    let totals = match function {
+        // A count is the number of distinct values of the variable, 0 for a group of the body
+        // without bindings of the atoms
+        AggregateFunction::Count => {
+            let counts = groups
+                .distinct()
+                .map(|(group, _)| group)
+                .count()
+                .map(|(group, count)| (group, (count as f64).to_bits()));
+            let outer_key = outer_key.clone();
+            let empty = bindings
+                .collection
+                .map(move |values| (project(&values, &outer_key), ()))
+                .distinct()
+                .antijoin(&counts.map(|(group, _)| group))
+                .map(|(group, ())| (group, 0f64.to_bits()));
+            counts.concat(&empty)
+        }
+        _ => {
+            let literals = literals.clone();
+            groups.reduce(move |_group, input, output| {
+                let numbers = input.iter().filter_map(|(value, count)| {
+                    literals
+                        .get(*value)
+                        .and_then(Literal::as_number)
+                        .map(|n| (n, *count))
+                });
+                if let Some(total) = aggregate(function, numbers) {
+                    output.push((total.to_bits(), 1));
+                }
+            })
+        }
+    };
+
+    let predicate = aggregate_of.predicate();
+    let bound = remap(aggregate_of.bound(), &bindings.variables);
+    let collection = bindings
+        .collection
+        .map(move |values| (project(&values, &outer_key), values))
+        .join_map(&totals, |_group, values, total| (values.clone(), *total))
+        .filter(move |(values, total)| {
+            holds_for_aggregate(
+                predicate,
+                f64::from_bits(*total),
+                value(&bound, values, &literals),
+            )
+        })
+        .map(|(values, _)| values);
+
+    Bindings {
+        variables: bindings.variables,
+        collection,
+    }
+}
+
+/// Removes the bindings for which a triple of `facts` matches `atom`
+pub(crate) fn negate_bindings<G, T, V>(
+    bindings: Bindings<G, V>,
+    atom: &[Term<V>; 3],
+    facts: &Partitions<G, V, T>,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    // The variables of the atom that are not bound are existential: they are projected away
+    let mut atom_key = vec![];
+    let mut bindings_key = vec![];
+    for (i, v) in atom_variables(atom).into_iter().enumerate() {
+        if let Some(p) = position(&bindings.variables, v) {
+            atom_key.push(i);
+            bindings_key.push(p);
+        }
+    }
+    let pattern = atom.clone();
+    let present = matching(atom, facts)
+        .flat_map(move |triple| {
+            match_atom(&pattern, &triple).map(|values| project(&values, &atom_key))
+        })
+        .distinct();
+
+    let collection = bindings
+        .collection
+        .map(move |values| (project(&values, &bindings_key), values))
+        .antijoin(&present)
+        .map(|(_, values)| values);
+
+    Bindings {
+        variables: bindings.variables,
+        collection,
+    }
+}
+
+// The bindings of the variables of the rule that satisfy its body
+fn rule_bindings<G, T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Partitions<G, V, T>,
+    literals: &Arc<HashMap<V, Literal>>,
+    joins: JoinStrategy,
+) -> Bindings<G, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let mut bindings = body_bindings(rule.body(), facts, joins);
+    for filter in rule.filters() {
+        bindings = filter_bindings(bindings, filter, literals.clone());
+    }
+    for aggregate in rule.aggregates() {
+        bindings = aggregate_bindings(bindings, aggregate, facts, literals.clone(), joins);
+    }
+    for atom in rule.negated() {
+        bindings = negate_bindings(bindings, atom, facts);
+    }
+    bindings
+}
+
+// The atom with its variables replaced by their position in `variables`
+pub(crate) fn positions<V: Clone>(atom: &[Term<V>; 3], variables: &[usize]) -> [Term<V>; 3] {
+    atom.clone().map(|term| match term {
+        Term::Variable(v) => Term::Variable(
+            position(variables, v).expect("Head and body variables are bound by the body"),
+        ),
+        constant => constant,
+    })
+}
+
+// The triple of an atom returned by `positions`, for the values of a binding
+pub(crate) fn instantiate<T, V>(atom: &[Term<V>; 3], values: &[V]) -> T
+where
+    V: Clone,
+    T: Triple<V>,
+{
+    let value = |term: &Term<V>| match term {
+        Term::Variable(i) => values[*i].clone(),
+        Term::Constant(c) => c.clone(),
+    };
+    T::from_spo(value(&atom[0]), value(&atom[1]), value(&atom[2]))
+}
+
+/// Triples derived by one application of the rule to `facts`
+pub(crate) fn derive<G, T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Partitions<G, V, T>,
+    literals: &Arc<HashMap<V, Literal>>,
+    joins: JoinStrategy,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let bindings = rule_bindings(rule, facts, literals, joins);
+    let head = positions(rule.head(), &bindings.variables);
+
+    bindings
+        .collection
+        .map(move |values| instantiate(&head, &values))
+}
+
+/// The supports of one application of the rule to `facts`: each derived triple with the triples
+/// matched by the atoms of the body
+pub(crate) fn supports<G, T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Partitions<G, V, T>,
+    literals: &Arc<HashMap<V, Literal>>,
+    joins: JoinStrategy,
+) -> Collection<G, (T, Support<T>)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+    T: ExchangeData + Triple<V>,
+{
+    let bindings = rule_bindings(rule, facts, literals, joins);
+    let head = positions(rule.head(), &bindings.variables);
+    let body: Vec<[Term<V>; 3]> = rule
+        .body()
+        .iter()
+        .map(|atom| positions(atom, &bindings.variables))
+        .collect();
+    let name = rule.name().to_string();
+
+    bindings.collection.map(move |values| {
+        let premises = body.iter().map(|atom| instantiate(atom, &values)).collect();
+        (
+            instantiate(&head, &values),
+            Support::new(name.clone(), premises),
+        )
+    })
+}
@@ -0,0 +1,213 @@
+//! Delete/Rederive, the classic maintenance of a materialization held in memory, without
+//! dataflow. It is the engine the differential dataflow one is compared with.
+//!
+//! An update first deletes every triple that has a derivation through a deleted triple, then
+//! puts back the ones that the remaining triples still derive in one step, and last inserts the
+//! consequences of the rederived and the inserted triples, semi-naively. Only the monotone rules
+//! can be maintained this way: a rule with negated atoms or aggregates is rejected.
+use crate::datalog::sequential::Facts;
+use crate::datalog::{CompiledProgram, RuleError};
+use crate::encoder::Triple;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// What an update did to the materialization
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Maintenance {
+    /// Triples deleted because they had a derivation through a deleted triple
+    pub overdeleted: usize,
+    /// Overdeleted triples put back because they are still derived
+    pub rederived: usize,
+    /// Triples added by the insertion phase, rederived ones included
+    pub inserted: usize,
+}
+
+/// A materialization maintained by Delete/Rederive
+pub struct DRed<T, V> {
+    program: CompiledProgram<V>,
+    asserted: HashSet<T>,
+    materialized: Facts<T, V>,
+}
+
+impl<T, V> DRed<T, V>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Clone + Triple<V>,
+{
+    /// An empty materialization of the program, which must not negate or aggregate triples
+    pub fn new(program: CompiledProgram<V>) -> Result<Self, RuleError> {
+        let rule = program
+            .rules()
+            .iter()
+            .find(|rule| !rule.negated().is_empty() || !rule.aggregates().is_empty());
+        if let Some(rule) = rule {
+            return Err(RuleError::NotMonotone(rule.name().to_string()));
+        }
+        Ok(Self {
+            program,
+            asserted: HashSet::new(),
+            materialized: Facts::new(),
+        })
+    }
+
+    /// The base facts
+    pub fn asserted(&self) -> &HashSet<T> {
+        &self.asserted
+    }
+
+    /// The triples of the materialization
+    pub fn materialized(&self) -> impl Iterator<Item = &T> {
+        self.materialized.iter()
+    }
+
+    /// Number of triples of the materialization
+    pub fn len(&self) -> usize {
+        self.materialized.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materialized.len() == 0
+    }
+
+    /// Inserts and deletes base facts. Inserting a base fact or deleting a triple that is not
+    /// one changes nothing.
+    pub fn update<I, D>(&mut self, inserted: I, deleted: D) -> Maintenance
+    where
+        I: IntoIterator<Item = T>,
+        D: IntoIterator<Item = T>,
+    {
+        let deleted: Vec<T> = deleted
+            .into_iter()
+            .filter(|triple| self.asserted.remove(triple))
+            .collect();
+        let inserted: Vec<T> = inserted
+            .into_iter()
+            .filter(|triple| self.asserted.insert(triple.clone()))
+            .collect();
+
+        // Every triple that has a derivation through a deleted one, found on the materialization
+        // as it was before the update
+        let mut overdeleted: Facts<T, V> = Facts::new();
+        let mut delta: Facts<T, V> = deleted.into_iter().collect();
+        while delta.len() > 0 {
+            let derived = self.program.derive_from(&self.materialized, &delta);
+            for triple in delta.into_set() {
+                overdeleted.insert(triple);
+            }
+            delta = derived
+                .into_iter()
+                .filter(|triple| !overdeleted.contains(triple))
+                .collect();
+        }
+        for triple in overdeleted.iter() {
+            self.materialized.remove(triple);
+        }
+
+        let rederived: Vec<T> = overdeleted
+            .iter()
+            .filter(|triple| {
+                self.asserted.contains(*triple) || self.program.derives(&self.materialized, *triple)
+            })
+            .cloned()
+            .collect();
+        let maintenance = Maintenance {
+            overdeleted: overdeleted.len(),
+            rederived: rederived.len(),
+            inserted: 0,
+        };
+
+        let known = self.materialized.len();
+        let mut delta: Facts<T, V> = rederived
+            .into_iter()
+            .chain(inserted)
+            .filter(|triple| !self.materialized.contains(triple))
+            .collect();
+        while delta.len() > 0 {
+            for triple in delta.iter() {
+                self.materialized.insert(triple.clone());
+            }
+            delta = self
+                .program
+                .derive_from(&self.materialized, &delta)
+                .into_iter()
+                .filter(|triple| !self.materialized.contains(triple))
+                .collect();
+        }
+
+        Maintenance {
+            inserted: self.materialized.len() - known,
+            ..maintenance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DRed;
+    use crate::datalog::{Program, RuleError};
+    use crate::model::{RDFS_SUB_CLASS_OF, RDF_TYPE};
+    use crate::tests::{facts_after_batches, TestTriple};
+    use std::collections::HashSet;
+
+    #[test]
+    fn maintains_the_closure_through_updates() {
+        let (sco, typ) = (0, 1);
+        let (student, person, agent, x, y) = (10, 11, 12, 20, 21);
+        let program = Program::parse(
+            "
+            T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+            T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            iri if iri == RDFS_SUB_CLASS_OF => Some(sco),
+            iri if iri == RDF_TYPE => Some(typ),
+            _ => None,
+        })
+        .unwrap();
+        let mut dred = DRed::new(program.clone()).unwrap();
+
+        let batches = vec![
+            vec![
+                ((student, sco, person), 1),
+                ((person, sco, agent), 1),
+                ((agent, sco, person), 1),
+                ((x, typ, student), 1),
+            ],
+            // x is asserted to be a person as well, which outlives the deletion of its type
+            vec![((y, typ, student), 1), ((x, typ, person), 1)],
+            // The cycle goes, (person, sco, person) with it
+            vec![((agent, sco, person), -1), ((x, typ, student), -1)],
+            vec![((student, sco, person), -1), ((x, typ, person), -1)],
+        ];
+        for (batch, facts) in batches.iter().zip(facts_after_batches(&batches)) {
+            let inserted = batch.iter().filter(|(_, diff)| *diff > 0);
+            let deleted = batch.iter().filter(|(_, diff)| *diff < 0);
+            dred.update(
+                inserted.map(|(triple, _)| *triple),
+                deleted.map(|(triple, _)| *triple),
+            );
+            let materialized: HashSet<TestTriple> = dred.materialized().copied().collect();
+            assert_eq!(materialized, program.materialize_set(facts));
+        }
+        assert!(dred.asserted().contains(&(y, typ, student)));
+        assert_eq!(dred.len(), 2);
+
+        let negated = Program::parse(
+            "T(?x, TYPE, <http://a>) <= T(?x, TYPE, ?c), not T(?x, TYPE, <http://b>)",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            "<http://a>" => Some(100),
+            "<http://b>" => Some(101),
+            iri if iri == RDF_TYPE => Some(typ),
+            _ => None,
+        })
+        .unwrap();
+        assert!(matches!(
+            DRed::<TestTriple, _>::new(negated),
+            Err(RuleError::NotMonotone(_))
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+//! Goal-directed evaluation of a program for a single triple pattern, with the magic sets
+//! transformation.
+//!
+//! The positions of the pattern that are constants are its adornment. Each rule is adorned by the
+//! positions of its head a demand binds, and passes its bindings sideways, from left to right:
+//! an atom of the body is asked for with the constants it has and the variables bound by the head
+//! or by the atoms before it. The adornments reachable from the one of the query are computed
+//! beforehand, so every rule is evaluated once per adornment it can be asked for.
+//!
+//! The rule language has the single predicate `T`, so the magic predicates can't be written as
+//! rules: the demands are triple patterns kept in their own collection, which grows in the same
+//! iterative scope as the facts. A rule then only derives the triples of its head some demand
+//! asks for, and only the triples relevant to the query are materialized. The demands follow
+//! the updates of the facts like any other collection of the dataflow.
+use crate::datalog::dataflow::{
+    atom_bindings, atom_variables, filter_bindings, instantiate, join_bindings, match_atom,
+    positions, Bindings,
+};
+use crate::datalog::{CompiledProgram, RuleError, Term};
+use crate::encoder::Triple;
+use crate::operators::SetSemantics;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::iterate::Variable;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::operators::ToStream;
+use timely::dataflow::{ProbeHandle, Scope};
+use timely::order::Product;
+
+/// The positions of a triple pattern that are bound
+type Adornment = [bool; 3];
+
+/// A triple pattern asked for by the query or by a rule: the values of its bound positions
+type Demand<V> = [Option<V>; 3];
+
+// A rule evaluated for the demands of one adornment
+#[derive(Debug, Clone)]
+struct AdornedRule<V> {
+    rule: usize,
+    adornment: Adornment,
+    // The variables the demand binds in the head, in order of first appearance
+    bound: Vec<usize>,
+    // The pattern each atom of the body is asked for with: its constants and the variables
+    // bound before it
+    demands: Vec<[Option<Term<V>>; 3]>,
+}
+
+// The positions of a demand, or of an atom of an adorned rule, that are bound
+fn adornment<X>(pattern: &[Option<X>; 3]) -> Adornment {
+    [
+        pattern[0].is_some(),
+        pattern[1].is_some(),
+        pattern[2].is_some(),
+    ]
+}
+
+// The demand of the query: its constants
+fn seed<V: Clone>(query: &[Term<V>; 3]) -> Demand<V> {
+    query.clone().map(|term| match term {
+        Term::Constant(c) => Some(c),
+        Term::Variable(_) => None,
+    })
+}
+
+// The values the demand gives to the `bound` variables of the head, if it can match the head
+fn bind_head<V: Eq + Clone>(
+    head: &[Term<V>; 3],
+    bound: &[usize],
+    demand: &Demand<V>,
+) -> Option<Vec<V>> {
+    let mut values: Vec<Option<V>> = vec![None; bound.len()];
+    for (term, value) in head.iter().zip(demand.iter()) {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        match term {
+            Term::Constant(c) => {
+                if c != value {
+                    return None;
+                }
+            }
+            Term::Variable(v) => {
+                let i = bound.iter().position(|x| x == v)?;
+                match &values[i] {
+                    Some(other) if other != value => return None,
+                    _ => values[i] = Some(value.clone()),
+                }
+            }
+        }
+    }
+    values.into_iter().collect()
+}
+
+// The demand of a pattern returned by `adorn`, its variables replaced by their position in the
+// bindings, for the values of a binding
+fn ask<V: Clone>(pattern: &[Option<Term<V>>; 3], values: &[V]) -> Demand<V> {
+    pattern.clone().map(|term| match term? {
+        Term::Variable(i) => Some(values[i].clone()),
+        Term::Constant(c) => Some(c),
+    })
+}
+
+// The rules adorned by every adornment reachable from the one of the query
+fn adorn<V: Clone>(program: &CompiledProgram<V>, query: Adornment) -> Vec<AdornedRule<V>> {
+    let mut adornments = vec![query];
+    let mut adorned = vec![];
+    let mut next = 0;
+    while next < adornments.len() {
+        let current = adornments[next];
+        next += 1;
+        for (rule, compiled) in program.rules().iter().enumerate() {
+            let mut bound = vec![];
+            for (term, &is_bound) in compiled.head().iter().zip(current.iter()) {
+                match term {
+                    Term::Variable(v) if is_bound && !bound.contains(v) => bound.push(*v),
+                    _ => (),
+                }
+            }
+            let mut known = bound.clone();
+            let mut demands = vec![];
+            for atom in compiled.body() {
+                let demand = atom.clone().map(|term| match term {
+                    Term::Variable(v) if !known.contains(&v) => None,
+                    term => Some(term),
+                });
+                let asked = adornment(&demand);
+                if !adornments.contains(&asked) {
+                    adornments.push(asked);
+                }
+                for v in atom_variables(atom) {
+                    if !known.contains(&v) {
+                        known.push(v);
+                    }
+                }
+                demands.push(demand);
+            }
+            adorned.push(AdornedRule {
+                rule,
+                adornment: current,
+                bound,
+                demands,
+            });
+        }
+    }
+    adorned
+}
+
+/// A program rewritten for a query pattern, whose variables are `Term::Variable`s
+#[derive(Debug, Clone)]
+pub struct MagicSets<V> {
+    program: CompiledProgram<V>,
+    query: [Term<V>; 3],
+    adorned: Vec<AdornedRule<V>>,
+}
+
+impl<V: Clone> MagicSets<V> {
+    /// Rewrites the program for the query. The demands only flow through positive atoms, so the
+    /// program must not negate or aggregate triples.
+    pub fn new(program: CompiledProgram<V>, query: [Term<V>; 3]) -> Result<Self, RuleError> {
+        let rule = program
+            .rules()
+            .iter()
+            .find(|rule| !rule.negated().is_empty() || !rule.aggregates().is_empty());
+        if let Some(rule) = rule {
+            return Err(RuleError::NotMonotone(rule.name().to_string()));
+        }
+        let adorned = adorn(&program, adornment(&seed(&query)));
+        Ok(Self {
+            program,
+            query,
+            adorned,
+        })
+    }
+
+    /// The query pattern
+    pub fn query(&self) -> &[Term<V>; 3] {
+        &self.query
+    }
+
+    /// Number of pairs of a rule and an adornment it is evaluated for
+    pub fn adorned_rules(&self) -> usize {
+        self.adorned.len()
+    }
+}
+
+impl<V> MagicSets<V>
+where
+    V: ExchangeData + std::hash::Hash,
+{
+    /// The triples of the closure of `data_collection` that match the query, computed from the
+    /// triples relevant to it only
+    pub fn answer<G, T>(&self, data_collection: &Collection<G, T>) -> Collection<G, T>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let facts = data_collection.set_semantics();
+        let mut scope = data_collection.scope();
+        // The query is asked for once, by the first worker
+        let first = scope.index() == 0;
+        let seed = Some((seed(&self.query), Default::default(), 1))
+            .filter(|_| first)
+            .to_stream(&mut scope)
+            .as_collection();
+
+        let closure = scope.iterative::<u64, _, _>(|inner| {
+            let step = Product::new(Default::default(), 1);
+            let base = facts.enter(inner);
+            let asked = seed.enter(inner);
+            let facts = Variable::new_from(base.clone(), step.clone());
+            let demands = Variable::new_from(asked.clone(), step);
+
+            let partitions = self.program.partition(&facts);
+            let literals = self.program.literals();
+            let joins = self.program.joins();
+            let mut derived = vec![];
+            let mut demanded = vec![];
+            for adorned in &self.adorned {
+                let rule = &self.program.rules()[adorned.rule];
+                let head = rule.head().clone();
+                let (bound, wanted) = (adorned.bound.clone(), adorned.adornment);
+                let mut bindings = Bindings {
+                    variables: adorned.bound.clone(),
+                    collection: demands
+                        .flat_map(move |demand| {
+                            if adornment(&demand) == wanted {
+                                bind_head(&head, &bound, &demand)
+                            } else {
+                                None
+                            }
+                        })
+                        .set_semantics(),
+                };
+                for (atom, demand) in rule.body().iter().zip(&adorned.demands) {
+                    let pattern = demand.clone().map(|term| match term? {
+                        Term::Variable(v) => {
+                            let i = bindings.variables.iter().position(|x| *x == v);
+                            Some(Term::Variable(i.expect("Asked variables are bound")))
+                        }
+                        constant => Some(constant),
+                    });
+                    demanded.push(
+                        bindings
+                            .collection
+                            .map(move |values| ask(&pattern, &values)),
+                    );
+                    bindings = join_bindings(bindings, atom_bindings(atom, &partitions), joins);
+                }
+                for filter in rule.filters() {
+                    bindings = filter_bindings(bindings, filter, literals.clone());
+                }
+                let head = positions(rule.head(), &bindings.variables);
+                derived.push(
+                    bindings
+                        .collection
+                        .map(move |values| instantiate(&head, &values)),
+                );
+            }
+
+            demands.set(&asked.concatenate(demanded).set_semantics());
+            facts
+                .set(&base.concatenate(derived).set_semantics())
+                .leave()
+        });
+
+        let query = self.query.clone();
+        closure.filter(move |triple| match_atom(&query, triple).is_some())
+    }
+
+    /// Same contract as the `full_materialization` of the experiments: computes the answers to
+    /// the query and returns their trace.
+    pub fn full_materialization<G, T>(
+        &self,
+        data_collection: &Collection<G, T>,
+        probe: &mut ProbeHandle<G::Timestamp>,
+    ) -> TraceAgent<OrdKeySpine<T, G::Timestamp, isize>>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let arrangement = self.answer(data_collection).arrange_by_self();
+        arrangement.stream.probe_with(probe);
+        arrangement.trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MagicSets;
+    use crate::datalog::{Program, RuleError, Term};
+    use crate::model::{RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE};
+    use crate::tests::{facts_after_batches, materialize_batches, TestTriple};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn answers_the_query_through_updates() {
+        let (sco, spo, typ) = (0, 1, 2);
+        let (student, person, agent, member, worker) = (10, 11, 12, 13, 14);
+        let (x, y, z, dept) = (20, 21, 22, 23);
+        let program = Program::parse(
+            "
+            T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+            T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+            T(?x, ?p, ?y) <= T(?p1, SPO, ?p), T(?x, ?p1, ?y)
+            T(?x, TYPE, <http://worker>) <= T(?x, <http://memberOf>, ?d)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            iri if iri == RDFS_SUB_CLASS_OF => Some(sco),
+            iri if iri == RDFS_SUB_PROPERTY_OF => Some(spo),
+            iri if iri == RDF_TYPE => Some(typ),
+            "<http://memberOf>" => Some(member),
+            "<http://worker>" => Some(worker),
+            _ => None,
+        })
+        .unwrap();
+
+        let batches = vec![
+            vec![
+                ((student, sco, person), 1),
+                ((person, sco, agent), 1),
+                ((worker, sco, person), 1),
+                ((x, typ, student), 1),
+                ((y, member, dept), 1),
+            ],
+            vec![((z, typ, agent), 1), ((student, sco, agent), 1)],
+            vec![((person, sco, agent), -1), ((y, member, dept), -1)],
+            vec![((person, sco, agent), 1), ((x, typ, student), -1)],
+        ];
+        let queries = vec![
+            [
+                Term::Variable(0),
+                Term::Constant(typ),
+                Term::Constant(agent),
+            ],
+            [Term::Constant(x), Term::Constant(typ), Term::Variable(0)],
+            [Term::Variable(0), Term::Constant(sco), Term::Variable(1)],
+            [Term::Variable(0), Term::Variable(1), Term::Variable(2)],
+        ];
+        for query in queries {
+            let magic = MagicSets::new(program.clone(), query.clone()).unwrap();
+            let answers = materialize_batches(batches.clone(), move |data| magic.answer(data));
+            for (answers, facts) in answers.into_iter().zip(facts_after_batches(&batches)) {
+                let expected: BTreeSet<TestTriple> = program
+                    .materialize_set(facts)
+                    .into_iter()
+                    .filter(|(s, p, o)| {
+                        query.iter().zip([s, p, o]).all(|(term, value)| match term {
+                            Term::Constant(c) => c == value,
+                            Term::Variable(_) => true,
+                        })
+                    })
+                    .collect();
+                assert_eq!(answers, expected);
+            }
+        }
+
+        let negated = Program::parse(
+            "T(?x, TYPE, <http://worker>) <= T(?x, TYPE, ?c), not T(?x, <http://memberOf>, ?c)",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            iri if iri == RDF_TYPE => Some(typ),
+            "<http://memberOf>" => Some(member),
+            "<http://worker>" => Some(worker),
+            _ => None,
+        })
+        .unwrap();
+        let query = [Term::Variable(0), Term::Constant(typ), Term::Variable(1)];
+        assert!(matches!(
+            MagicSets::new(negated, query),
+            Err(RuleError::NotMonotone(_))
+        ));
+    }
+}
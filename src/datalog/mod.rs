@@ -0,0 +1,209 @@
+//! Datalog-style rule language.
+//!
+//! Rules are written over the single predicate `T`, the set of triples, as in:
+//!
+//! ```text
+//! % rdfs9
+//! T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+//! T(?x, <http://example.org/knows>, ?y) <= T(?y, <http://example.org/knows>, ?x).
+//! ```
+//!
+//! Variables start with `?`, constants are IRIs (`<...>`), N-Triples literals, numbers (read as
+//! `xsd:integer` or `xsd:decimal` literals) or one of the rho-df keywords `SCO`, `SPO`, `TYPE`,
+//! `DOMAIN`, `RANGE`. The body can contain any number of atoms and the trailing `.` is optional.
+//! `%` starts a comment.
+//!
+//! Besides atoms, the body can contain negated atoms and filters on the values of the variables:
+//!
+//! ```text
+//! T(?x, TYPE, <http://example.org/Adult>) <=
+//!     T(?x, <http://example.org/age>, ?a), ?a >= 18, not T(?x, TYPE, <http://example.org/Minor>)
+//! T(?x, TYPE, <http://example.org/Smith>) <=
+//!     T(?x, <http://example.org/name>, ?n), regex(?n, "^Smith")
+//! ```
+//!
+//! A negated atom holds when no triple matches it, its variables that the rest of the body does
+//! not bind being existential; it is compiled to an antijoin. The filters are the comparisons
+//! `=`, `!=`, `<`, `<=`, `>`, `>=` and the string predicates `contains`, `strstarts`, `strends`
+//! and `regex`. Numeric literals compare by value, string literals by lexical form and any other
+//! term is only equal to itself. The variables of a filter must be bound by a positive atom.
+//!
+//! Aggregates compare the number of distinct values bound to a variable by their atoms, or the
+//! minimum, maximum or sum of the numeric literals bound to it, with a term:
+//!
+//! ```text
+//! T(?d, TYPE, <http://example.org/LargeDepartment>) <=
+//!     T(?d, TYPE, <http://example.org/Department>),
+//!     count(?m : T(?m, <http://example.org/memberOf>, ?d)) > 50
+//! ```
+//!
+//! The bindings are grouped by the variables the atoms of the aggregate share with the body, the
+//! other ones are local to the aggregate. A group without bindings counts 0, so `= 0` or `< 1`
+//! select the bindings of the body the atoms of the aggregate don't match. A group without
+//! numeric values has no minimum, maximum or sum and fails every comparison.
+//!
+//! Negation and aggregation must be stratified: a rule can't negate or aggregate triples that
+//! depend on its own result.
+//!
+//! A parsed [`Program`] is compiled against the encoding of its constants into a
+//! [`CompiledProgram`], which turns every rule into joins over the input collection. The rules
+//! are ordered automatically: the strongly connected components of their [`DependencyGraph`]
+//! are evaluated in topological order and each recursive component gets its own `iterate` scope.
+//!
+//! Rule sets written in the Horn-rule subset of Notation3 can be loaded as well, see
+//! [`parse_n3`].
+//!
+//! The triples matching a single pattern can be computed without the rest of the closure: see
+//! [`MagicSets`], which rewrites the rules for the pattern.
+//!
+//! The constants are declared as the vocabulary of the program, so that they are encoded through
+//! the same encoder used for the data, and a program runs as a
+//! [`Reasoner`](crate::reasoners::Reasoner):
+//!
+//! ```ignore
+//! let program = Program::parse(&rules)?;
+//! run_materialization(encoder, Reasoner::from(program))
+//! ```
+
+pub(crate) mod builtins;
+
+pub(crate) mod dataflow;
+
+mod sequential;
+
+mod dred;
+pub use dred::DRed;
+pub use dred::Maintenance;
+
+mod magic;
+pub use magic::MagicSets;
+
+mod n3;
+pub use n3::parse_n3;
+
+mod parser;
+pub use parser::parse_rules;
+
+mod program;
+pub use program::CompiledAggregate;
+pub use program::CompiledBuiltin;
+pub use program::CompiledProgram;
+pub use program::CompiledRule;
+pub use program::Operand;
+pub use program::Program;
+pub use program::Term;
+
+mod stratification;
+pub use stratification::DependencyGraph;
+pub use stratification::Stratum;
+
+/// Errors raised while parsing, validating or compiling a rule set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleError {
+    /// The text is not a valid rule set
+    Parse(String),
+    /// A variable of the head does not appear in the body, so the head can't be instantiated
+    UnboundHeadVariable {
+        /// The offending rule
+        rule: String,
+        /// Name of the variable
+        variable: String,
+    },
+    /// A constant of the rule set has no encoding
+    UnresolvedConstant(String),
+    /// The rule has no positive atom to evaluate the rest of its body on
+    EmptyBody(String),
+    /// A variable of a filter does not appear in a positive atom of the body
+    UnsafeVariable {
+        /// The offending rule
+        rule: String,
+        /// Name of the variable
+        variable: String,
+    },
+    /// A filter can't be evaluated, e.g. its regular expression is invalid
+    InvalidFilter {
+        /// The offending rule
+        rule: String,
+        /// The filter
+        filter: String,
+        /// What is wrong with it
+        reason: String,
+    },
+    /// An aggregate can't be evaluated, e.g. its variable does not appear in its atoms
+    InvalidAggregate {
+        /// The offending rule
+        rule: String,
+        /// The aggregate
+        aggregate: String,
+        /// What is wrong with it
+        reason: String,
+    },
+    /// A rule negates or aggregates triples that depend on its own result
+    NotStratifiable {
+        /// The rule with the negated atom
+        rule: String,
+        /// The rule deriving the negated triples
+        negated: String,
+    },
+    /// A rule negates or aggregates triples, which Delete/Rederive can't maintain and the magic
+    /// sets can't pass demands through
+    NotMonotone(String),
+}
+
+impl std::fmt::Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleError::Parse(message) => write!(f, "Could not parse the rules: {}", message),
+            RuleError::UnboundHeadVariable { rule, variable } => write!(
+                f,
+                "Variable ?{} in the head of `{}` does not appear in its body",
+                variable, rule
+            ),
+            RuleError::UnresolvedConstant(constant) => {
+                write!(f, "Constant {} has no encoding", constant)
+            }
+            RuleError::EmptyBody(rule) => {
+                write!(f, "The body of `{}` has no positive atom", rule)
+            }
+            RuleError::UnsafeVariable { rule, variable } => write!(
+                f,
+                "Variable ?{} of `{}` is only used in filters, it must appear in a positive atom",
+                variable, rule
+            ),
+            RuleError::InvalidFilter {
+                rule,
+                filter,
+                reason,
+            } => write!(f, "Invalid filter `{}` in `{}`: {}", filter, rule, reason),
+            RuleError::InvalidAggregate {
+                rule,
+                aggregate,
+                reason,
+            } => write!(
+                f,
+                "Invalid aggregate `{}` in `{}`: {}",
+                aggregate, rule, reason
+            ),
+            RuleError::NotStratifiable { rule, negated } if rule == negated => write!(
+                f,
+                "`{}` negates or aggregates the triples it derives, the program is not \
+                 stratifiable",
+                rule
+            ),
+            RuleError::NotStratifiable { rule, negated } => write!(
+                f,
+                "`{}` negates or aggregates the triples derived by `{}`, which depends on it: \
+                 the program is not stratifiable",
+                rule, negated
+            ),
+            RuleError::NotMonotone(rule) => write!(
+                f,
+                "`{}` negates or aggregates triples, Delete/Rederive and the magic sets only \
+                 evaluate monotone rules",
+                rule
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
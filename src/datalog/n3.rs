@@ -0,0 +1,142 @@
+use crate::datalog::RuleError;
+use crate::model::{CustomLiteral, CustomRule, PossibleTerm};
+use std::collections::HashMap;
+
+lalrpop_mod!(n3, "/datalog/n3.rs");
+
+// A term as it is written, before the prefixed names are expanded
+#[derive(Debug, Clone)]
+pub enum N3Term {
+    Variable(String),
+    Iri(String),
+    Prefixed(String),
+    // The literal and the prefixed name of its datatype, if it is written `"..."^^prefix:name`
+    Literal(String, Option<String>),
+}
+
+pub type N3Triple = [N3Term; 3];
+
+// A directive or a rule of the document
+pub enum N3Statement {
+    Prefix(String, String),
+    Rule {
+        body: Vec<[N3Term; 3]>,
+        head: Vec<[N3Term; 3]>,
+    },
+}
+
+fn expand(prefixes: &HashMap<String, String>, name: &str) -> Result<String, RuleError> {
+    let (prefix, local) = name.split_at(name.find(':').expect("A prefixed name has a colon") + 1);
+    let namespace = prefixes
+        .get(prefix)
+        .ok_or_else(|| RuleError::Parse(format!("Prefix {} is not declared", prefix)))?;
+    Ok(format!("{}{}>", &namespace[..namespace.len() - 1], local))
+}
+
+fn term(prefixes: &HashMap<String, String>, term: &N3Term) -> Result<PossibleTerm, RuleError> {
+    Ok(match term {
+        N3Term::Variable(v) => PossibleTerm::LiteralVariable(v.clone()),
+        N3Term::Iri(iri) => PossibleTerm::ConstantValue(iri.clone()),
+        N3Term::Prefixed(name) => PossibleTerm::ConstantValue(expand(prefixes, name)?),
+        N3Term::Literal(literal, None) => PossibleTerm::ConstantValue(literal.clone()),
+        N3Term::Literal(literal, Some(datatype)) => {
+            PossibleTerm::ConstantValue(format!("{}^^{}", literal, expand(prefixes, datatype)?))
+        }
+    })
+}
+
+fn literal(
+    prefixes: &HashMap<String, String>,
+    triple: &[N3Term; 3],
+) -> Result<CustomLiteral, RuleError> {
+    let [s, p, o] = triple;
+    Ok(CustomLiteral {
+        tuple_of_terms: [term(prefixes, s)?, term(prefixes, p)?, term(prefixes, o)?],
+    })
+}
+
+/// Parses the Horn-rule subset of Notation3: `@prefix` (or `PREFIX`) declarations and rules
+/// `{ body } => { head } .` (or `{ head } <= { body } .`) whose formulas are sets of triples made
+/// of variables, IRIs, prefixed names and literals, with `a` for `rdf:type` and the `;` and `,`
+/// abbreviations. A rule gets one [`CustomRule`] per triple of its head.
+///
+/// ```text
+/// @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+/// { ?c rdfs:subClassOf ?d . ?x a ?c } => { ?x a ?d } .
+/// ```
+pub fn parse_n3(input: &str) -> Result<Vec<CustomRule>, RuleError> {
+    let statements = n3::DocumentParser::new()
+        .parse(input)
+        .map_err(|e| RuleError::Parse(e.to_string()))?;
+
+    // A prefix applies to the statements that follow its declaration
+    let mut prefixes = HashMap::new();
+    let mut rules = vec![];
+    for statement in statements {
+        match statement {
+            N3Statement::Prefix(prefix, namespace) => {
+                if !prefix.ends_with(':') {
+                    return Err(RuleError::Parse(format!(
+                        "{} is not a prefix, it should end with ':'",
+                        prefix
+                    )));
+                }
+                prefixes.insert(prefix, namespace);
+            }
+            N3Statement::Rule { body, head } => {
+                let body = body
+                    .iter()
+                    .map(|triple| literal(&prefixes, triple))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for triple in head.iter() {
+                    rules.push(CustomRule {
+                        head: literal(&prefixes, triple)?,
+                        body: body.clone(),
+                        negated: vec![],
+                        filters: vec![],
+                        aggregates: vec![],
+                    });
+                }
+            }
+        }
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_n3;
+    use crate::model::{PossibleTerm, RDFS_SUB_CLASS_OF, RDF_TYPE};
+
+    #[test]
+    fn parses_horn_rules_with_prefixes() {
+        let input = "
+            @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+            PREFIX : <http://example.org/>
+            # rdfs9
+            { ?c rdfs:subClassOf ?d . ?x a ?c . } => { ?x a ?d } .
+            { ?x :age \"18\"^^:years ; :knows ?y, ?z } => { ?y :knows ?x . ?z :knows ?x } .
+        ";
+        let rules = parse_n3(input).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[0].to_string(),
+            format!(
+                "T(?x, {}, ?d) <= T(?c, {}, ?d), T(?x, {}, ?c)",
+                RDF_TYPE, RDFS_SUB_CLASS_OF, RDF_TYPE
+            )
+        );
+        assert_eq!(rules[1].body.len(), 3);
+        assert_eq!(
+            rules[1].body[0].tuple_of_terms[2],
+            PossibleTerm::ConstantValue(String::from("\"18\"^^<http://example.org/years>"))
+        );
+        assert_eq!(rules[1].body, rules[2].body);
+    }
+
+    #[test]
+    fn rejects_undeclared_prefixes() {
+        assert!(parse_n3("{ ?x ex:p ?y } => { ?y ex:p ?x } .").is_err());
+        assert!(parse_n3("{ ?x <http://a> ?y } => { ?y <http://a> ?x }").is_err());
+    }
+}
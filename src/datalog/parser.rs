@@ -0,0 +1,132 @@
+use crate::datalog::RuleError;
+use crate::model::{CustomAggregate, CustomBuiltin, CustomLiteral, CustomRule};
+
+lalrpop_mod!(rules, "/datalog/rules.rs");
+
+// An element of the body of a rule, as it is read
+enum BodyItem {
+    Positive(CustomLiteral),
+    Negated(CustomLiteral),
+    Filter(CustomBuiltin),
+    Aggregate(CustomAggregate),
+}
+
+fn rule(head: CustomLiteral, items: Vec<BodyItem>) -> CustomRule {
+    let mut rule = CustomRule {
+        head,
+        body: vec![],
+        negated: vec![],
+        filters: vec![],
+        aggregates: vec![],
+    };
+    for item in items {
+        match item {
+            BodyItem::Positive(literal) => rule.body.push(literal),
+            BodyItem::Negated(literal) => rule.negated.push(literal),
+            BodyItem::Filter(filter) => rule.filters.push(filter),
+            BodyItem::Aggregate(aggregate) => rule.aggregates.push(aggregate),
+        }
+    }
+    rule
+}
+
+/// Parses a set of rules, see the module documentation for the syntax.
+pub fn parse_rules(input: &str) -> Result<Vec<CustomRule>, RuleError> {
+    rules::RulesParser::new()
+        .parse(input)
+        .map_err(|e| RuleError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_rules;
+    use crate::model::{AggregateFunction, BuiltinPredicate, PossibleTerm, RhoDFWord, XSD_INTEGER};
+
+    #[test]
+    fn parses_rho_df_rule() {
+        let rules = parse_rules("T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)").unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].body.len(), 2);
+        assert_eq!(
+            rules[0].head.tuple_of_terms[1],
+            PossibleTerm::RhoDFProperty(RhoDFWord::TYPE)
+        );
+        assert_eq!(
+            rules[0].to_string(),
+            "T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)"
+        );
+    }
+
+    #[test]
+    fn parses_many_rules_with_constants_and_comments() {
+        let input = "
+            % symmetric property
+            T(?y, <http://example.org/knows>, ?x) <= T(?x, <http://example.org/knows>, ?y).
+            T(?x, TYPE, <http://example.org/Adult>) <=
+                T(?x, <http://example.org/age>, \"18\"), T(?x, TYPE, ?c), T(?c, SCO, ?d)
+        ";
+        let rules = parse_rules(input).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].body.len(), 3);
+        assert_eq!(
+            rules[1].body[0].tuple_of_terms[2],
+            PossibleTerm::ConstantValue(String::from("\"18\""))
+        );
+    }
+
+    #[test]
+    fn parses_negation_and_filters() {
+        let rules = parse_rules(
+            "T(?x, TYPE, <http://a/Adult>) <= T(?x, <http://a/age>, ?a), ?a >= 18, \
+             not T(?x, TYPE, <http://a/Minor>), strstarts(?a, \"1\")",
+        )
+        .unwrap();
+        assert_eq!(rules[0].body.len(), 1);
+        assert_eq!(rules[0].negated.len(), 1);
+        assert_eq!(rules[0].filters.len(), 2);
+        assert_eq!(
+            rules[0].filters[0].predicate,
+            BuiltinPredicate::GreaterOrEqual
+        );
+        assert_eq!(
+            rules[0].filters[0].arguments[1],
+            PossibleTerm::ConstantValue(format!("\"18\"^^{}", XSD_INTEGER))
+        );
+        assert_eq!(
+            rules[0].to_string(),
+            format!(
+                "T(?x, TYPE, <http://a/Adult>) <= T(?x, <http://a/age>, ?a), \
+                 not T(?x, TYPE, <http://a/Minor>), ?a >= \"18\"^^{}, strstarts(?a, \"1\")",
+                XSD_INTEGER
+            )
+        );
+    }
+
+    #[test]
+    fn parses_aggregates() {
+        let rules = parse_rules(
+            "T(?d, TYPE, <http://a/LargeDepartment>) <= T(?d, TYPE, <http://a/Department>), \
+             count(?m : T(?m, <http://a/memberOf>, ?d)) > 50",
+        )
+        .unwrap();
+        let aggregate = &rules[0].aggregates[0];
+        assert_eq!(aggregate.function, AggregateFunction::Count);
+        assert_eq!(aggregate.variable, "m");
+        assert_eq!(aggregate.body.len(), 1);
+        assert_eq!(aggregate.predicate, BuiltinPredicate::Greater);
+        assert_eq!(
+            aggregate.to_string(),
+            format!(
+                "count(?m : T(?m, <http://a/memberOf>, ?d)) > \"50\"^^{}",
+                XSD_INTEGER
+            )
+        );
+        assert!(parse_rules("T(?d, TYPE, ?c) <= T(?d, TYPE, ?c), sum(?m) > 5").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(parse_rules("T(?x, TYPE, ?b) <=").is_err());
+        assert!(parse_rules("T(?x, TYPE) <= T(?x, TYPE, ?b)").is_err());
+    }
+}
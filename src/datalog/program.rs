@@ -0,0 +1,1193 @@
+use crate::datalog::dataflow::{derive, supports};
+use crate::datalog::sequential::{self, Facts};
+use crate::datalog::{parse_n3, parse_rules, DependencyGraph, RuleError, Stratum};
+use crate::encoder::Triple;
+use crate::model::{
+    AggregateFunction, BuiltinPredicate, CustomBuiltin, CustomLiteral, CustomRule, Literal,
+    PossibleTerm,
+};
+use crate::operators::{JoinStrategy, Partitions, SetSemantics};
+use crate::reasoners::explanation::Support;
+use crate::vocabulary::{Vocabulary, VocabularyDeclaration, VocabularyError};
+use differential_dataflow::collection::concatenate;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::iterate::Iterate;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{Collection, ExchangeData};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+
+/// A validated set of rules whose constants are still the IRIs written in the rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    rules: Vec<CustomRule>,
+}
+
+impl Program {
+    /// Validates the rules: every rule needs a positive atom in its body, every variable in the
+    /// head of a rule or in a filter must appear in a positive atom of its body, and no rule can
+    /// depend on the negation of its own result.
+    pub fn new(rules: Vec<CustomRule>) -> Result<Self, RuleError> {
+        for rule in &rules {
+            if rule.body.is_empty() {
+                return Err(RuleError::EmptyBody(rule.to_string()));
+            }
+            let bound = |variable: &str| {
+                rule.body
+                    .iter()
+                    .any(|literal| contains_variable(literal, variable))
+            };
+            for term in rule.head.tuple_of_terms.iter() {
+                if let PossibleTerm::LiteralVariable(variable) = term {
+                    if !bound(variable) {
+                        return Err(RuleError::UnboundHeadVariable {
+                            rule: rule.to_string(),
+                            variable: variable.clone(),
+                        });
+                    }
+                }
+            }
+            let compared = rule
+                .filters
+                .iter()
+                .flat_map(|filter| filter.arguments.iter())
+                .chain(rule.aggregates.iter().map(|aggregate| &aggregate.bound));
+            for term in compared {
+                if let PossibleTerm::LiteralVariable(variable) = term {
+                    if !bound(variable) {
+                        return Err(RuleError::UnsafeVariable {
+                            rule: rule.to_string(),
+                            variable: variable.clone(),
+                        });
+                    }
+                }
+            }
+            for filter in &rule.filters {
+                if filter.predicate == BuiltinPredicate::Regex {
+                    validate_pattern(rule, filter)?;
+                }
+            }
+            for aggregate in &rule.aggregates {
+                if !aggregate
+                    .body
+                    .iter()
+                    .any(|literal| contains_variable(literal, &aggregate.variable))
+                {
+                    return Err(RuleError::InvalidAggregate {
+                        rule: rule.to_string(),
+                        aggregate: aggregate.to_string(),
+                        reason: format!("?{} does not appear in its atoms", aggregate.variable),
+                    });
+                }
+            }
+        }
+        let program = Self { rules };
+        // The dependencies only depend on which constants are equal, so the IRIs themselves can
+        // stand for their encoding.
+        program.compile(|iri| Some(iri.to_string()))?;
+        Ok(program)
+    }
+
+    /// Parses and validates a rule set written in the rule language.
+    pub fn parse(input: &str) -> Result<Self, RuleError> {
+        Self::new(parse_rules(input)?)
+    }
+
+    /// Parses and validates a rule set written in Notation3.
+    pub fn parse_n3(input: &str) -> Result<Self, RuleError> {
+        Self::new(parse_n3(input)?)
+    }
+
+    /// Reads the rule set in `path`: Notation3 if the extension is `n3`, the rule language
+    /// otherwise.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, RuleError> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .map_err(|e| RuleError::Parse(format!("Could not read {}: {}", path.display(), e)))?;
+        if path.extension().is_some_and(|extension| extension == "n3") {
+            Self::parse_n3(&input)
+        } else {
+            Self::parse(&input)
+        }
+    }
+
+    /// The rules of the program
+    pub fn rules(&self) -> &[CustomRule] {
+        &self.rules
+    }
+
+    /// Every constant used by the rules, rho-df keywords included, in their IRI form. These are
+    /// the values that need an encoding before the program can be compiled.
+    pub fn constants(&self) -> Vec<&str> {
+        let mut constants = BTreeSet::new();
+        for rule in &self.rules {
+            let atoms = std::iter::once(&rule.head)
+                .chain(rule.body.iter())
+                .chain(rule.negated.iter())
+                .chain(
+                    rule.aggregates
+                        .iter()
+                        .flat_map(|aggregate| aggregate.body.iter()),
+                );
+            for literal in atoms {
+                for term in literal.tuple_of_terms.iter() {
+                    if let Some(constant) = constant_of(term) {
+                        constants.insert(constant);
+                    }
+                }
+            }
+            // Literals in filters are compared by value, they don't need an encoding
+            let compared = rule
+                .filters
+                .iter()
+                .flat_map(|filter| filter.arguments.iter())
+                .chain(rule.aggregates.iter().map(|aggregate| &aggregate.bound));
+            for term in compared {
+                if let Some(constant) = constant_of(term) {
+                    if Literal::parse(constant).is_none() {
+                        constants.insert(constant);
+                    }
+                }
+            }
+        }
+        constants.into_iter().collect()
+    }
+
+    /// Translates the constants of the rules in the encoded domain through `resolve` and
+    /// plans the joins of each rule. The filters of the compiled program know no literal of the
+    /// data until [`CompiledProgram::with_literals`] is called.
+    pub fn compile<V, F>(&self, mut resolve: F) -> Result<CompiledProgram<V>, RuleError>
+    where
+        V: Eq,
+        F: FnMut(&str) -> Option<V>,
+    {
+        let mut rules = vec![];
+        for rule in &self.rules {
+            rules.push(compile_rule(rule, &mut resolve)?);
+        }
+        let graph = DependencyGraph::new(&rules);
+        if let Some((producer, consumer)) = graph.negative_cycle() {
+            return Err(RuleError::NotStratifiable {
+                rule: rules[consumer].name().to_string(),
+                negated: rules[producer].name().to_string(),
+            });
+        }
+        let strata = graph.strata();
+        Ok(CompiledProgram {
+            rules,
+            strata,
+            literals: Arc::new(HashMap::new()),
+            joins: JoinStrategy::default(),
+            properties: None,
+        })
+    }
+
+    /// The constants of the rules as a vocabulary, each one declared under its own IRI. The
+    /// values of the literals are indexed when a rule has filters or aggregates.
+    pub fn vocabulary(&self) -> VocabularyDeclaration {
+        let declaration = self
+            .constants()
+            .into_iter()
+            .fold(VocabularyDeclaration::new(), |declaration, constant| {
+                declaration.with_iri(constant, constant)
+            });
+        if self
+            .rules
+            .iter()
+            .any(|rule| !rule.filters.is_empty() || !rule.aggregates.is_empty())
+        {
+            declaration.indexing_terms()
+        } else {
+            declaration
+        }
+    }
+
+    /// Compiles the program against a vocabulary resolved from [`Program::vocabulary`].
+    pub fn resolve<V>(
+        &self,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<CompiledProgram<V>, VocabularyError>
+    where
+        V: Eq + Copy + std::hash::Hash,
+    {
+        for constant in self.constants() {
+            vocabulary.get(constant)?;
+        }
+        Ok(self
+            .compile(|iri| vocabulary.get(iri).ok())
+            .expect("Every constant of the program is in the vocabulary")
+            .with_literals(vocabulary.literals()))
+    }
+}
+
+fn validate_pattern(rule: &CustomRule, filter: &CustomBuiltin) -> Result<(), RuleError> {
+    let invalid = |reason: String| RuleError::InvalidFilter {
+        rule: rule.to_string(),
+        filter: filter.to_string(),
+        reason,
+    };
+    let pattern = match &filter.arguments[1] {
+        PossibleTerm::ConstantValue(constant) => Literal::parse(constant),
+        _ => None,
+    }
+    .ok_or_else(|| invalid(String::from("the pattern must be a literal")))?;
+    Regex::new(&pattern.lexical)
+        .map(|_| ())
+        .map_err(|e| invalid(e.to_string()))
+}
+
+fn contains_variable(literal: &CustomLiteral, variable: &str) -> bool {
+    literal
+        .tuple_of_terms
+        .iter()
+        .any(|term| matches!(term, PossibleTerm::LiteralVariable(v) if v == variable))
+}
+
+fn constant_of(term: &PossibleTerm) -> Option<&str> {
+    match term {
+        PossibleTerm::LiteralVariable(_) => None,
+        PossibleTerm::RhoDFProperty(word) => Some(word.iri()),
+        PossibleTerm::ConstantValue(constant) => Some(constant.as_str()),
+    }
+}
+
+/// A term of a compiled rule: variables are replaced by their index in the bindings of the rule
+/// and constants by their encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term<V> {
+    /// Index of the variable
+    Variable(usize),
+    /// Encoded constant
+    Constant(V),
+}
+
+/// An argument of a compiled filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand<V> {
+    /// A variable or an encoded constant
+    Term(Term<V>),
+    /// A literal written in the rule, compared by value
+    Literal(Literal),
+}
+
+/// A filter of a compiled rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledBuiltin<V> {
+    predicate: BuiltinPredicate,
+    arguments: [Operand<V>; 2],
+}
+
+impl<V> CompiledBuiltin<V> {
+    /// The predicate
+    pub fn predicate(&self) -> BuiltinPredicate {
+        self.predicate
+    }
+
+    /// The arguments of the predicate
+    pub fn arguments(&self) -> &[Operand<V>; 2] {
+        &self.arguments
+    }
+}
+
+/// An aggregate of a compiled rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledAggregate<V> {
+    function: AggregateFunction,
+    variable: usize,
+    body: Vec<[Term<V>; 3]>,
+    predicate: BuiltinPredicate,
+    bound: Operand<V>,
+}
+
+impl<V> CompiledAggregate<V> {
+    /// The aggregate function
+    pub fn function(&self) -> AggregateFunction {
+        self.function
+    }
+
+    /// Index of the aggregated variable
+    pub fn variable(&self) -> usize {
+        self.variable
+    }
+
+    /// The atoms the aggregated values come from, in the order they are joined
+    pub fn body(&self) -> &[[Term<V>; 3]] {
+        &self.body
+    }
+
+    /// The comparison applied to the aggregate
+    pub fn predicate(&self) -> BuiltinPredicate {
+        self.predicate
+    }
+
+    /// The term the aggregate is compared to
+    pub fn bound(&self) -> &Operand<V> {
+        &self.bound
+    }
+}
+
+/// A rule ready to be turned into dataflow operators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledRule<V> {
+    name: String,
+    head: [Term<V>; 3],
+    body: Vec<[Term<V>; 3]>,
+    negated: Vec<[Term<V>; 3]>,
+    filters: Vec<CompiledBuiltin<V>>,
+    aggregates: Vec<CompiledAggregate<V>>,
+}
+
+impl<V> CompiledRule<V> {
+    /// The rule as it was written, used to identify it in logs and errors
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The pattern of the derived triples
+    pub fn head(&self) -> &[Term<V>; 3] {
+        &self.head
+    }
+
+    /// The body atoms, in the order they are joined
+    pub fn body(&self) -> &[[Term<V>; 3]] {
+        &self.body
+    }
+
+    /// The negated atoms. Their variables that the body does not bind are existential.
+    pub fn negated(&self) -> &[[Term<V>; 3]] {
+        &self.negated
+    }
+
+    /// The filters, applied to the bindings of the body
+    pub fn filters(&self) -> &[CompiledBuiltin<V>] {
+        &self.filters
+    }
+
+    /// The aggregates, compared after the filters. The variables of their atoms that the body
+    /// binds are the keys they are grouped by, the others are local to the aggregate.
+    pub fn aggregates(&self) -> &[CompiledAggregate<V>] {
+        &self.aggregates
+    }
+}
+
+// Atoms are joined in order. The atom with the most constants goes first as it is the most
+// selective, then at each step we pick the atom that shares the most variables with what has been
+// joined so far, so that we never compute a cross product unless the rule asks for it.
+fn join_order(body: &[CustomLiteral]) -> Vec<&CustomLiteral> {
+    let constants = |literal: &CustomLiteral| {
+        literal
+            .tuple_of_terms
+            .iter()
+            .filter(|term| constant_of(term).is_some())
+            .count()
+    };
+    let mut remaining: Vec<&CustomLiteral> = body.iter().collect();
+    let mut ordered = vec![];
+    let mut bound: Vec<&str> = vec![];
+
+    while !remaining.is_empty() {
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, literal)| {
+                let shared = literal
+                    .tuple_of_terms
+                    .iter()
+                    .filter(|term| match term {
+                        PossibleTerm::LiteralVariable(v) => bound.contains(&v.as_str()),
+                        _ => false,
+                    })
+                    .count();
+                // Reverse on the index so that ties keep the order of the rule
+                (shared, constants(literal), std::cmp::Reverse(*i))
+            })
+            .expect("remaining is not empty");
+        let literal = remaining.remove(position);
+        for term in literal.tuple_of_terms.iter() {
+            if let PossibleTerm::LiteralVariable(v) = term {
+                if !bound.contains(&v.as_str()) {
+                    bound.push(v);
+                }
+            }
+        }
+        ordered.push(literal);
+    }
+    ordered
+}
+
+fn compile_term<'a, V, F>(
+    term: &'a PossibleTerm,
+    variables: &mut Vec<&'a str>,
+    resolve: &mut F,
+) -> Result<Term<V>, RuleError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    match term {
+        PossibleTerm::LiteralVariable(v) => {
+            let index = match variables.iter().position(|known| *known == v.as_str()) {
+                Some(index) => index,
+                None => {
+                    variables.push(v.as_str());
+                    variables.len() - 1
+                }
+            };
+            Ok(Term::Variable(index))
+        }
+        other => {
+            let constant = constant_of(other).expect("Not a variable");
+            resolve(constant)
+                .map(Term::Constant)
+                .ok_or_else(|| RuleError::UnresolvedConstant(constant.to_string()))
+        }
+    }
+}
+
+fn compile_operand<'a, V, F>(
+    term: &'a PossibleTerm,
+    variables: &mut Vec<&'a str>,
+    resolve: &mut F,
+) -> Result<Operand<V>, RuleError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    match term {
+        PossibleTerm::ConstantValue(constant) => match Literal::parse(constant) {
+            Some(literal) => Ok(Operand::Literal(literal)),
+            None => compile_term(term, variables, resolve).map(Operand::Term),
+        },
+        _ => compile_term(term, variables, resolve).map(Operand::Term),
+    }
+}
+
+fn compile_literal<'a, V, F>(
+    literal: &'a CustomLiteral,
+    variables: &mut Vec<&'a str>,
+    resolve: &mut F,
+) -> Result<[Term<V>; 3], RuleError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    let [s, p, o] = &literal.tuple_of_terms;
+    Ok([
+        compile_term(s, variables, resolve)?,
+        compile_term(p, variables, resolve)?,
+        compile_term(o, variables, resolve)?,
+    ])
+}
+
+fn compile_rule<V, F>(rule: &CustomRule, resolve: &mut F) -> Result<CompiledRule<V>, RuleError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    let mut variables = vec![];
+    let mut body = vec![];
+    for literal in join_order(&rule.body) {
+        body.push(compile_literal(literal, &mut variables, resolve)?);
+    }
+    let head = compile_literal(&rule.head, &mut variables, resolve)?;
+    let mut filters = vec![];
+    for filter in &rule.filters {
+        let [left, right] = &filter.arguments;
+        filters.push(CompiledBuiltin {
+            predicate: filter.predicate,
+            arguments: [
+                compile_operand(left, &mut variables, resolve)?,
+                compile_operand(right, &mut variables, resolve)?,
+            ],
+        });
+    }
+    let mut aggregates = vec![];
+    for aggregate in &rule.aggregates {
+        let bound = compile_operand(&aggregate.bound, &mut variables, resolve)?;
+        let mut body = vec![];
+        for literal in join_order(&aggregate.body) {
+            body.push(compile_literal(literal, &mut variables, resolve)?);
+        }
+        let variable = variables
+            .iter()
+            .position(|known| *known == aggregate.variable)
+            .expect("The aggregated variable appears in the atoms of the aggregate");
+        aggregates.push(CompiledAggregate {
+            function: aggregate.function,
+            variable,
+            body,
+            predicate: aggregate.predicate,
+            bound,
+        });
+    }
+    let mut negated = vec![];
+    for literal in &rule.negated {
+        negated.push(compile_literal(literal, &mut variables, resolve)?);
+    }
+
+    Ok(CompiledRule {
+        name: rule.to_string(),
+        head,
+        body,
+        negated,
+        filters,
+        aggregates,
+    })
+}
+
+/// A rule set whose constants are encoded, ready to be evaluated on a collection of encoded
+/// triples.
+#[derive(Debug, Clone)]
+pub struct CompiledProgram<V> {
+    rules: Vec<CompiledRule<V>>,
+    strata: Vec<Stratum>,
+    // The literals of the data, by encoding, for the filters
+    literals: Arc<HashMap<V, Literal>>,
+    joins: JoinStrategy,
+    // The properties the facts are split by, when they are
+    properties: Option<Vec<V>>,
+}
+
+impl<V> CompiledProgram<V> {
+    /// Gives the filters the value of the literals of the data, by encoding. An encoding that is
+    /// not among them is compared as an opaque term.
+    pub fn with_literals(mut self, literals: HashMap<V, Literal>) -> Self {
+        self.literals = Arc::new(literals);
+        self
+    }
+
+    /// Sets how the atoms of the bodies are joined, on the hash of the shared values by default
+    pub fn with_joins(mut self, joins: JoinStrategy) -> Self {
+        self.joins = joins;
+        self
+    }
+
+    /// Splits the facts in one collection per property of `properties`, and per pair of
+    /// property and object the atoms of the rules mention, before the rules read them: an atom
+    /// with a constant predicate then only reads the triples of its property.
+    pub fn with_partitions(mut self, properties: Vec<V>) -> Self {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// The compiled rules
+    pub fn rules(&self) -> &[CompiledRule<V>] {
+        &self.rules
+    }
+
+    /// The components of the dependency graph of the rules, in evaluation order
+    pub fn strata(&self) -> &[Stratum] {
+        &self.strata
+    }
+
+    pub(crate) fn literals(&self) -> &Arc<HashMap<V, Literal>> {
+        &self.literals
+    }
+
+    pub(crate) fn joins(&self) -> JoinStrategy {
+        self.joins
+    }
+}
+
+impl<V> CompiledProgram<V>
+where
+    V: ExchangeData + std::hash::Hash,
+{
+    /// Computes the closure of `data_collection` under the rules. The strata are evaluated in
+    /// topological order, each one on top of the result of the previous ones: a recursive
+    /// stratum is evaluated to a fixpoint in its own `iterate` scope, the others with a single
+    /// application of their rule. Negated atoms only match triples of earlier strata, which are
+    /// complete by the time they are antijoined.
+    pub fn materialize<G, T>(&self, data_collection: &Collection<G, T>) -> Collection<G, T>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let mut facts = data_collection.set_semantics();
+
+        for stratum in &self.strata {
+            let rules: Vec<&CompiledRule<V>> =
+                stratum.rules.iter().map(|&i| &self.rules[i]).collect();
+            facts = if stratum.recursive {
+                facts.iterate(|inner| {
+                    let base = facts.enter(&inner.scope());
+                    let partitions = self.partition(inner);
+                    let derived = rules
+                        .iter()
+                        .map(|rule| derive(rule, &partitions, &self.literals, self.joins));
+                    base.concatenate(derived).set_semantics()
+                })
+            } else {
+                let partitions = self.partition(&facts);
+                let derived = rules
+                    .iter()
+                    .map(|rule| derive(rule, &partitions, &self.literals, self.joins));
+                facts.concatenate(derived).set_semantics()
+            };
+        }
+        facts
+    }
+
+    /// A single application of every rule to `facts`: only the derived triples, duplicates
+    /// included. Lets a profile that also needs operators the rule language cannot express
+    /// evaluate the rules inside its own fixpoint. Negated atoms are checked against `facts` as
+    /// they are, which is only sound for rules that don't negate what the fixpoint derives.
+    pub fn derive<G, T>(&self, facts: &Collection<G, T>) -> Collection<G, T>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let partitions = self.partition(facts);
+        concatenate(
+            &mut facts.scope(),
+            self.rules
+                .iter()
+                .map(|rule| derive(rule, &partitions, &self.literals, self.joins)),
+        )
+    }
+
+    /// The supports of the triples of `materialized`, the closure computed by
+    /// [`materialize`](Self::materialize): every application of the rules to its triples. The
+    /// premises of a support are the triples matched by the positive atoms of the rule.
+    pub fn supports<G, T>(&self, materialized: &Collection<G, T>) -> Collection<G, (T, Support<T>)>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let partitions = self.partition(materialized);
+        concatenate(
+            &mut materialized.scope(),
+            self.rules
+                .iter()
+                .map(|rule| supports(rule, &partitions, &self.literals, self.joins)),
+        )
+    }
+
+    pub(crate) fn partition<G, T>(&self, facts: &Collection<G, T>) -> Partitions<G, V, T>
+    where
+        G: Scope,
+        T: ExchangeData + Triple<V>,
+    {
+        let properties = match &self.properties {
+            Some(properties) => properties,
+            None => return Partitions::unpartitioned(facts),
+        };
+        let mut pairs = vec![];
+        for rule in &self.rules {
+            let aggregated = rule.aggregates().iter().flat_map(|a| a.body().iter());
+            for atom in rule.body().iter().chain(rule.negated()).chain(aggregated) {
+                if let (Term::Constant(p), Term::Constant(o)) = (&atom[1], &atom[2]) {
+                    if !pairs.contains(&(p.clone(), o.clone())) {
+                        pairs.push((p.clone(), o.clone()));
+                    }
+                }
+            }
+        }
+        Partitions::new(facts, properties, &pairs)
+    }
+
+    /// Same contract as the `full_materialization` of the experiments: computes the
+    /// materialization and returns the trace of the result.
+    pub fn full_materialization<G, T>(
+        &self,
+        data_collection: &Collection<G, T>,
+        probe: &mut ProbeHandle<G::Timestamp>,
+    ) -> TraceAgent<OrdKeySpine<T, G::Timestamp, isize>>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + std::hash::Hash + Triple<V>,
+    {
+        let arrangement = self.materialize(data_collection).arrange_by_self();
+        arrangement.stream.probe_with(probe);
+        arrangement.trace
+    }
+}
+
+// Evaluated without dataflow, on sets held in memory
+impl<V> CompiledProgram<V>
+where
+    V: Eq + std::hash::Hash + Clone,
+{
+    /// The closure computed by [`materialize`](Self::materialize), on a set of triples held in
+    /// memory: the rules of each stratum are applied to the whole set until they derive nothing
+    /// new. Much slower than the dataflow, but it shares none of its operators, so the
+    /// materialization can be checked against it.
+    pub fn materialize_set<T>(&self, facts: HashSet<T>) -> HashSet<T>
+    where
+        T: Eq + std::hash::Hash + Triple<V>,
+    {
+        let mut facts: Facts<T, V> = facts.into_iter().collect();
+        for stratum in &self.strata {
+            loop {
+                let derived: Vec<T> = stratum
+                    .rules
+                    .iter()
+                    .flat_map(|&i| sequential::derive(&self.rules[i], &facts, &self.literals))
+                    .collect();
+                let known = facts.len();
+                for triple in derived {
+                    facts.insert(triple);
+                }
+                if !stratum.recursive || facts.len() == known {
+                    break;
+                }
+            }
+        }
+        facts.into_set()
+    }
+
+    /// A single application of every rule to a set of triples held in memory, the counterpart of
+    /// [`derive`](Self::derive) for [`materialize_set`](Self::materialize_set)
+    pub fn derive_set<T>(&self, facts: &HashSet<T>) -> Vec<T>
+    where
+        T: Eq + std::hash::Hash + Clone + Triple<V>,
+    {
+        let index: Facts<T, V> = facts.iter().cloned().collect();
+        self.rules
+            .iter()
+            .flat_map(|rule| sequential::derive(rule, &index, &self.literals))
+            .collect()
+    }
+
+    // A single application of every rule that uses at least one triple of `delta`
+    pub(crate) fn derive_from<T>(&self, facts: &Facts<T, V>, delta: &Facts<T, V>) -> Vec<T>
+    where
+        T: Eq + std::hash::Hash + Triple<V>,
+    {
+        self.rules
+            .iter()
+            .flat_map(|rule| sequential::derive_from(rule, facts, delta, &self.literals))
+            .collect()
+    }
+
+    // Whether a single application of one of the rules to `facts` derives the triple
+    pub(crate) fn derives<T>(&self, facts: &Facts<T, V>, triple: &T) -> bool
+    where
+        T: Eq + std::hash::Hash + Triple<V>,
+    {
+        self.rules
+            .iter()
+            .any(|rule| sequential::derives(rule, facts, triple, &self.literals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Program;
+    use crate::datalog::RuleError;
+    use crate::model::{
+        Literal, RDFS_DOMAIN, RDFS_RANGE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE,
+        XSD_INTEGER,
+    };
+    use crate::operators::JoinStrategy;
+    use crate::tests::{facts_after_batches, materialize_batches};
+    use std::collections::BTreeSet;
+
+    const RHO_DF: &str = "
+        T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+        T(?a, SPO, ?c) <= T(?a, SPO, ?b), T(?b, SPO, ?c)
+        T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+        T(?x, ?p, ?y) <= T(?p1, SPO, ?p), T(?x, ?p1, ?y)
+        T(?a, TYPE, ?d) <= T(?p, DOMAIN, ?d), T(?a, ?p, ?b)
+        T(?b, TYPE, ?r) <= T(?p, RANGE, ?r), T(?a, ?p, ?b)
+    ";
+
+    fn encoding(iri: &str) -> Option<u64> {
+        [
+            RDFS_SUB_CLASS_OF,
+            RDFS_SUB_PROPERTY_OF,
+            RDF_TYPE,
+            RDFS_DOMAIN,
+            RDFS_RANGE,
+        ]
+        .iter()
+        .position(|keyword| *keyword == iri)
+        .map(|i| i as u64)
+    }
+
+    #[test]
+    fn rejects_unbound_head_variables() {
+        assert!(Program::parse("T(?x, TYPE, ?c) <= T(?x, SCO, ?b)").is_err());
+    }
+
+    #[test]
+    fn reports_unresolved_constants() {
+        let program = Program::parse("T(?x, TYPE, <http://a>) <= T(?x, SCO, ?b)").unwrap();
+        assert_eq!(
+            program.constants(),
+            vec!["<http://a>", RDF_TYPE, RDFS_SUB_CLASS_OF]
+        );
+        assert!(program.compile(encoding).is_err());
+    }
+
+    #[test]
+    fn materializes_rho_df_incrementally() {
+        let (sco, spo, typ, domain) = (0, 1, 2, 3);
+        let (student, person, agent, takes, attends, course, x, y) =
+            (10, 11, 12, 13, 14, 15, 20, 21);
+        let program = Program::parse(RHO_DF).unwrap().compile(encoding).unwrap();
+
+        let batches = vec![
+            vec![
+                ((student, sco, person), 1),
+                ((person, sco, agent), 1),
+                ((takes, spo, attends), 1),
+                ((attends, domain, student), 1),
+                ((x, takes, y), 1),
+            ],
+            vec![((y, typ, course), 1)],
+            vec![((person, sco, agent), -1)],
+        ];
+        let results = materialize_batches(batches, move |data| program.materialize(data));
+
+        assert!(results[0].contains(&(student, sco, agent)));
+        assert!(results[0].contains(&(x, attends, y)));
+        assert!(results[0].contains(&(x, typ, student)));
+        assert!(results[0].contains(&(x, typ, person)));
+        assert!(results[0].contains(&(x, typ, agent)));
+        assert_eq!(results[0].len(), 10);
+        assert!(results[1].contains(&(y, typ, course)));
+        assert_eq!(results[1].len(), 11);
+        assert!(!results[2].contains(&(x, typ, agent)));
+        assert!(!results[2].contains(&(student, sco, agent)));
+        assert_eq!(results[2].len(), 8);
+    }
+
+    #[test]
+    fn skewed_joins_match_hash_joins() {
+        let (sco, typ) = (0, 2);
+        let (student, person, agent) = (10, 11, 12);
+        let program = Program::parse(RHO_DF).unwrap().compile(encoding).unwrap();
+        let skewed = program
+            .clone()
+            .with_joins(JoinStrategy::Skewed { threshold: 2 });
+
+        // The class `student` becomes heavy with the second batch and light again with the last
+        let batches = vec![
+            vec![((student, sco, person), 1), ((20, typ, student), 1)],
+            vec![((21, typ, student), 1), ((22, typ, student), 1)],
+            vec![((person, sco, agent), 1), ((21, typ, student), -1)],
+            vec![((22, typ, student), -1)],
+        ];
+        let expected = materialize_batches(batches.clone(), move |data| program.materialize(data));
+        let results = materialize_batches(batches, move |data| skewed.materialize(data));
+
+        assert!(results[2].contains(&(22, typ, agent)));
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn partitioned_facts_match_unpartitioned_ones() {
+        let (sco, spo, typ, domain) = (0, 1, 2, 3);
+        let (student, person, takes, attends, x, y) = (10, 11, 13, 14, 20, 21);
+        let program = Program::parse(
+            "
+            T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+            T(?x, ?p, ?y) <= T(?p1, SPO, ?p), T(?x, ?p1, ?y)
+            T(?x, TYPE, ?d) <= T(?p, DOMAIN, ?d), T(?x, ?p, ?y)
+            T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+            T(?x, <http://a>, ?y) <= T(?x, TYPE, <http://b>), T(?x, ?p, ?y)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| {
+            encoding(iri).or(match iri {
+                "<http://a>" => Some(100),
+                "<http://b>" => Some(person),
+                _ => None,
+            })
+        })
+        .unwrap();
+        // `attends` and `<http://a>` only appear in derived triples, TYPE has a partition of
+        // its own for the persons
+        let partitioned = program
+            .clone()
+            .with_partitions(vec![sco, spo, typ, domain, takes]);
+
+        let batches = vec![
+            vec![
+                ((student, sco, person), 1),
+                ((takes, spo, attends), 1),
+                ((attends, domain, student), 1),
+                ((x, takes, y), 1),
+            ],
+            vec![((y, typ, person), 1)],
+            vec![((student, sco, person), -1)],
+        ];
+        let expected = materialize_batches(batches.clone(), move |data| program.materialize(data));
+        let results = materialize_batches(batches, move |data| partitioned.materialize(data));
+
+        assert!(results[0].contains(&(x, 100, y)));
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn materializes_mutually_recursive_rules() {
+        let (a, b) = (100, 101);
+        let program = Program::parse(
+            "
+            T(?x, <http://b>, ?y) <= T(?x, <http://a>, ?y)
+            T(?x, <http://a>, ?z) <= T(?x, <http://b>, ?y), T(?y, <http://b>, ?z)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            "<http://a>" => Some(a),
+            "<http://b>" => Some(b),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(program.strata().len(), 1);
+
+        let batches = vec![
+            vec![((1, a, 2), 1), ((2, a, 3), 1), ((3, a, 4), 1)],
+            vec![((2, a, 3), -1)],
+        ];
+        let results = materialize_batches(batches, move |data| program.materialize(data));
+
+        assert!(results[0].contains(&(1, a, 4)));
+        assert!(results[0].contains(&(1, b, 4)));
+        assert_eq!(results[0].len(), 12);
+        assert_eq!(results[1].len(), 4);
+    }
+
+    #[test]
+    fn evaluates_negation_and_filters_incrementally() {
+        let (age, typ, adult, minor, name) = (100, 101, 102, 103, 104);
+        let (x, y, z) = (1, 2, 3);
+        let (seventeen, forty, smith) = (200, 201, 202);
+        let program = Program::parse(
+            "
+            T(?x, TYPE, <http://a/Minor>) <= T(?x, <http://a/age>, ?a), ?a < 18
+            T(?x, TYPE, <http://a/Adult>) <=
+                T(?x, <http://a/age>, ?a), not T(?x, TYPE, <http://a/Minor>)
+            T(?x, TYPE, <http://a/Smith>) <=
+                T(?x, <http://a/name>, ?n), regex(?n, \"^Smi\"), not T(?x, <http://a/age>, ?any)
+            ",
+        )
+        .unwrap();
+        assert!(program.vocabulary().indexes_terms());
+        let program = program
+            .compile(|iri| match iri {
+                "<http://a/age>" => Some(age),
+                iri if iri == RDF_TYPE => Some(typ),
+                "<http://a/Adult>" => Some(adult),
+                "<http://a/Minor>" => Some(minor),
+                "<http://a/Smith>" => Some(smith + 1),
+                "<http://a/name>" => Some(name),
+                _ => None,
+            })
+            .unwrap()
+            .with_literals(
+                [
+                    (seventeen, format!("\"17\"^^{}", XSD_INTEGER)),
+                    (
+                        forty,
+                        String::from("\"40.5\"^^<http://www.w3.org/2001/XMLSchema#double>"),
+                    ),
+                    (smith, String::from("\"Smith\"@en")),
+                ]
+                .iter()
+                .map(|(v, term)| (*v, Literal::parse(term).unwrap()))
+                .collect(),
+            );
+        assert_eq!(program.strata().len(), 3);
+        let sequential = program.clone();
+
+        let batches = vec![
+            vec![
+                ((x, age, seventeen), 1),
+                ((y, age, forty), 1),
+                ((z, name, smith), 1),
+            ],
+            vec![((x, age, seventeen), -1), ((x, age, forty), 1)],
+            vec![((z, age, forty), 1)],
+        ];
+        let facts = facts_after_batches(&batches);
+        let results = materialize_batches(batches, move |data| program.materialize(data));
+        for (facts, result) in facts.into_iter().zip(&results) {
+            assert_eq!(
+                &sequential
+                    .materialize_set(facts)
+                    .into_iter()
+                    .collect::<BTreeSet<_>>(),
+                result
+            );
+        }
+
+        assert!(results[0].contains(&(x, typ, minor)));
+        assert!(!results[0].contains(&(x, typ, adult)));
+        assert!(results[0].contains(&(y, typ, adult)));
+        assert!(results[0].contains(&(z, typ, smith + 1)));
+        assert_eq!(results[0].len(), 6);
+        assert!(results[1].contains(&(x, typ, adult)));
+        assert!(!results[1].contains(&(x, typ, minor)));
+        assert_eq!(results[1].len(), 6);
+        assert!(!results[2].contains(&(z, typ, smith + 1)));
+        assert!(results[2].contains(&(z, typ, adult)));
+        assert_eq!(results[2].len(), 7);
+    }
+
+    #[test]
+    fn rejects_unsafe_filters_and_invalid_patterns() {
+        assert!(matches!(
+            Program::parse("T(?x, TYPE, ?c) <= T(?x, TYPE, ?c), ?y > 3"),
+            Err(RuleError::UnsafeVariable { .. })
+        ));
+        assert!(matches!(
+            Program::parse("T(?x, TYPE, ?c) <= T(?x, TYPE, ?c), regex(?c, \"(\")"),
+            Err(RuleError::InvalidFilter { .. })
+        ));
+        assert!(matches!(
+            Program::parse("T(?x, TYPE, <http://a>) <= not T(?x, TYPE, <http://b>)"),
+            Err(RuleError::EmptyBody(_))
+        ));
+        assert!(matches!(
+            Program::parse("T(?x, TYPE, ?c) <= T(?x, TYPE, ?c), count(?y : T(?x, ?p, ?z)) > 1"),
+            Err(RuleError::InvalidAggregate { .. })
+        ));
+        assert!(matches!(
+            Program::parse(
+                "T(?x, <http://a>, ?c) <= T(?x, TYPE, ?c), count(?y : T(?x, ?p, ?y)) > 1"
+            ),
+            Err(RuleError::NotStratifiable { .. })
+        ));
+    }
+
+    #[test]
+    fn maintains_aggregates_incrementally() {
+        let (typ, member_of, department, large, salary, rich) = (100, 101, 102, 103, 104, 105);
+        let (d, a, b, c) = (1, 2, 3, 4);
+        let (ten, twenty) = (200, 201);
+        let program = Program::parse(
+            "
+            T(?d, TYPE, <http://a/Large>) <=
+                T(?d, TYPE, <http://a/Department>), count(?m : T(?m, <http://a/memberOf>, ?d)) > 2
+            T(?d, TYPE, <http://a/Rich>) <= T(?d, TYPE, <http://a/Department>),
+                sum(?s : T(?m, <http://a/memberOf>, ?d), T(?m, <http://a/salary>, ?s)) >= 30,
+                max(?s : T(?m, <http://a/memberOf>, ?d), T(?m, <http://a/salary>, ?s)) < 20.5
+            ",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            iri if iri == RDF_TYPE => Some(typ),
+            "<http://a/memberOf>" => Some(member_of),
+            "<http://a/Department>" => Some(department),
+            "<http://a/Large>" => Some(large),
+            "<http://a/salary>" => Some(salary),
+            "<http://a/Rich>" => Some(rich),
+            _ => None,
+        })
+        .unwrap()
+        .with_literals(
+            [
+                (ten, "\"10\"^^<http://www.w3.org/2001/XMLSchema#int>"),
+                (twenty, "\"20\""),
+            ]
+            .iter()
+            .map(|(v, term)| (*v, Literal::parse(term).unwrap()))
+            .collect(),
+        );
+
+        let batches = vec![
+            vec![
+                ((d, typ, department), 1),
+                ((a, member_of, d), 1),
+                ((b, member_of, d), 1),
+                ((a, salary, ten), 1),
+                ((b, salary, ten), 1),
+            ],
+            vec![((c, member_of, d), 1), ((c, salary, ten), 1)],
+            vec![((b, member_of, d), -1), ((c, salary, twenty), 1)],
+        ];
+        let sequential = program.clone();
+        let facts = facts_after_batches(&batches);
+        let results = materialize_batches(batches, move |data| program.materialize(data));
+        for (facts, result) in facts.into_iter().zip(&results) {
+            assert_eq!(
+                &sequential
+                    .materialize_set(facts)
+                    .into_iter()
+                    .collect::<BTreeSet<_>>(),
+                result
+            );
+        }
+
+        assert!(!results[0].contains(&(d, typ, large)));
+        assert!(!results[0].contains(&(d, typ, rich)));
+        assert!(results[1].contains(&(d, typ, large)));
+        assert!(results[1].contains(&(d, typ, rich)));
+        // A string salary is not a number: the sum falls back to 20
+        assert!(!results[2].contains(&(d, typ, large)));
+        assert!(!results[2].contains(&(d, typ, rich)));
+    }
+
+    #[test]
+    fn counts_empty_groups_and_distinct_values() {
+        let (typ, member_of, department, empty, paid, salary) = (100, 101, 102, 103, 104, 105);
+        let (d, a, b) = (1, 2, 3);
+        let (ten, twenty) = (200, 201);
+        let program = Program::parse(
+            "
+            T(?d, TYPE, <http://a/Empty>) <=
+                T(?d, TYPE, <http://a/Department>), count(?m : T(?m, <http://a/memberOf>, ?d)) = 0
+            T(?d, TYPE, <http://a/Paid>) <= T(?d, TYPE, <http://a/Department>),
+                count(?m : T(?m, <http://a/memberOf>, ?d), T(?m, <http://a/salary>, ?s)) >= 2
+            ",
+        )
+        .unwrap()
+        .compile(|iri| match iri {
+            iri if iri == RDF_TYPE => Some(typ),
+            "<http://a/memberOf>" => Some(member_of),
+            "<http://a/Department>" => Some(department),
+            "<http://a/Empty>" => Some(empty),
+            "<http://a/Paid>" => Some(paid),
+            "<http://a/salary>" => Some(salary),
+            _ => None,
+        })
+        .unwrap();
+
+        let batches = vec![
+            vec![((d, typ, department), 1)],
+            // Two salaries of the same member count once
+            vec![
+                ((a, member_of, d), 1),
+                ((a, salary, ten), 1),
+                ((a, salary, twenty), 1),
+            ],
+            vec![((b, member_of, d), 1), ((b, salary, ten), 1)],
+            vec![((a, member_of, d), -1), ((b, member_of, d), -1)],
+        ];
+        let sequential = program.clone();
+        let facts = facts_after_batches(&batches);
+        let results = materialize_batches(batches, move |data| program.materialize(data));
+        for (facts, result) in facts.into_iter().zip(&results) {
+            assert_eq!(
+                &sequential
+                    .materialize_set(facts)
+                    .into_iter()
+                    .collect::<BTreeSet<_>>(),
+                result
+            );
+        }
+
+        let types: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.contains(&(d, typ, empty)),
+                    result.contains(&(d, typ, paid)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            types,
+            vec![(true, false), (false, false), (false, true), (true, false)]
+        );
+    }
+}
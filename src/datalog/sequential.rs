@@ -0,0 +1,401 @@
+use crate::datalog::builtins::{aggregate, holds, holds_for_aggregate, Value};
+use crate::datalog::{CompiledAggregate, CompiledRule, Operand, Term};
+use crate::encoder::Triple;
+use crate::model::{AggregateFunction, BuiltinPredicate, Literal};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+// The values of the variables of a rule, by index, `None` while unbound
+type Binding<V> = Vec<Option<V>>;
+
+/// The triples of a set held in memory, indexed by predicate
+pub(crate) struct Facts<T, V> {
+    by_predicate: HashMap<V, HashSet<T>>,
+    len: usize,
+}
+
+impl<T, V> Facts<T, V>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            by_predicate: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Adds the triple, returns whether it was not there yet
+    pub(crate) fn insert(&mut self, triple: T) -> bool {
+        let inserted = self
+            .by_predicate
+            .entry((*triple.p()).clone())
+            .or_default()
+            .insert(triple);
+        self.len += inserted as usize;
+        inserted
+    }
+
+    /// Removes the triple, returns whether it was there
+    pub(crate) fn remove(&mut self, triple: &T) -> bool {
+        let removed = self
+            .by_predicate
+            .get_mut(&*triple.p())
+            .is_some_and(|triples| triples.remove(triple));
+        self.len -= removed as usize;
+        removed
+    }
+
+    pub(crate) fn contains(&self, triple: &T) -> bool {
+        self.by_predicate
+            .get(&*triple.p())
+            .is_some_and(|triples| triples.contains(triple))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.by_predicate.values().flatten()
+    }
+
+    pub(crate) fn into_set(self) -> HashSet<T> {
+        self.by_predicate.into_values().flatten().collect()
+    }
+
+    // The triples that can match the atom once the binding is applied to it
+    fn candidates<'a>(
+        &'a self,
+        atom: &[Term<V>; 3],
+        binding: &Binding<V>,
+    ) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        let predicate = match &atom[1] {
+            Term::Constant(p) => Some(p),
+            Term::Variable(v) => binding[*v].as_ref(),
+        };
+        match predicate {
+            Some(p) => Box::new(self.by_predicate.get(p).into_iter().flatten()),
+            None => Box::new(self.iter()),
+        }
+    }
+}
+
+impl<T, V> FromIterator<T> for Facts<T, V>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(triples: I) -> Self {
+        let mut facts = Self::new();
+        for triple in triples {
+            facts.insert(triple);
+        }
+        facts
+    }
+}
+
+// Extends the binding with the values of a triple that matches the atom
+fn match_atom<T, V>(atom: &[Term<V>; 3], triple: &T, binding: &Binding<V>) -> Option<Binding<V>>
+where
+    V: Eq + Clone,
+    T: Triple<V>,
+{
+    let mut binding = binding.clone();
+    let values = [triple.s(), triple.p(), triple.o()];
+    for (term, value) in atom.iter().zip(values.iter().map(|value| &**value)) {
+        match term {
+            Term::Constant(c) => {
+                if c != value {
+                    return None;
+                }
+            }
+            Term::Variable(v) => match &binding[*v] {
+                Some(bound) => {
+                    if bound != value {
+                        return None;
+                    }
+                }
+                None => binding[*v] = Some(value.clone()),
+            },
+        }
+    }
+    Some(binding)
+}
+
+// The extensions of the binding that satisfy every atom, joined in order
+fn atoms_bindings<T, V>(
+    atoms: &[[Term<V>; 3]],
+    facts: &Facts<T, V>,
+    binding: Binding<V>,
+) -> Vec<Binding<V>>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let mut bindings = vec![binding];
+    for atom in atoms {
+        bindings = bindings
+            .iter()
+            .flat_map(|binding| {
+                facts
+                    .candidates(atom, binding)
+                    .filter_map(move |triple| match_atom(atom, triple, binding))
+            })
+            .collect();
+    }
+    bindings
+}
+
+fn variables<V>(atoms: &[[Term<V>; 3]]) -> Vec<usize> {
+    let mut variables = vec![];
+    for term in atoms.iter().flatten() {
+        if let Term::Variable(v) = term {
+            if !variables.contains(v) {
+                variables.push(*v);
+            }
+        }
+    }
+    variables
+}
+
+// One more than the largest index of a variable of the rule
+fn variable_count<V>(rule: &CompiledRule<V>) -> usize {
+    let operand = |operand: &Operand<V>| match operand {
+        Operand::Term(Term::Variable(v)) => *v + 1,
+        _ => 0,
+    };
+    let atoms = std::iter::once(rule.head())
+        .chain(rule.body())
+        .chain(rule.negated())
+        .chain(rule.aggregates().iter().flat_map(|a| a.body()));
+    let in_atoms = atoms.flatten().map(|term| match term {
+        Term::Variable(v) => *v + 1,
+        Term::Constant(_) => 0,
+    });
+    let in_filters = rule
+        .filters()
+        .iter()
+        .flat_map(|filter| filter.arguments().iter().map(operand));
+    let in_aggregates = rule
+        .aggregates()
+        .iter()
+        .flat_map(|a| [a.variable() + 1, operand(a.bound())]);
+    in_atoms
+        .chain(in_filters)
+        .chain(in_aggregates)
+        .max()
+        .unwrap_or(0)
+}
+
+fn value<'a, V>(
+    operand: &'a Operand<V>,
+    binding: &'a Binding<V>,
+    literals: &'a HashMap<V, Literal>,
+) -> Value<'a, V>
+where
+    V: Eq + Hash,
+{
+    let term = match operand {
+        Operand::Literal(literal) => return Value::Literal(literal),
+        Operand::Term(Term::Variable(i)) => binding[*i]
+            .as_ref()
+            .expect("Compared variables are bound by the body"),
+        Operand::Term(Term::Constant(c)) => c,
+    };
+    literals.get(term).map_or(Value::Term(term), Value::Literal)
+}
+
+// Whether the group of the binding satisfies the aggregate. The atoms of the aggregate are
+// matched from the binding of the body, which fixes the variables they share with it.
+fn aggregate_holds<T, V>(
+    aggregate_of: &CompiledAggregate<V>,
+    binding: &Binding<V>,
+    facts: &Facts<T, V>,
+    literals: &HashMap<V, Literal>,
+) -> bool
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let local = variables(aggregate_of.body());
+    let aggregated = local
+        .iter()
+        .position(|v| *v == aggregate_of.variable())
+        .expect("The aggregated variable appears in the atoms of the aggregate");
+    // Each binding of the atoms counts once, whatever the number of derivations of its triples
+    let inner: HashSet<Vec<V>> = atoms_bindings(aggregate_of.body(), facts, binding.clone())
+        .into_iter()
+        .map(|inner| {
+            local
+                .iter()
+                .map(|v| inner[*v].clone().expect("The atoms bind their variables"))
+                .collect()
+        })
+        .collect();
+
+    let function = aggregate_of.function();
+    let total = match function {
+        // A count is the number of distinct values of the variable
+        AggregateFunction::Count => {
+            let values: HashSet<&V> = inner.iter().map(|values| &values[aggregated]).collect();
+            aggregate(function, values.iter().map(|_| (0.0, 1)))
+        }
+        _ => aggregate(
+            function,
+            inner.iter().filter_map(|values| {
+                literals
+                    .get(&values[aggregated])
+                    .and_then(Literal::as_number)
+                    .map(|n| (n, 1))
+            }),
+        ),
+    };
+    total.is_some_and(|total| {
+        holds_for_aggregate(
+            aggregate_of.predicate(),
+            total,
+            value(aggregate_of.bound(), binding, literals),
+        )
+    })
+}
+
+// The compiled regular expressions of the filters of the rule, by filter
+fn patterns<V>(rule: &CompiledRule<V>) -> Vec<Option<Regex>> {
+    rule.filters()
+        .iter()
+        .map(
+            |filter| match (filter.predicate(), &filter.arguments()[1]) {
+                (BuiltinPredicate::Regex, Operand::Literal(pattern)) => Some(
+                    Regex::new(&pattern.lexical).expect("Patterns are validated with the rules"),
+                ),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+// Whether a binding of the positive atoms satisfies the filters, the aggregates and the negated
+// atoms of the rule
+fn satisfies<T, V>(
+    rule: &CompiledRule<V>,
+    patterns: &[Option<Regex>],
+    binding: &Binding<V>,
+    facts: &Facts<T, V>,
+    literals: &HashMap<V, Literal>,
+) -> bool
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let filters = rule
+        .filters()
+        .iter()
+        .zip(patterns)
+        .all(|(filter, pattern)| {
+            let [left, right] = filter.arguments();
+            holds(
+                filter.predicate(),
+                value(left, binding, literals),
+                value(right, binding, literals),
+                pattern.as_ref(),
+            )
+        });
+    filters
+        && rule
+            .aggregates()
+            .iter()
+            .all(|aggregate_of| aggregate_holds(aggregate_of, binding, facts, literals))
+        && rule.negated().iter().all(|atom| {
+            facts
+                .candidates(atom, binding)
+                .all(|triple| match_atom(atom, triple, binding).is_none())
+        })
+}
+
+fn instantiate<T, V>(rule: &CompiledRule<V>, binding: &Binding<V>) -> T
+where
+    V: Clone,
+    T: Triple<V>,
+{
+    let term = |term: &Term<V>| match term {
+        Term::Variable(v) => binding[*v]
+            .clone()
+            .expect("Head variables are bound by the body"),
+        Term::Constant(c) => c.clone(),
+    };
+    let [s, p, o] = rule.head();
+    T::from_spo(term(s), term(p), term(o))
+}
+
+/// Triples derived by one application of the rule to `facts`
+pub(crate) fn derive<T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Facts<T, V>,
+    literals: &HashMap<V, Literal>,
+) -> Vec<T>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let patterns = patterns(rule);
+    atoms_bindings(rule.body(), facts, vec![None; variable_count(rule)])
+        .into_iter()
+        .filter(|binding| satisfies(rule, &patterns, binding, facts, literals))
+        .map(|binding| instantiate(rule, &binding))
+        .collect()
+}
+
+/// Triples derived by one application of the rule to `facts` where at least one of the positive
+/// atoms matches a triple of `delta`, the semi-naive step of a fixpoint. `delta` is expected to
+/// be part of `facts`.
+pub(crate) fn derive_from<T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Facts<T, V>,
+    delta: &Facts<T, V>,
+    literals: &HashMap<V, Literal>,
+) -> Vec<T>
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let patterns = patterns(rule);
+    let unbound = vec![None; variable_count(rule)];
+    let body = rule.body();
+    (0..body.len())
+        .flat_map(|i| {
+            let rest: Vec<[Term<V>; 3]> = body[..i].iter().chain(&body[i + 1..]).cloned().collect();
+            delta
+                .candidates(&body[i], &unbound)
+                .filter_map(|triple| match_atom(&body[i], triple, &unbound))
+                .flat_map(|binding| atoms_bindings(&rest, facts, binding))
+                .collect::<Vec<_>>()
+        })
+        .filter(|binding| satisfies(rule, &patterns, binding, facts, literals))
+        .map(|binding| instantiate(rule, &binding))
+        .collect()
+}
+
+/// Whether one application of the rule to `facts` derives `triple`
+pub(crate) fn derives<T, V>(
+    rule: &CompiledRule<V>,
+    facts: &Facts<T, V>,
+    triple: &T,
+    literals: &HashMap<V, Literal>,
+) -> bool
+where
+    V: Eq + Hash + Clone,
+    T: Eq + Hash + Triple<V>,
+{
+    let unbound = vec![None; variable_count(rule)];
+    let binding = match match_atom(rule.head(), triple, &unbound) {
+        Some(binding) => binding,
+        None => return false,
+    };
+    let patterns = patterns(rule);
+    atoms_bindings(rule.body(), facts, binding)
+        .iter()
+        .any(|binding| satisfies(rule, &patterns, binding, facts, literals))
+}
@@ -0,0 +1,270 @@
+use crate::datalog::{CompiledRule, Term};
+
+/// A strongly connected component of the dependency graph of a rule set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stratum {
+    /// Indexes of the rules of the component, in the order of the rule set
+    pub rules: Vec<usize>,
+    /// Whether the rules of the component depend on themselves, in which case they must be
+    /// evaluated to a fixpoint in an `iterate` scope
+    pub recursive: bool,
+}
+
+/// Dependency graph of a rule set: there is an edge from rule `i` to rule `j` if a triple
+/// derived by `i` can match one of the atoms in the body of `j`. The edge is negative if the
+/// atom is negated or aggregated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyGraph {
+    edges: Vec<Vec<usize>>,
+    negative: Vec<Vec<usize>>,
+}
+
+// Two patterns can match the same triple unless they have different constants in the same
+// position. This over-approximates the dependencies when variables are repeated, which is safe:
+// at worst two rules end up in the same component.
+fn unifiable<V: Eq>(head: &[Term<V>; 3], atom: &[Term<V>; 3]) -> bool {
+    head.iter().zip(atom.iter()).all(|terms| match terms {
+        (Term::Constant(a), Term::Constant(b)) => a == b,
+        _ => true,
+    })
+}
+
+impl DependencyGraph {
+    /// Builds the graph of the rules
+    pub fn new<V: Eq>(rules: &[CompiledRule<V>]) -> Self {
+        let consumers = |atoms: fn(&CompiledRule<V>) -> Vec<&[Term<V>; 3]>| -> Vec<Vec<usize>> {
+            rules
+                .iter()
+                .map(|producer| {
+                    rules
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, consumer)| {
+                            atoms(consumer)
+                                .iter()
+                                .any(|atom| unifiable(producer.head(), atom))
+                        })
+                        .map(|(j, _)| j)
+                        .collect()
+                })
+                .collect()
+        };
+        let negative = consumers(|rule| {
+            let aggregated = rule.aggregates().iter().flat_map(|a| a.body().iter());
+            rule.negated().iter().chain(aggregated).collect()
+        });
+        let mut edges = consumers(|rule| rule.body().iter().collect());
+        for (producer, consumers) in negative.iter().enumerate() {
+            for &consumer in consumers {
+                if !edges[producer].contains(&consumer) {
+                    edges[producer].push(consumer);
+                }
+            }
+        }
+        Self { edges, negative }
+    }
+
+    /// Rules that consume the triples derived by `rule`
+    pub fn dependents(&self, rule: usize) -> &[usize] {
+        &self.edges[rule]
+    }
+
+    /// Rules that negate or aggregate the triples derived by `rule`
+    pub fn negative_dependents(&self, rule: usize) -> &[usize] {
+        &self.negative[rule]
+    }
+
+    /// A negative edge between two rules of the same component, as (producer, consumer), if
+    /// there is one. The negation can only be evaluated once the negated triples are all
+    /// derived, so a rule set with such an edge is not stratifiable.
+    pub fn negative_cycle(&self) -> Option<(usize, usize)> {
+        let mut component = vec![0; self.edges.len()];
+        for (i, stratum) in self.strata().iter().enumerate() {
+            for &rule in &stratum.rules {
+                component[rule] = i;
+            }
+        }
+        self.negative
+            .iter()
+            .enumerate()
+            .flat_map(|(producer, consumers)| consumers.iter().map(move |&c| (producer, c)))
+            .find(|&(producer, consumer)| component[producer] == component[consumer])
+    }
+
+    /// The strongly connected components of the graph in topological order: every rule comes
+    /// after the rules it depends on, unless they are in the same component.
+    pub fn strata(&self) -> Vec<Stratum> {
+        let mut tarjan = Tarjan {
+            edges: &self.edges,
+            index: vec![None; self.edges.len()],
+            low_link: vec![0; self.edges.len()],
+            on_stack: vec![false; self.edges.len()],
+            stack: vec![],
+            next_index: 0,
+            components: vec![],
+        };
+        for rule in 0..self.edges.len() {
+            if tarjan.index[rule].is_none() {
+                tarjan.connect(rule);
+            }
+        }
+
+        // Tarjan emits a component only after every component reachable from it, so the
+        // consumers come first.
+        tarjan
+            .components
+            .into_iter()
+            .rev()
+            .map(|mut rules| {
+                rules.sort_unstable();
+                let recursive = rules.len() > 1 || self.edges[rules[0]].contains(&rules[0]);
+                Stratum { rules, recursive }
+            })
+            .collect()
+    }
+}
+
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low_link: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    // Rule sets are small, the recursion depth is bounded by the number of rules.
+    fn connect(&mut self, node: usize) {
+        self.index[node] = Some(self.next_index);
+        self.low_link[node] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &next in self.edges[node].iter() {
+            match self.index[next] {
+                None => {
+                    self.connect(next);
+                    self.low_link[node] = self.low_link[node].min(self.low_link[next]);
+                }
+                Some(index) if self.on_stack[next] => {
+                    self.low_link[node] = self.low_link[node].min(index);
+                }
+                _ => {}
+            }
+        }
+
+        if Some(self.low_link[node]) == self.index[node] {
+            let mut component = vec![];
+            loop {
+                let member = self.stack.pop().expect("The node is on the stack");
+                self.on_stack[member] = false;
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependencyGraph, Stratum};
+    use crate::datalog::{Program, RuleError};
+    use crate::model::{
+        RDFS_DOMAIN, RDFS_RANGE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE,
+    };
+
+    fn encoding(iri: &str) -> Option<u64> {
+        [
+            RDFS_SUB_CLASS_OF,
+            RDFS_SUB_PROPERTY_OF,
+            RDF_TYPE,
+            RDFS_DOMAIN,
+            RDFS_RANGE,
+        ]
+        .iter()
+        .position(|keyword| *keyword == iri)
+        .map(|i| i as u64)
+    }
+
+    #[test]
+    fn orders_schema_rules_first() {
+        let program = Program::parse(
+            "
+            T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+            T(?a, TYPE, ?d) <= T(?p, DOMAIN, ?d), T(?a, ?p, ?b)
+            T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+            ",
+        )
+        .unwrap()
+        .compile(encoding)
+        .unwrap();
+        let strata = DependencyGraph::new(program.rules()).strata();
+
+        // The domain rule matches the triples derived by the type rule and vice versa.
+        assert_eq!(
+            strata,
+            vec![
+                Stratum {
+                    rules: vec![2],
+                    recursive: true
+                },
+                Stratum {
+                    rules: vec![0, 1],
+                    recursive: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_mutually_recursive_rules() {
+        let program = Program::parse(
+            "
+            T(?x, <http://b>, ?y) <= T(?x, <http://a>, ?y)
+            T(?x, <http://a>, ?z) <= T(?x, <http://b>, ?y), T(?y, <http://b>, ?z)
+            T(?x, TYPE, ?y) <= T(?x, <http://a>, ?y)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| Some(iri.to_string()))
+        .unwrap();
+        let strata = DependencyGraph::new(program.rules()).strata();
+
+        assert_eq!(strata.len(), 2);
+        assert_eq!(strata[0].rules, vec![0, 1]);
+        assert!(strata[0].recursive);
+        assert_eq!(strata[1].rules, vec![2]);
+    }
+
+    #[test]
+    fn rejects_negation_through_recursion() {
+        let error = Program::parse(
+            "
+            T(?x, <http://b>, ?y) <= T(?x, <http://a>, ?y), not T(?x, <http://c>, ?y)
+            T(?x, <http://c>, ?y) <= T(?x, <http://b>, ?y)
+            ",
+        )
+        .unwrap_err();
+        assert!(matches!(error, RuleError::NotStratifiable { .. }));
+        assert!(error.to_string().contains("not stratifiable"));
+
+        let program = Program::parse(
+            "
+            T(?x, <http://c>, ?y) <= T(?x, <http://a>, ?y), not T(?x, <http://b>, ?y)
+            T(?x, <http://b>, ?z) <= T(?x, <http://b>, ?y), T(?y, <http://b>, ?z)
+            ",
+        )
+        .unwrap()
+        .compile(|iri| Some(iri.to_string()))
+        .unwrap();
+        let graph = DependencyGraph::new(program.rules());
+        assert_eq!(graph.negative_dependents(1), &[0]);
+        assert_eq!(program.strata()[0].rules, vec![1]);
+    }
+}
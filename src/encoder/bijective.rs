@@ -19,6 +19,10 @@ where
     // bimap standard to return the attempted pair.
     fn insert(&mut self, left: K, right: V) -> Result<(), (K, V)>;
 
+    // Every pair in the map, in no particular order. Needed by the rules that depend on the
+    // shape of the terms (e.g. the datatype of a literal) rather than on the triples.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_>;
+
     // [IMPROVMENT]:
     // So this function returns a Vec<(&K, &K, &K)> should this be generic? Should this return
     // something else?
@@ -29,7 +33,7 @@ where
     {
         let mut translated = vec![];
         for triple in encoded_dataset {
-            let opt_s = self.get_left(triple.s());
+            let opt_s = self.get_left(&triple.s());
             let s_back = if let Some(s) = opt_s {
                 s
             } else {
@@ -41,7 +45,7 @@ where
                     panic!("VALUE NOT PRESENT IN THE MAPS");
                 }
             };
-            let opt_p = self.get_left(triple.p());
+            let opt_p = self.get_left(&triple.p());
             let p_back = if let Some(p) = opt_p {
                 p
             } else {
@@ -52,7 +56,7 @@ where
                 }
             };
 
-            let opt_o = self.get_left(triple.o());
+            let opt_o = self.get_left(&triple.o());
             let o_back = if let Some(o) = opt_o {
                 o
             } else {
@@ -73,7 +77,7 @@ where
 
 use bimap::BiMap;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct BijectiveMap<K, V>
 where
     V: std::cmp::Eq + std::hash::Hash,
@@ -106,4 +110,7 @@ where
     fn insert(&mut self, left: K, right: V) -> Result<(), (K, V)> {
         self.bimap.insert_no_overwrite(left, right)
     }
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
+        Box::new(self.bimap.iter())
+    }
 }
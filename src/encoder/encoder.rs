@@ -1,5 +1,6 @@
 use crate::encoder::{BiMapTrait, BijectiveMap, EncodingLogic, ParserTrait, Triple};
 use log::info;
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
@@ -8,8 +9,10 @@ use std::marker::PhantomData;
 use std::path::Path;
 
 type EncodedTriple<T> = (T, T, T);
+// The map and the datasets loaded together, or the term and the encoding that could not be used
+type Loaded<M, D, K, V> = Result<(M, Vec<D>), (K, V)>;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct EncoderUnit<L, R, E, P, F>
 where
     R: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Send + Sync,
@@ -23,6 +26,10 @@ where
     parser: P,
     encoding_logic: F,
     bijective_map: Option<E::MapStructure>,
+    // The encodings found in predicate position by `encode_persistent`
+    properties: HashSet<R>,
+    // The source ids the files encoded with `encode_persistent` were tagged with
+    sources: BTreeSet<String>,
 }
 
 impl<L, R, E, P, F> EncoderUnit<L, R, E, P, F>
@@ -41,15 +48,19 @@ where
             parser,
             encoding_logic,
             bijective_map: None,
+            properties: HashSet::new(),
+            sources: BTreeSet::new(),
         }
     }
 
+    // Returns the term and the encoding that could not be used, because the encoding is already
+    // in the map or does not fit in the triple type.
     pub fn encode<W: AsRef<Path>>(
         &mut self,
         file_path: W,
         index: Option<usize>,
         peers: Option<usize>,
-    ) -> E::EncodedDataSet {
+    ) -> Result<E::EncodedDataSet, (L, R)> {
         if let None = self.bijective_map {
             let (map, encoded_dataset) = E::load_from_file(
                 file_path,
@@ -57,11 +68,11 @@ where
                 &mut self.parser,
                 index,
                 peers,
-            );
+            )?;
             self.bijective_map = Some(map);
-            encoded_dataset
+            Ok(encoded_dataset)
         } else {
-            let encoded_dataset = E::insert_from_file(
+            E::insert_from_file(
                 file_path,
                 &mut self.encoding_logic,
                 &mut self.bijective_map.as_mut().unwrap(),
@@ -69,11 +80,6 @@ where
                 index,
                 peers,
             )
-            // [IMPROVEMENT]:
-            // Error handling here please!
-            .expect("Could not insert into map");
-
-            encoded_dataset
         }
     }
 
@@ -82,11 +88,36 @@ where
         file_path: std::path::PathBuf,
         index: Option<usize>,
         peers: Option<usize>,
-    ) -> String {
+    ) -> Result<String, (L, R)>
+    where
+        <E::EncodedDataSet as IntoIterator>::Item: Triple<R>,
+        R: Clone,
+    {
+        Ok(self
+            .encode_persistent_from(file_path, None, index, peers)?
+            .0)
+    }
+
+    // Same as `encode_persistent`, tagging the triples of the file with the id of the source
+    // they come from, by default the name of the file up to its first `.`. Returns the path of
+    // the encoded file and the source id.
+    pub fn encode_persistent_from(
+        &mut self,
+        file_path: std::path::PathBuf,
+        source: Option<&str>,
+        index: Option<usize>,
+        peers: Option<usize>,
+    ) -> Result<(String, String), (L, R)>
+    where
+        <E::EncodedDataSet as IntoIterator>::Item: Triple<R>,
+        R: Clone,
+    {
+        let source = source.map_or_else(|| Self::default_source(&file_path), String::from);
         // Encoded dataset prepared to be written.
         // [IMPORTANT]:
         // This requires to save all the dataset in memory which is meh.
-        let dataset = self.encode(file_path.clone(), index, peers);
+        let dataset = self.encode(file_path.clone(), index, peers)?;
+        self.sources.insert(source.clone());
         let output_path = Self::get_encoded_path_name(file_path);
 
         // Triples are saved as a list of items separated by newline
@@ -101,11 +132,22 @@ where
             .expect("Something wrong happened with the ouput file");
 
         for elem in dataset {
+            self.properties.insert((*elem.p()).clone());
             if let Err(e) = writeln!(full_materialization_file, "{:?}", elem) {
                 panic!("Couldn't write to file: {}", e);
             }
         }
-        output_path
+        Ok((output_path, source))
+    }
+
+    fn default_source(file_path: &Path) -> String {
+        let path_name = file_path
+            .file_name()
+            .expect("File Path name error")
+            .to_str()
+            .expect("Could not convert OsStr to str");
+        let index = path_name.find('.').unwrap_or(path_name.len());
+        path_name[0..index].to_string()
     }
 
     fn get_encoded_path_name(file_path: std::path::PathBuf) -> String {
@@ -136,6 +178,33 @@ where
         &self.bijective_map
     }
 
+    // The encodings that appeared as the predicate of a triple encoded so far with
+    // `encode_persistent`, i.e. the properties of the data
+    pub fn properties(&self) -> &HashSet<R> {
+        &self.properties
+    }
+
+    // The source ids of the files encoded so far
+    pub fn sources(&self) -> &BTreeSet<String> {
+        &self.sources
+    }
+
+    // Returns the encoding of `left`, encoding it with the encoding logic if the map does not
+    // contain it yet. This is how constants that might not appear in the data (e.g. the IRIs
+    // used by a rule set) get an encoding.
+    pub fn get_or_insert_right(&mut self, left: L) -> Result<&R, (L, R)>
+    where
+        L: Clone,
+    {
+        let map = self.bijective_map.get_or_insert_with(Default::default);
+        if map.get_right(&left).is_none() {
+            let right = self.encoding_logic.encode(left.clone());
+            map.insert(left.clone(), right)?;
+        }
+        // Safe to unwrap as the value has just been inserted
+        Ok(map.get_right(&left).unwrap())
+    }
+
     pub fn get_right_from_map(&mut self, left: L) -> &R {
         match self.bijective_map.as_mut() {
             Some(map) => map.get_right(&left).expect("Could not retrieve right"),
@@ -164,8 +233,9 @@ where
     V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
     K: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
 {
-    // Maps each string of type K to another type V
-    type MapStructure: BiMapTrait<K, V> + Send + Sync;
+    // Maps each string of type K to another type V. Default is required to create the map
+    // before any dataset is encoded, e.g. when the constants of a rule set are encoded first.
+    type MapStructure: BiMapTrait<K, V> + Send + Sync + Default;
     // Set of triples in the encoding domain: for now this is IntoIterator for compatibility with
     // Vec. But I want it an Iterator
     type EncodedDataSet: IntoIterator;
@@ -219,7 +289,7 @@ where
         // [IMPROVEMENT]:
         // How about defining a structure that has a map and a vec of Encoded data set and return
         // that.
-    ) -> (Self::MapStructure, Vec<Self::EncodedDataSet>)
+    ) -> Loaded<Self::MapStructure, Self::EncodedDataSet, K, V>
     where
         F: EncodingLogic<K, V>,
         P: ParserTrait<K>;
@@ -246,18 +316,18 @@ where
         parser: &mut P,
         index: Option<usize>,
         peers: Option<usize>,
-    ) -> (Self::MapStructure, Self::EncodedDataSet)
+    ) -> Result<(Self::MapStructure, Self::EncodedDataSet), (K, V)>
     where
         F: EncodingLogic<K, V>,
         P: ParserTrait<K>,
     {
         let parsed_triples = vec![Self::parse(file_name, parser, index, peers)];
-        let (map, mut vec) = Self::load_from_parser_output::<_, P>(parsed_triples, encoding_logic);
+        let (map, mut vec) = Self::load_from_parser_output::<_, P>(parsed_triples, encoding_logic)?;
         assert_eq!(vec.len(), 1);
         let only_vec = vec
             .pop()
             .expect("THE LOADED RETURNING VEC OF DATASET DID NOT CONTAIN ANY ENCODED DATASET");
-        (map, only_vec)
+        Ok((map, only_vec))
     }
 
     fn load_from_multiple_files_same_encoded_dataset<F, P, W: AsRef<Path>>(
@@ -266,7 +336,7 @@ where
         parser: &mut P,
         index: Option<usize>,
         peers: Option<usize>,
-    ) -> (Self::MapStructure, Self::EncodedDataSet)
+    ) -> Result<(Self::MapStructure, Self::EncodedDataSet), (K, V)>
     where
         F: EncodingLogic<K, V>,
         P: ParserTrait<K>,
@@ -276,12 +346,12 @@ where
             parsed_triples.append(&mut Self::parse(file_name, parser, index, peers));
         }
         let (map, mut vec) =
-            Self::load_from_parser_output::<_, P>(vec![parsed_triples], encoding_logic);
+            Self::load_from_parser_output::<_, P>(vec![parsed_triples], encoding_logic)?;
         assert_eq!(vec.len(), 1);
         let only_vec = vec
             .pop()
             .expect("THE LOADED RETURNING VEC OF DATASET DID NOT CONTAIN ANY ENCODED DATASET");
-        (map, only_vec)
+        Ok((map, only_vec))
     }
 
     fn load_from_multiple_files_different_encoded_dataset<F, P, W: AsRef<Path>>(
@@ -290,7 +360,7 @@ where
         parser: &mut P,
         index: Option<usize>,
         peers: Option<usize>,
-    ) -> (Self::MapStructure, Vec<Self::EncodedDataSet>)
+    ) -> Loaded<Self::MapStructure, Self::EncodedDataSet, K, V>
     where
         F: EncodingLogic<K, V>,
         P: ParserTrait<K>,
@@ -348,12 +418,24 @@ where
 use bimap::BiMap;
 use std::sync::Arc;
 
-// This specializes encoding data structure
-pub struct BiMapEncoder {}
+// This specializes encoding data structure. The encoded triples are built with `Triple::from_spo`,
+// as plain tuples by default or e.g. as `PackedTriple`s.
+pub struct BiMapEncoder<T = EncodedTriple<u64>> {
+    triple_type: PhantomData<T>,
+}
+
+// The encoding of `term`, or the error of the encoder if it does not fit in the triple type, so
+// that no triple is built with it
+fn fitting<T: Triple<u64>>(term: &Arc<String>, encoded: u64) -> Result<u64, (Arc<String>, u64)> {
+    match T::max_encoding() {
+        Some(max) if encoded > max => Err((term.clone(), encoded)),
+        _ => Ok(encoded),
+    }
+}
 
-impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
+impl<T: Triple<u64>> EncoderTrait<Arc<String>, u64> for BiMapEncoder<T> {
     type MapStructure = BijectiveMap<Arc<String>, u64>;
-    type EncodedDataSet = Vec<EncodedTriple<u64>>;
+    type EncodedDataSet = Vec<T>;
     fn load_encoded_from_persistent<W: AsRef<Path>>(
         file_path: W,
         index: Option<usize>,
@@ -396,7 +478,7 @@ impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
 
                 assert!(split_iter.next().is_none());
 
-                result.push((s, p, o));
+                result.push(T::from_spo(s, p, o));
             }
         }
         result
@@ -404,7 +486,7 @@ impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
     fn load_from_parser_output<F, P>(
         parsed_triples: Vec<Vec<P::TripleType>>,
         encoding_fn: &mut F,
-    ) -> (Self::MapStructure, Vec<Self::EncodedDataSet>)
+    ) -> Loaded<Self::MapStructure, Self::EncodedDataSet, Arc<String>, u64>
     where
         F: EncodingLogic<Arc<String>, u64>,
         P: ParserTrait<Arc<String>>,
@@ -421,9 +503,10 @@ impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
                 // would be constant with respect to the String length as the references would
                 // be compared.
                 let (s, p, o) = (triple.s(), triple.p(), triple.o());
-                let s_encoded = encoding_fn.encode(s.clone());
-                let p_encoded = encoding_fn.encode(p.clone());
-                let o_encoded = encoding_fn.encode(o.clone());
+                let (s, p, o) = (&*s, &*p, &*o);
+                let s_encoded = fitting::<T>(s, encoding_fn.encode(s.clone()))?;
+                let p_encoded = fitting::<T>(p, encoding_fn.encode(p.clone()))?;
+                let o_encoded = fitting::<T>(o, encoding_fn.encode(o.clone()))?;
 
                 let mut triple = (0, 0, 0);
 
@@ -433,25 +516,25 @@ impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
                 } else {
                     // In this case is safe to use unwrap() because if we are in the else
                     // branch that means that a value was present.
-                    triple.0 = *bimap.get_by_left(&s).unwrap();
+                    triple.0 = *bimap.get_by_left(s).unwrap();
                 }
                 if let Ok(()) = bimap.insert_no_overwrite(p.clone(), p_encoded) {
                     triple.1 = p_encoded;
                 } else {
-                    triple.1 = *bimap.get_by_left(&p).unwrap();
+                    triple.1 = *bimap.get_by_left(p).unwrap();
                 }
 
                 if let Ok(()) = bimap.insert_no_overwrite(o.clone(), o_encoded) {
                     triple.2 = o_encoded;
                 } else {
-                    triple.2 = *bimap.get_by_left(&o).unwrap();
+                    triple.2 = *bimap.get_by_left(o).unwrap();
                 }
 
-                vec.push(triple);
+                vec.push(T::from_spo(triple.0, triple.1, triple.2));
             }
             resulting_vec.push(vec);
         }
-        (BijectiveMap::new(bimap), resulting_vec)
+        Ok((BijectiveMap::new(bimap), resulting_vec))
     }
 
     fn insert_from_parser_output<F, P>(
@@ -468,40 +551,41 @@ impl EncoderTrait<Arc<String>, u64> for BiMapEncoder {
             // [WARNING]:
             // Is cloning a &Rc the same as cloning the Rc?
             let (s, p, o) = (triple.s(), triple.p(), triple.o());
+            let (s, p, o) = (&*s, &*p, &*o);
             let mut triple = (0, 0, 0);
 
-            if let Some(idx) = map.get_right(&s) {
+            if let Some(idx) = map.get_right(s) {
                 // if element present in map return its index
                 triple.0 = *idx
             } else {
-                let s_encoded = encoding_logic.encode(s.clone());
+                let s_encoded = fitting::<T>(s, encoding_logic.encode(s.clone()))?;
                 // Return an error if the string not contained in the map returns an index
                 // present in the map.
                 map.insert(s.clone(), s_encoded)?;
                 triple.0 = s_encoded;
             }
-            if let Some(idx) = map.get_right(&p) {
+            if let Some(idx) = map.get_right(p) {
                 // if element present in map return its index
                 triple.1 = *idx
             } else {
-                let p_encoded = encoding_logic.encode(p.clone());
+                let p_encoded = fitting::<T>(p, encoding_logic.encode(p.clone()))?;
                 // Return an error if the string not contained in the map returns an index
                 // present in the map.
                 map.insert(p.clone(), p_encoded)?;
                 triple.1 = p_encoded;
             }
-            if let Some(idx) = map.get_right(&o) {
+            if let Some(idx) = map.get_right(o) {
                 // if element present in map return its index
                 triple.2 = *idx
             } else {
-                let o_encoded = encoding_logic.encode(o.clone());
+                let o_encoded = fitting::<T>(o, encoding_logic.encode(o.clone()))?;
                 // Return an error if the string not contained in the map returns an index
                 // present in the map.
                 map.insert(o.clone(), o_encoded)?;
                 triple.2 = o_encoded;
             }
 
-            resulting_vec.push(triple);
+            resulting_vec.push(T::from_spo(triple.0, triple.1, triple.2));
         }
         Ok(resulting_vec)
     }
@@ -11,6 +11,10 @@ mod encoding_logic;
 pub use encoding_logic::EncodingLogic;
 pub use encoding_logic::SimpleLogic;
 
+mod packed;
+pub use packed::PackedTerm;
+pub use packed::PackedTriple;
+
 mod parser;
 pub use parser::NTriplesParser;
 pub use parser::ParserTrait;
@@ -0,0 +1,197 @@
+use crate::encoder::Triple;
+use abomonation::Abomonation;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+// An encoded triple packed in a single u128: the subject takes the highest SUBJECT bits, the
+// predicate the next PREDICATE bits and the object the remaining ones. Exchanges, arrangements
+// and thresholds then compare and hash one integer instead of three fields, and the order is
+// still the one of the (s, p, o) tuple.
+//
+// The widths are checked when the type is used: each term gets at most 64 bits and the three
+// of them fill the u128. The encodings must fit them as well: the encoder returns the first term
+// whose encoding does not, before building its triple, and `run_materialization` checks the
+// encodings given to the vocabulary through `Triple::max_encoding` before the dataflow is built.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PackedTriple<const SUBJECT: u32 = 48, const PREDICATE: u32 = 32> {
+    key: u128,
+}
+
+// A term unpacked from a `PackedTriple`, returned by its accessors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedTerm(u64);
+
+impl Deref for PackedTerm {
+    type Target = u64;
+
+    fn deref(&self) -> &u64 {
+        &self.0
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+impl<const SUBJECT: u32, const PREDICATE: u32> PackedTriple<SUBJECT, PREDICATE> {
+    const OBJECT: u32 = {
+        assert!(
+            SUBJECT >= 1 && SUBJECT <= 64,
+            "The subject takes 1 to 64 bits"
+        );
+        assert!(
+            PREDICATE >= 1 && PREDICATE <= 64,
+            "The predicate takes 1 to 64 bits"
+        );
+        assert!(
+            SUBJECT + PREDICATE >= 64 && SUBJECT + PREDICATE < 128,
+            "The object takes 1 to 64 bits"
+        );
+        128 - SUBJECT - PREDICATE
+    };
+
+    // Packs the three terms. Panics if one of them does not fit its width.
+    pub fn new(s: u64, p: u64, o: u64) -> Self {
+        for (term, bits) in [(s, SUBJECT), (p, PREDICATE), (o, Self::OBJECT)] {
+            assert!(
+                term <= mask(bits),
+                "The encoding {} does not fit in {} bits",
+                term,
+                bits
+            );
+        }
+        Self {
+            key: (s as u128) << (PREDICATE + Self::OBJECT)
+                | (p as u128) << Self::OBJECT
+                | o as u128,
+        }
+    }
+
+    fn term(&self, shift: u32, bits: u32) -> PackedTerm {
+        PackedTerm((self.key >> shift) as u64 & mask(bits))
+    }
+
+    // Checks that every encoding up to `largest` can be packed
+    pub fn validate(largest: u64) -> Result<(), String> {
+        let max = Self::max();
+        if largest > max {
+            Err(format!(
+                "The encoding {} does not fit in a triple packed with {}/{}/{} bits, the largest \
+                 is {}",
+                largest,
+                SUBJECT,
+                PREDICATE,
+                Self::OBJECT,
+                max
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn max() -> u64 {
+        mask(SUBJECT).min(mask(PREDICATE)).min(mask(Self::OBJECT))
+    }
+}
+
+impl<const SUBJECT: u32, const PREDICATE: u32> Triple<u64> for PackedTriple<SUBJECT, PREDICATE> {
+    type Term<'a> = PackedTerm;
+
+    fn s(&self) -> PackedTerm {
+        self.term(PREDICATE + Self::OBJECT, SUBJECT)
+    }
+    fn p(&self) -> PackedTerm {
+        self.term(Self::OBJECT, PREDICATE)
+    }
+    fn o(&self) -> PackedTerm {
+        self.term(0, Self::OBJECT)
+    }
+    fn from_spo(s: u64, p: u64, o: u64) -> Self {
+        Self::new(s, p, o)
+    }
+    fn max_encoding() -> Option<u64> {
+        Some(Self::max())
+    }
+}
+
+// A single write of the key: `Hashable` comes from the blanket implementation over `Hash`
+impl<const SUBJECT: u32, const PREDICATE: u32> Hash for PackedTriple<SUBJECT, PREDICATE> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u128(self.key);
+    }
+}
+
+// The key holds no pointer, the default (no-op) implementation is enough
+impl<const SUBJECT: u32, const PREDICATE: u32> Abomonation for PackedTriple<SUBJECT, PREDICATE> {}
+
+// Written like the (s, p, o) tuple, so that the persistent encodings can be read back by
+// `BiMapEncoder` whatever the triple type
+impl<const SUBJECT: u32, const PREDICATE: u32> fmt::Debug for PackedTriple<SUBJECT, PREDICATE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", *self.s(), *self.p(), *self.o())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackedTriple;
+    use crate::encoder::{BiMapEncoder, EncoderUnit, NTriplesParser, SimpleLogic, Triple};
+
+    #[test]
+    fn packs_and_orders_like_tuples() {
+        let triples = [
+            (3, 1, 2),
+            (1, 5, 7),
+            (1, 5, 6),
+            (2, 0, 0),
+            (1, 4, u32::MAX as u64),
+        ];
+        let mut packed: Vec<PackedTriple> = triples
+            .iter()
+            .map(|&(s, p, o)| PackedTriple::from_spo(s, p, o))
+            .collect();
+        packed.sort();
+        let mut sorted = triples.to_vec();
+        sorted.sort();
+
+        let unpacked: Vec<(u64, u64, u64)> = packed
+            .iter()
+            .map(|triple| (*triple.s(), *triple.p(), *triple.o()))
+            .collect();
+        assert_eq!(unpacked, sorted);
+        assert_eq!(format!("{:?}", packed[0]), "(1, 4, 4294967295)");
+    }
+
+    #[test]
+    fn validates_the_dictionary_size() {
+        type Narrow = PackedTriple<56, 16>;
+        assert_eq!(Narrow::max_encoding(), Some(u16::MAX as u64));
+        assert!(Narrow::validate(u16::MAX as u64).is_ok());
+        assert!(Narrow::validate(u16::MAX as u64 + 1).is_err());
+        assert!(std::panic::catch_unwind(|| Narrow::from_spo(0, 1 << 16, 0)).is_err());
+
+        // The encoder stops at the first encoding that does not fit, before building the triple
+        let file = std::env::temp_dir().join(format!(
+            "reasoning_service_packed_{}.nt",
+            std::process::id()
+        ));
+        std::fs::write(&file, "<a> <p> <b> .\n<b> <p> <c> .\n").unwrap();
+        let encode = |first: u64| {
+            let mut encoder: EncoderUnit<_, _, BiMapEncoder<Narrow>, _, _> =
+                EncoderUnit::new(NTriplesParser::new(), SimpleLogic::new(first));
+            let encoded = encoder.encode(&file, None, None);
+            encoded.map_err(|(term, encoded)| (term.to_string(), encoded))
+        };
+        assert_eq!(encode(0).unwrap().len(), 2);
+        assert_eq!(
+            encode(u16::MAX as u64 - 1),
+            Err((String::from("<b>"), u16::MAX as u64 + 1))
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+}
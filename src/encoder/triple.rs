@@ -1,13 +1,41 @@
+use std::ops::Deref;
+
 // Trait used by some encoder components for the triple behavior
 pub trait Triple<T> {
-    fn s(&self) -> &T;
-    fn p(&self) -> &T;
-    fn o(&self) -> &T;
+    // What the accessors return: a reference for the triples that store their terms, a value for
+    // the ones that compute them (see `PackedTriple`). Either way `*triple.s()` is the term.
+    type Term<'a>: Deref<Target = T> + Copy
+    where
+        Self: 'a,
+        T: 'a;
+
+    fn s(&self) -> Self::Term<'_>;
+    fn p(&self) -> Self::Term<'_>;
+    fn o(&self) -> Self::Term<'_>;
+
+    // Needed by the rules to build the triples they derive.
+    fn from_spo(s: T, p: T, o: T) -> Self
+    where
+        Self: Sized;
+
+    // The largest encoding the type can hold in every position, if it is limited. Checked
+    // against the dictionary before the triples are loaded.
+    fn max_encoding() -> Option<T>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 // Implementation for basic types
 
 impl<T> Triple<T> for (T, T, T) {
+    type Term<'a>
+        = &'a T
+    where
+        T: 'a;
+
     fn s(&self) -> &T {
         &self.0
     }
@@ -17,4 +45,7 @@ impl<T> Triple<T> for (T, T, T) {
     fn o(&self) -> &T {
         &self.2
     }
+    fn from_spo(s: T, p: T, o: T) -> Self {
+        (s, p, o)
+    }
 }
@@ -1,6 +1,11 @@
+use differential_dataflow::logging::DifferentialEvent;
+use std::cell::Cell;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::worker::Worker;
 
 #[derive(Debug)]
 /// Contains full materialization statistics
@@ -11,6 +16,49 @@ pub struct Statistics {
     pub mat_time: u128,
     /// full materialization time to save from trace to vec, to be joined in the main thread
     pub save_persistent_time: u128,
+    /// records held in the arrangements of the worker once the materialization is done
+    pub arranged_records: usize,
+    /// input triples dropped to keep the base facts a set: duplicate insertions and deletions of
+    /// triples that were not asserted
+    pub ignored_triples: usize,
+}
+
+/// Number of records held in the arrangements of a worker, the bulk of its memory use. It follows
+/// the batches that differential dataflow creates, merges and drops.
+#[derive(Debug, Clone, Default)]
+pub struct ArrangedRecords(Rc<Cell<isize>>);
+
+impl ArrangedRecords {
+    /// Starts counting the records of the arrangements built by `worker` from now on
+    pub fn register<A: Allocate>(worker: &mut Worker<A>) -> Self {
+        let records = Self::default();
+        let counter = records.0.clone();
+        worker.log_register().insert::<DifferentialEvent, _>(
+            "differential/arrange",
+            move |_time, events| {
+                for (_, _, event) in events.drain(..) {
+                    let change = match event {
+                        DifferentialEvent::Batch(batch) => batch.length as isize,
+                        DifferentialEvent::Merge(merge) => match merge.complete {
+                            Some(length) => {
+                                length as isize - merge.length1 as isize - merge.length2 as isize
+                            }
+                            None => 0,
+                        },
+                        DifferentialEvent::Drop(drop) => -(drop.length as isize),
+                        _ => 0,
+                    };
+                    counter.set(counter.get() + change);
+                }
+            },
+        );
+        records
+    }
+
+    /// The current number of records
+    pub fn get(&self) -> usize {
+        self.0.get().max(0) as usize
+    }
 }
 
 impl Statistics {
@@ -31,13 +79,20 @@ impl Statistics {
         // If the file is empty it means that it's the first iteration, we want
         // to append new results to previous results so to have more data to analyze
         if metadata.len() == 0 {
-            writeln!(file, "Load Time, Materialization, Save to File Time (ms)")
-                .expect("Invalid File Path");
+            writeln!(
+                file,
+                "Load Time, Materialization, Save to File Time (ms), Arranged Records, Ignored Triples"
+            )
+            .expect("Invalid File Path");
         }
 
         let string = format!(
-            "{}, {}, {}",
-            self.load_time, self.mat_time, self.save_persistent_time
+            "{}, {}, {}, {}, {}",
+            self.load_time,
+            self.mat_time,
+            self.save_persistent_time,
+            self.arranged_records,
+            self.ignored_triples
         );
         writeln!(file, "{}", string).expect("Could not write statistics to file");
     }
@@ -266,6 +321,86 @@ pub fn output_figures(output_path: PathBuf) {
         save_to_file_plot_info,
     )
 }
+/// Plots the load, materialization and save-to-file times of several engines, named with the
+/// `stats` folder they wrote their statistics to, on the same axes. An engine that only ran with
+/// one number of workers, e.g. one that runs on a single thread, is drawn as a horizontal line
+/// over the numbers of workers of the others.
+pub fn output_engine_figures(engines: &[(&str, PathBuf)], figures: PathBuf) {
+    let times: Vec<Vec<(f64, [f64; 3])>> = engines
+        .iter()
+        .map(|(_, stats)| times_per_peers(stats))
+        .collect();
+    let workers = times.iter().flatten().map(|(peers, _)| *peers);
+    let (first, last) = match (workers.clone().reduce(f64::min), workers.reduce(f64::max)) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return,
+    };
+
+    if !figures.is_dir() {
+        std::fs::create_dir_all(figures.clone()).unwrap();
+    }
+    let columns = [
+        ("engines_load_time.svg", "Load Time (ms)"),
+        ("engines_mat_time.svg", "Materialization Time (ms)"),
+        ("engines_save_time.svg", "Save to File Time (ms)"),
+    ];
+    for (column, (file, y_label)) in columns.iter().enumerate() {
+        let mut plotter = Plotter::new();
+        let mut plots = vec![];
+        let (mut min, mut max) = (f64::MAX, f64::MIN);
+        for ((name, _), times) in engines.iter().zip(&times) {
+            let mut data: Vec<(f64, f64)> = times
+                .iter()
+                .map(|(peers, times)| (*peers, times[column]))
+                .collect();
+            if let [(_, time)] = data[..] {
+                data = vec![(first, time), (last, time)];
+            }
+            for (_, time) in &data {
+                min = min.min(*time);
+                max = max.max(*time);
+            }
+            if !data.is_empty() {
+                plots.push(plotter.generate_plot(data).legend(name.to_string()));
+            }
+        }
+        let plot_info = PlotInfo {
+            x_range: None,
+            y_range: Some(compute_axis_range(min, max, 0.4)),
+            x_label: "Number of Workers",
+            y_label,
+        };
+        plotter.save_plot(figures.join(file), plots, plot_info);
+    }
+}
+
+// The load, materialization and save-to-file times of the slowest worker for each number of
+// workers that has statistics in the folder, by number of workers
+fn times_per_peers(stats_path: &Path) -> Vec<(f64, [f64; 3])> {
+    let mut times = vec![];
+    if !stats_path.is_dir() {
+        return times;
+    }
+    for entry in WalkDir::new(stats_path).min_depth(1).max_depth(1) {
+        let entry = entry.expect("Failed to read file in stats path");
+        if entry.path().is_dir() && is_peers_folder(entry.path()) {
+            let mut slowest = [0f64; 3];
+            for file in WalkDir::new(entry.path()).min_depth(1) {
+                let file = file.expect("Could not traverse peers folder");
+                let (load, mat, save_to_file) = get_averaged_data(file.path());
+                slowest = [
+                    slowest[0].max(load),
+                    slowest[1].max(mat),
+                    slowest[2].max(save_to_file),
+                ];
+            }
+            times.push((peers_from_file(&entry) as f64, slowest));
+        }
+    }
+    times.sort_by(|(a, _), (b, _)| a.partial_cmp(b).expect("Tried to compare with NaN"));
+    times
+}
+
 fn is_peers_folder(entry: &Path) -> bool {
     let filename = entry.file_name().unwrap().to_str().unwrap();
     let peers = &filename[0..5];
@@ -364,6 +499,9 @@ fn get_averaged_data<W: AsRef<Path>>(path: W) -> (f64, f64, f64) {
             .parse::<u64>()
             .expect("Data format not valid");
 
+        // The arranged records and the ignored triples, when the file has them, are not plotted
+        let _arranged_records = iter.next();
+        let _ignored_triples = iter.next();
         assert!(iter.next().is_none());
     }
     (
@@ -2,23 +2,35 @@
 #[macro_use]
 extern crate lalrpop_util;
 
+use crate::datalog::{DRed, MagicSets, Term};
 use crate::encoder::BiMapTrait;
-use crate::eval::Statistics;
-use crate::model::{RDFS_DOMAIN, RDFS_RANGE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE};
+use crate::eval::{ArrangedRecords, Statistics};
+use crate::lookup::TripleLookup;
+use crate::reasoners::explanation::{explain, Support};
+use crate::reasoners::same_as::Aliases;
+use crate::reasoners::Reasoner;
+use crate::sparql::{CompiledQuery, Query};
+use crate::standing::{Deltas, StandingQuery};
+use crate::vocabulary::{SameAs, Vocabulary, VocabularyError};
+use differential_dataflow::hashable::Hashable;
 use differential_dataflow::input::{Input, InputSession};
-use differential_dataflow::operators::arrange::TraceAgent;
-use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::operators::arrange::{Arrange, ArrangeBySelf, TraceAgent};
+use differential_dataflow::operators::Threshold;
+use differential_dataflow::trace::implementations::ord::{OrdKeySpine, OrdValSpine};
 use differential_dataflow::trace::{cursor::Cursor, TraceReader};
+use differential_dataflow::AsCollection;
 use differential_dataflow::{Collection, ExchangeData};
-use log::info;
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::sync::Arc;
 use std::time::Instant;
 use structopt::StructOpt;
 use timely::communication::allocator::generic::Generic;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Broadcast, Exchange, Probe};
 use timely::dataflow::scopes::child::Child;
-use timely::dataflow::ProbeHandle;
 use timely::order::PartialOrder;
 use timely::worker::Worker;
 
@@ -30,8 +42,22 @@ use encoder::EncodingLogic;
 use encoder::ParserTrait;
 use encoder::Triple;
 
+pub mod datalog;
 pub mod eval;
+pub mod lookup;
 pub mod model;
+pub mod operators;
+pub mod reasoners;
+pub mod sparql;
+pub mod standing;
+pub mod vocabulary;
+
+/// Trace of a collection of the triples of an encoded data set, as kept by the workers
+pub type TripleTrace<D> = TraceAgent<OrdKeySpine<<D as IntoIterator>::Item, usize, isize>>;
+
+/// Scope in which `run_materialization` builds the dataflow. The reasoner passed to it operates
+/// on collections of this scope.
+pub type MaterializationScope<'a> = Child<'a, Worker<Generic>, usize>;
 
 fn parse_key_val<T, U, V>(s: &str) -> Result<(T, U, V), Box<dyn std::error::Error>>
 where
@@ -56,6 +82,29 @@ where
     ))
 }
 
+fn parse_update(s: &str) -> Result<Update, Box<dyn std::error::Error>> {
+    let pos = s
+        .find('=')
+        .ok_or_else(|| format!("Invalid Path=Mode-Type: no `=` found in `{}`", s))?;
+    let second_part = s[pos + 1..].to_lowercase();
+    if second_part == "r" || second_part == "retract" || second_part == "retraction" {
+        return Ok(Update::Retraction {
+            source: s[..pos].to_string(),
+        });
+    }
+    let (spec, source) = match s[pos + 1..].find('@') {
+        Some(at) => (&s[..pos + 1 + at], Some(s[pos + at + 2..].to_string())),
+        None => (s, None),
+    };
+    let (path, mode, kind) = parse_key_val(spec)?;
+    Ok(Update::File {
+        path,
+        mode,
+        kind,
+        source,
+    })
+}
+
 #[derive(StructOpt, Debug)]
 pub struct Args {
     // Differential dataflow parameters
@@ -73,14 +122,70 @@ pub struct Args {
     pub a_box_path: std::path::PathBuf,
     #[structopt(parse(from_os_str))]
     pub output_folder: std::path::PathBuf,
+    // Either PATH=MODE_TYPE[@SOURCE] or SOURCE=retract, see `Update`. The TBox and the ABox
+    // come from the sources named after their files.
     #[structopt(
         name = "UPDATE",
         short = "u",
         long = "update",
-        parse(try_from_str = parse_key_val),
+        parse(try_from_str = parse_update),
         number_of_values = 1,
     )]
-    pub incremental_file_paths: Vec<(std::path::PathBuf, IncrementalMode, IncrementalType)>,
+    pub incremental_file_paths: Vec<Update>,
+    // Read by the binaries to pick the reasoner they pass to `run_materialization`
+    #[structopt(long, default_value = "rho-df")]
+    pub profile: reasoners::Profile,
+    // Handle owl:sameAs through canonical representatives
+    #[structopt(long)]
+    pub same_as: bool,
+    // Write every alias of the canonical triples in the output
+    #[structopt(long)]
+    pub expand_aliases: bool,
+    // Type the individuals that violate a cardinality restriction with owl:Nothing
+    #[structopt(long)]
+    pub check_cardinality: bool,
+    // Spread the keys of a join held by more than this many records over all the workers
+    #[structopt(long)]
+    pub skew_threshold: Option<usize>,
+    // Split the triples in one collection per property before the rules read them
+    #[structopt(long)]
+    pub partition_by_predicate: bool,
+    // Read by the binaries: encode the triples as `PackedTriple`s instead of tuples
+    #[structopt(long)]
+    pub packed: bool,
+    // Which triples are written: all, asserted, inferred, or graphs for both kinds in N-Quads
+    #[structopt(long, default_value = "all")]
+    pub output: OutputMode,
+    // Triples, written as in N-Triples, whose derivation is written after every update
+    #[structopt(long, number_of_values = 1)]
+    pub explain: Vec<String>,
+    // Check the materialization after every update against a closure of the base facts
+    // computed in memory, without dataflow
+    #[structopt(long)]
+    pub verify: bool,
+    // Also maintain the materialization with Delete/Rederive, on one thread and without
+    // dataflow, and plot the timings of both engines together
+    #[structopt(long)]
+    pub dred: bool,
+    // Triple pattern, written as in N-Triples with `?name` for its variables, whose answers
+    // replace the materialization: only the triples relevant to it are derived
+    #[structopt(long)]
+    pub query: Option<String>,
+    // Triple patterns, written as in N-Triples with `?` for any term, whose triples are written
+    // after every update by the workers that hold them
+    #[structopt(long, number_of_values = 1)]
+    pub lookup: Vec<String>,
+    // Files with a SPARQL SELECT query each, whose solutions are written after every update
+    #[structopt(long, number_of_values = 1, parse(from_os_str))]
+    pub sparql: Vec<std::path::PathBuf>,
+    // Files with a basic graph pattern or a triple pattern each, whose added and retracted
+    // bindings are written after every update
+    #[structopt(long, number_of_values = 1, parse(from_os_str))]
+    pub standing: Vec<std::path::PathBuf>,
+    // Rule set that replaces the rules of the profile: Notation3 if the file ends in .n3, the
+    // rule language of the datalog module otherwise
+    #[structopt(long, parse(from_os_str))]
+    pub rules: Option<std::path::PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +199,66 @@ pub enum IncrementalType {
     TBox,
 }
 
+/// An update of the base facts, applied after the full materialization
+#[derive(Debug, Clone)]
+pub enum Update {
+    /// `PATH=MODE_TYPE[@SOURCE]`: the triples of a file, inserted as asserted by `SOURCE` or
+    /// deleted. The source defaults to the name of the file up to its first `.` for an
+    /// insertion. A deletion without a source deletes the triples whatever their sources.
+    File {
+        path: std::path::PathBuf,
+        mode: IncrementalMode,
+        kind: IncrementalType,
+        source: Option<String>,
+    },
+    /// `SOURCE=retract`: every triple asserted by `SOURCE` is deleted, unless another source
+    /// asserts it as well
+    Retraction { source: String },
+}
+
+impl Update {
+    /// Name of the folder of the statistics of the update
+    pub fn name(&self) -> String {
+        match self {
+            Update::File { path, .. } => {
+                let filename = path.file_name().unwrap().to_str().unwrap();
+                let index = filename.find('.').expect("Wrong format name of folder");
+                filename[0..index].to_string()
+            }
+            Update::Retraction { source } => format!("retract_{}", source),
+        }
+    }
+}
+
+/// Which triples of the materialization are written to the output files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Every triple, asserted or inferred, in one N-Triples file
+    All,
+    /// Only the base facts
+    Asserted,
+    /// Only the triples that the rules add to the base facts. A triple that is both asserted and
+    /// derivable is inferred once it is deleted.
+    Inferred,
+    /// Every triple in one N-Quads file, in the `ASSERTED_GRAPH` or the `INFERRED_GRAPH`
+    Graphs,
+}
+
+/// Named graph of the asserted triples in the `OutputMode::Graphs` output
+pub const ASSERTED_GRAPH: &str = "<urn:reasoning-service:asserted>";
+/// Named graph of the inferred triples in the `OutputMode::Graphs` output
+pub const INFERRED_GRAPH: &str = "<urn:reasoning-service:inferred>";
+
+impl OutputMode {
+    /// Extension of the output files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputMode::Graphs => "nq",
+            _ => "nt",
+        }
+    }
+}
+
 // [IMPROVEMENT]:
 // Error Handling.
 // This should go in its own module
@@ -150,6 +315,24 @@ impl std::str::FromStr for IncrementalType {
     }
 }
 
+impl std::str::FromStr for OutputMode {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(OutputMode::All),
+            "asserted" => Ok(OutputMode::Asserted),
+            "inferred" => Ok(OutputMode::Inferred),
+            "graphs" => Ok(OutputMode::Graphs),
+            _ => Err(format!(
+                "{} is not a correct output [all / asserted / inferred / graphs].",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
 fn get_folder_name(path: std::path::PathBuf) -> String {
     let mut path_for_folder = path.clone();
     path_for_folder.pop();
@@ -207,16 +390,26 @@ fn get_avg_encoding_time(path: std::path::PathBuf) -> f64 {
     sum as f64 / count as f64
 }
 
-pub fn run_materialization<L, R, E, P, F, M>(
+pub fn run_materialization<L, R, E, P, F>(
     mut encoder: EncoderUnit<L, R, E, P, F>,
-    materialization: M,
+    reasoner: Reasoner,
 ) -> Result<(), String>
 where
     // [IMPROVEMENT]:
     // The timely dataflow constraint require all of the components that get passed from
     // worker to worker to be 'static because we don't want the closure to outlive
     // the variable.. Is there another way around?
-    R: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Send + Sync + 'static + Clone + Copy,
+    R: std::cmp::Eq
+        + std::hash::Hash
+        + std::fmt::Debug
+        + Ord
+        + Send
+        + Sync
+        + 'static
+        + Clone
+        + Copy
+        // The rules rewritten for `--query` exchange the encodings in their bindings
+        + ExchangeData,
     L: std::cmp::Eq
         + std::hash::Hash
         + std::fmt::Debug
@@ -224,6 +417,7 @@ where
         + Send
         + Sync
         + 'static
+        + Clone
         + From<String>,
     E: EncoderTrait<L, R> + 'static,
     <E::EncodedDataSet as IntoIterator>::Item: ExchangeData
@@ -232,24 +426,10 @@ where
         + std::fmt::Debug
         + Clone
         + Copy
+        + std::hash::Hash
         + differential_dataflow::hashable::Hashable,
     P: ParserTrait<L> + 'static,
     F: EncodingLogic<L, R> + 'static,
-    // [IMPROVEMENT]:
-    // The dataflow timestamp is a usize, should it be anything else?
-    M: Fn(
-            &Collection<
-                Child<'_, Worker<Generic>, usize>,
-                <E::EncodedDataSet as IntoIterator>::Item,
-            >,
-            &mut ProbeHandle<usize>,
-            // RDFS keywords. These might be needed
-            &[R; 5],
-        )
-            -> TraceAgent<OrdKeySpine<<E::EncodedDataSet as IntoIterator>::Item, usize, isize>>
-        + Send
-        + Sync
-        + 'static,
 {
     let args = Arc::new(Args::from_args());
     let another_args = Args::from_args();
@@ -275,44 +455,262 @@ where
     //    My focus right now is on the materialization.
     let mut start = Instant::now();
 
-    let t_box_encoded_path = encoder.encode_persistent(args.t_box_path.clone(), None, None);
+    // The encoder stops at the first term it cannot encode, before the triple with that term is
+    // built: triple types with a limited width, e.g. `PackedTriple`, cannot hold it
+    let max_encoding = <E::EncodedDataSet as IntoIterator>::Item::max_encoding();
+    let not_encoded = |(term, encoded): (L, R)| match max_encoding {
+        Some(max) if encoded > max => format!(
+            "The encoding {:?} of {} does not fit in the triple type, the largest is {:?}",
+            encoded, term, max
+        ),
+        _ => format!("The encoding {:?} of {} is already in use", encoded, term),
+    };
+
+    let (t_box_encoded_path, t_box_source) = encoder
+        .encode_persistent_from(args.t_box_path.clone(), None, None, None)
+        .map_err(not_encoded)?;
     let encoding_time_tbox = start.elapsed().as_millis();
     info!("Persistent Encoding of TBox: {}ms", encoding_time_tbox);
     write_encoding_time(args.t_box_path.clone(), encoding_time_tbox);
 
     start = Instant::now();
-    let a_box_encoded_path = encoder.encode_persistent(args.a_box_path.clone(), None, None);
+    let (a_box_encoded_path, a_box_source) = encoder
+        .encode_persistent_from(args.a_box_path.clone(), None, None, None)
+        .map_err(not_encoded)?;
     let encoding_time_abox = start.elapsed().as_millis();
     info!("Persistent Encoding of ABox: {}ms", encoding_time_abox);
     write_encoding_time(args.a_box_path.clone(), encoding_time_abox);
 
-    start = Instant::now();
-    // Get the encoding of the constant
-    let rdfs_keywords = [
-        *encoder.get_right_from_map(L::from(String::from(RDFS_SUB_CLASS_OF))),
-        *encoder.get_right_from_map(L::from(String::from(RDFS_SUB_PROPERTY_OF))),
-        *encoder.get_right_from_map(L::from(String::from(RDF_TYPE))),
-        *encoder.get_right_from_map(L::from(String::from(RDFS_DOMAIN))),
-        *encoder.get_right_from_map(L::from(String::from(RDFS_RANGE))),
-    ];
-
     let mut update_paths = vec![];
 
-    for (i, (path, a, b)) in args.incremental_file_paths.iter().enumerate() {
-        let update_path = encoder.encode_persistent(path.clone(), None, None);
-        let update_encoding_time = start.elapsed().as_millis();
-
-        info!(
-            "Persistent Encoding of Update #{}: {}ms",
-            i, update_encoding_time,
-        );
-        write_encoding_time(path.clone(), update_encoding_time);
-        start = Instant::now();
+    // The files are replaced by their encodings, and the insertions get the source they are
+    // tagged with
+    for (i, update) in args.incremental_file_paths.iter().enumerate() {
+        let encoded = match update {
+            Update::File {
+                path,
+                mode,
+                kind,
+                source,
+            } => {
+                let (update_path, tagged) = encoder
+                    .encode_persistent_from(path.clone(), source.as_deref(), None, None)
+                    .map_err(not_encoded)?;
+                let update_encoding_time = start.elapsed().as_millis();
+
+                info!(
+                    "Persistent Encoding of Update #{}: {}ms",
+                    i, update_encoding_time,
+                );
+                write_encoding_time(path.clone(), update_encoding_time);
+                start = Instant::now();
+
+                Update::File {
+                    path: update_path.into(),
+                    mode: mode.to_owned(),
+                    kind: kind.to_owned(),
+                    source: match mode {
+                        IncrementalMode::Addition => Some(tagged),
+                        IncrementalMode::Deletion => source.clone(),
+                    },
+                }
+            }
+            Update::Retraction { .. } => update.clone(),
+        };
+        update_paths.push((encoded, update.name()));
+    }
+    // A retraction is checked against the sources tagged by the encoder, all of them are known
+    // by now
+    for update in args.incremental_file_paths.iter() {
+        if let Update::Retraction { source } = update {
+            if !encoder.sources().contains(source) {
+                warn!("No file comes from the retracted source {}", source);
+            }
+        }
+    }
 
-        update_paths.push((update_path, a.to_owned(), b.to_owned()));
+    // The vocabulary is resolved once every dataset has been encoded, so that the terms that do
+    // not appear in the data get an encoding that does not clash with it.
+    let vocabulary = if args.expand_aliases {
+        reasoner.vocabulary().with::<SameAs>()
+    } else {
+        reasoner.vocabulary()
+    };
+    // The filters of the SPARQL queries compare the literals of the data by value
+    let terms: Vec<(String, R)> = if vocabulary.indexes_terms() || !args.sparql.is_empty() {
+        encoder
+            .get_map()
+            .iter()
+            .flat_map(|map| map.iter())
+            .map(|(term, encoded)| (term.to_string(), *encoded))
+            .collect()
+    } else {
+        vec![]
+    };
+    let mut encode = |iri: &str| {
+        encoder
+            .get_or_insert_right(L::from(String::from(iri)))
+            .ok()
+            .cloned()
+    };
+    let mut resolved = Vocabulary::resolve(&vocabulary, &mut encode).map_err(|e| e.to_string())?;
+    resolved
+        .index_terms(terms, &mut encode)
+        .map_err(|e| e.to_string())?;
+    resolved.record_properties(encoder.properties().iter().copied());
+    let vocabulary = resolved;
+
+    // A constant of the query that is not in the data gets an encoding of its own, which
+    // matches nothing
+    let query = match args.query.as_ref() {
+        Some(pattern) => Some(
+            parse_query(pattern, |term| {
+                encoder
+                    .get_or_insert_right(L::from(String::from(term)))
+                    .ok()
+                    .cloned()
+            })
+            .ok_or_else(|| format!("Could not parse the query {}", pattern))?,
+        ),
+        None => None,
+    };
+    if query.is_some()
+        && (args.verify
+            || !args.explain.is_empty()
+            || !args.sparql.is_empty()
+            || !args.standing.is_empty())
+    {
+        return Err(String::from(
+            "--query only materializes the triples relevant to it, it can't be combined with \
+             --verify, --explain, --sparql or --standing",
+        ));
     }
+    // The SPARQL queries, named after their file. As for `--query`, a constant that is not in
+    // the data gets an encoding of its own.
+    let queries = args
+        .sparql
+        .iter()
+        .map(|path| {
+            let name = path.file_stem().map_or_else(
+                || String::from("query"),
+                |stem| stem.to_string_lossy().into(),
+            );
+            let compiled = Query::load(path)
+                .and_then(|query| {
+                    query.compile(|term| {
+                        encoder
+                            .get_or_insert_right(L::from(String::from(term)))
+                            .ok()
+                            .cloned()
+                    })
+                })
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .with_literals(vocabulary.literals());
+            Ok((name, compiled))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let standing = args
+        .standing
+        .iter()
+        .map(|path| {
+            let name = path.file_stem().map_or_else(
+                || String::from("standing"),
+                |stem| stem.to_string_lossy().into(),
+            );
+            let pattern = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            StandingQuery::parse(&name, &pattern, |term| {
+                encoder
+                    .get_or_insert_right(L::from(String::from(term)))
+                    .ok()
+                    .cloned()
+            })
+            .map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // The encodings given to the vocabulary and to the queries must fit as well, before the rules
+    // build triples with them.
+    if let Some(max) = max_encoding {
+        let largest = encoder
+            .get_map()
+            .iter()
+            .flat_map(|map| map.iter())
+            .map(|(_, encoded)| *encoded)
+            .max();
+        if let Some(largest) = largest.filter(|largest| *largest > max) {
+            return Err(format!(
+                "The encoding {:?} does not fit in the triple type, the largest is {:?}",
+                largest, max
+            ));
+        }
+    }
+
+    // The triples to explain, encoded once the dictionary is complete
+    let explained: Vec<(String, Option<<E::EncodedDataSet as IntoIterator>::Item>)> = args
+        .explain
+        .iter()
+        .map(|statement| {
+            let encoded = split_statement(statement).and_then(|(s, p, o)| {
+                let map = encoder.get_map().as_ref()?;
+                let encode = |term: &str| map.get_right(&L::from(term.to_string())).copied();
+                Some(Triple::from_spo(encode(s)?, encode(p)?, encode(o)?))
+            });
+            (statement.clone(), encoded)
+        })
+        .collect();
+
+    let program = if args.dred || query.is_some() {
+        let program = reasoner
+            .program(&vocabulary)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| {
+                String::from(
+                    "--dred and --query need rules that are all written in the rule language: \
+                     --rules or the rho-df profile, without --same-as or --check-cardinality",
+                )
+            })?;
+        Some(program)
+    } else {
+        None
+    };
+    // The patterns to look up, with their terms as in the dictionary
+    let looked_up = args
+        .lookup
+        .iter()
+        .map(|pattern| {
+            let (s, p, o) = split_statement(pattern)
+                .ok_or_else(|| format!("Could not parse the lookup {}", pattern))?;
+            let term =
+                |term: &str| Some(L::from(term.to_string())).filter(|_| !term.starts_with('?'));
+            Ok((pattern.clone(), [term(s), term(p), term(o)]))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Checked before the dataflow runs, the engine itself runs after it
+    let dred = match program.as_ref().filter(|_| args.dred) {
+        Some(program) => Some(DRed::new(program.clone()).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let magic = match (program, query) {
+        (Some(program), Some(query)) => {
+            let magic = MagicSets::new(program, query).map_err(|e| e.to_string())?;
+            info!(
+                "Query rewritten into {} adorned rules",
+                magic.adorned_rules()
+            );
+            Some(magic)
+        }
+        _ => None,
+    };
+    let dred_input = [
+        (t_box_encoded_path.clone(), t_box_source.clone()),
+        (a_box_encoded_path.clone(), a_box_source.clone()),
+    ];
+    let dred_updates = update_paths.clone();
 
     let safe_encoder = Arc::new(encoder);
+    let dred_encoder = safe_encoder.clone();
 
     timely::execute_from_args(timely_params, move |worker| {
         let mut timer = worker.timer();
@@ -320,26 +718,188 @@ where
         let peers = worker.peers();
 
         let mut probe = timely::dataflow::ProbeHandle::new();
+        let arranged_records = ArrangedRecords::register(worker);
 
         // VERY IMPORTANT:
         // TBox data needs to be inserted by EACH WORKER, hence we don't pass the
         // index and the peers to parallelize the computation.
-        let t_data = E::load_encoded_from_persistent(t_box_encoded_path.clone(), None, None);
-        let a_data =
-            E::load_encoded_from_persistent(a_box_encoded_path.clone(), Some(index), Some(peers));
+        let t_data = E::load_encoded_from_persistent(t_box_encoded_path.clone(), None, None)
+            .into_iter()
+            .collect::<Vec<_>>();
+        // The ABox is split by the hash of the triples rather than by line, so that an update
+        // reaches the worker that holds the triple in its base facts.
+        let a_data = E::load_encoded_from_persistent(a_box_encoded_path.clone(), None, None)
+            .into_iter()
+            .filter(|triple| owned_by(triple, index, peers))
+            .collect::<Vec<_>>();
 
         let load_time = timer.elapsed().as_millis();
         info!("Worker {}\t Load time: {}ms", index, load_time,);
         timer = std::time::Instant::now();
 
-        let (mut data_input, mut result_trace) = worker.dataflow::<usize, _, _>(|scope| {
+        let (
+            mut data_input,
+            mut result_trace,
+            mut alias_trace,
+            mut asserted_trace,
+            mut supports_trace,
+            mut verified_traces,
+            mut lookup,
+            mut solutions_traces,
+        ) = worker.dataflow::<usize, _, _>(|scope| {
             let (data_input, data_collection) =
                 scope.new_collection::<<E::EncodedDataSet as IntoIterator>::Item, _>();
-            let res_trace = materialization(&data_collection, &mut probe, &rdfs_keywords);
-            (data_input, res_trace)
-        });
+            let mut res_trace = match magic.as_ref() {
+                Some(magic) => magic.full_materialization(&data_collection, &mut probe),
+                None => reasoner.materialization(&data_collection, &mut probe, &vocabulary)?,
+            };
+
+            // Every worker needs all of the aliases to expand its own part of the output, so
+            // the alias triples are broadcast and arranged locally.
+            let alias_trace = if args.expand_aliases {
+                let same_as = vocabulary.term::<SameAs>()?;
+                let aliases = res_trace
+                    .import(scope)
+                    .as_collection(|triple, _| *triple)
+                    .filter(move |triple| *triple.p() == same_as)
+                    .inner
+                    .broadcast()
+                    .as_collection()
+                    .arrange_core::<_, OrdKeySpine<_, _, _>>(Pipeline, "Aliases");
+                aliases.stream.probe_with(&mut probe);
+                Some((same_as, aliases.trace))
+            } else {
+                None
+            };
 
-        insert_starting_data::<E, _, _>(a_data, &mut data_input, t_data);
+            // The base facts, to tell them apart from the inferred triples in the output.
+            // Arranged by themselves like the materialization, so that each worker holds the
+            // base facts among its part of it.
+            let asserted_trace = if args.output != OutputMode::All {
+                let asserted = data_collection.arrange_by_self();
+                asserted.stream.probe_with(&mut probe);
+                Some(asserted.trace)
+            } else {
+                None
+            };
+
+            // The supports are gathered on the first worker, which writes the explanations
+            let supports_trace = if !args.explain.is_empty() {
+                let materialized = res_trace.import(scope).as_collection(|triple, _| *triple);
+                let supports = reasoner
+                    .supports(&materialized, &vocabulary)?
+                    .concat(&data_collection.map(|triple| (triple, Support::asserted())))
+                    .distinct()
+                    .inner
+                    .exchange(|_| 0)
+                    .as_collection()
+                    .arrange_core::<_, OrdValSpine<_, _, _, _>>(Pipeline, "Supports");
+                supports.stream.probe_with(&mut probe);
+                Some(supports.trace)
+            } else {
+                None
+            };
+
+            // The base facts and the materialization are gathered on the first worker, which
+            // computes the closure of the former to compare it with the latter
+            let verified_traces = if args.verify {
+                let mut gathered = |collection: &Collection<_, _>, name| {
+                    let arranged = collection
+                        .inner
+                        .exchange(|_| 0)
+                        .as_collection()
+                        .arrange_core::<_, OrdKeySpine<_, _, _>>(Pipeline, name);
+                    arranged.stream.probe_with(&mut probe);
+                    arranged.trace
+                };
+                let materialized = res_trace.import(scope).as_collection(|triple, _| *triple);
+                Some((
+                    gathered(&data_collection, "VerifiedBaseFacts"),
+                    gathered(&materialized, "VerifiedMaterialization"),
+                ))
+            } else {
+                None
+            };
+
+            // Each worker holds the triples whose term in the order of the arrangement hashes to it
+            let lookup = if !looked_up.is_empty() {
+                let materialized = res_trace.import(scope).as_collection(|triple, _| *triple);
+                Some(TripleLookup::new(&materialized, &mut probe))
+            } else {
+                None
+            };
+
+            // The solutions of each query are gathered on the first worker, which writes them
+            let solutions_traces = queries
+                .iter()
+                .map(|(_, query)| {
+                    let materialized = res_trace.import(scope).as_collection(|triple, _| *triple);
+                    let solutions = query
+                        .solutions(&materialized)
+                        .inner
+                        .exchange(|_| 0)
+                        .as_collection()
+                        .arrange_core::<_, OrdKeySpine<_, _, _>>(Pipeline, "Solutions");
+                    solutions.stream.probe_with(&mut probe);
+                    solutions.trace
+                })
+                .collect::<Vec<_>>();
+
+            Ok((
+                data_input,
+                res_trace,
+                alias_trace,
+                asserted_trace,
+                supports_trace,
+                verified_traces,
+                lookup,
+                solutions_traces,
+            ))
+        })?;
+
+        // The standing queries follow the materialization from its start, their deltas are
+        // appended to one file per query by the first worker
+        let mut deltas: Vec<Deltas<R>> = standing
+            .iter()
+            .map(|query| query.register(worker, &mut result_trace, &mut probe))
+            .collect();
+        let mut delta_files = if index == 0 {
+            standing
+                .iter()
+                .map(|query| {
+                    let mut path = args.output_folder.clone();
+                    path.push(format!("standing_{}.tsv", query.name()));
+                    let mut file = OpenOptions::new()
+                        .write(true)
+                        .truncate(true)
+                        .create(true)
+                        .open(path)
+                        .expect("Something wrong happened with the ouput file");
+                    let header: Vec<String> = query
+                        .variables()
+                        .iter()
+                        .map(|v| format!("?{}", v))
+                        .collect();
+                    if let Err(e) = writeln!(file, "update\tdelta\t{}", header.join("\t")) {
+                        panic!("Couldn't write to file: {}", e);
+                    }
+                    file
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let mut base_facts = BaseFacts::new();
+        let ignored = insert_starting_data::<E, _, _, _>(
+            a_data,
+            &mut data_input,
+            t_data,
+            &mut base_facts,
+            &t_box_source,
+            &a_box_source,
+        );
+        info!("Worker {}\t Ignored triples: {}", index, ignored);
 
         while probe.less_than(data_input.time()) {
             worker.step();
@@ -350,19 +910,86 @@ where
             "Worker {}\t Full Materialization time: {}ms",
             index, full_mat_time,
         );
+        info!(
+            "Worker {}\t Arranged records: {}",
+            index,
+            arranged_records.get()
+        );
         timer = std::time::Instant::now();
 
         let mut output = args.output_folder.clone();
-        output.push(format!("full_materialization_worker{}.nt", index));
+        output.push(format!(
+            "full_materialization_worker{}.{}",
+            index,
+            args.output.extension()
+        ));
 
         // This is basically not parallel since it locks the encoder during the execution of the
         // function
+        let aliases = alias_trace
+            .as_mut()
+            .map(|(same_as, trace)| Aliases::from_trace(trace, 1, *same_as));
         save_to_file_through_trace::<E, _, _, _>(
-            &safe_encoder.get_map().as_ref().unwrap(),
+            safe_encoder.get_map().as_ref().unwrap(),
             output.as_path(),
             &mut result_trace,
             1,
+            aliases.as_ref(),
+            args.output,
+            asserted_trace.as_mut(),
         );
+        if let Some(trace) = supports_trace.as_mut().filter(|_| index == 0) {
+            let mut path = args.output_folder.clone();
+            path.push("full_materialization_explanations.txt");
+            save_explanations::<E, _, _, _, _>(
+                safe_encoder.get_map().as_ref().unwrap(),
+                path,
+                trace,
+                1,
+                &explained,
+            );
+        }
+
+        if let Some((asserted, materialized)) = verified_traces.as_mut().filter(|_| index == 0) {
+            let mut path = args.output_folder.clone();
+            path.push("full_materialization_verification.txt");
+            verify_materialization::<E, _, _, _, _>(
+                safe_encoder.get_map().as_ref().unwrap(),
+                path,
+                asserted,
+                materialized,
+                1,
+                |facts| reasoner.materialize_set(facts, &vocabulary),
+            )?;
+        }
+
+        if let Some(lookup) = lookup.as_mut() {
+            let mut path = args.output_folder.clone();
+            path.push(format!("full_materialization_lookups_worker{}.txt", index));
+            save_lookups::<E, _, _, _>(
+                safe_encoder.get_map().as_ref().unwrap(),
+                path,
+                lookup,
+                1,
+                &looked_up,
+            );
+        }
+        for (deltas, file) in deltas.iter_mut().zip(delta_files.iter_mut()) {
+            save_deltas::<E, _, _>(safe_encoder.get_map().as_ref().unwrap(), file, deltas, 1);
+        }
+        if index == 0 {
+            for ((name, query), trace) in queries.iter().zip(solutions_traces.iter_mut()) {
+                let mut path = args.output_folder.clone();
+                path.push(format!("full_materialization_{}.tsv", name));
+                save_solutions::<E, _, _, _>(
+                    safe_encoder.get_map().as_ref().unwrap(),
+                    path,
+                    query,
+                    trace,
+                    1,
+                );
+            }
+        }
 
         let save_persistent_time = timer.elapsed().as_millis();
         info!(
@@ -375,6 +1002,8 @@ where
             load_time,
             mat_time: full_mat_time,
             save_persistent_time,
+            arranged_records: arranged_records.get(),
+            ignored_triples: ignored,
         };
 
         full_mat_stats.write_to_file(args.output_folder.clone(), Some(index), Some(peers));
@@ -390,12 +1019,25 @@ where
         // What about a data structure shared by all the workers where all the t-box triples are
         // stored only once?
 
-        for (i, (path, mode, t)) in update_paths.clone().iter().enumerate() {
-            let data = match t {
-                IncrementalType::TBox => E::load_encoded_from_persistent(path.clone(), None, None),
-                IncrementalType::ABox => {
-                    E::load_encoded_from_persistent(path, Some(index), Some(peers))
-                }
+        for (i, (update, name)) in update_paths.iter().enumerate() {
+            let data: Vec<_> = match update {
+                Update::File {
+                    path,
+                    kind: IncrementalType::TBox,
+                    ..
+                } => E::load_encoded_from_persistent(path, None, None)
+                    .into_iter()
+                    .collect(),
+                Update::File {
+                    path,
+                    kind: IncrementalType::ABox,
+                    ..
+                } => E::load_encoded_from_persistent(path, None, None)
+                    .into_iter()
+                    .filter(|triple| owned_by(triple, index, peers))
+                    .collect(),
+                // Each worker retracts the source from its own base facts
+                Update::Retraction { .. } => vec![],
             };
             let load_time = timer.elapsed().as_millis();
             info!(
@@ -406,10 +1048,39 @@ where
             );
             timer = std::time::Instant::now();
 
-            match mode {
-                IncrementalMode::Addition => add_data::<E, _, _>(data, &mut data_input, 2 + i),
-                IncrementalMode::Deletion => remove_data::<E, _, _>(data, &mut data_input, 2 + i),
-            }
+            let ignored = match update {
+                Update::File {
+                    mode: IncrementalMode::Addition,
+                    source,
+                    ..
+                } => add_data::<E, _, _, _>(
+                    data,
+                    &mut data_input,
+                    &mut base_facts,
+                    source.as_deref().unwrap_or(name),
+                    2 + i,
+                ),
+                Update::File {
+                    mode: IncrementalMode::Deletion,
+                    source,
+                    ..
+                } => remove_data::<E, _, _, _>(
+                    data,
+                    &mut data_input,
+                    &mut base_facts,
+                    source.as_deref(),
+                    2 + i,
+                ),
+                Update::Retraction { source } => {
+                    retract_source::<E, _, _>(source, &mut data_input, &mut base_facts, 2 + i)
+                }
+            };
+            info!(
+                "Worker {}\t Update #{} Ignored triples: {}",
+                index,
+                i + 1,
+                ignored,
+            );
 
             while probe.less_than(data_input.time()) {
                 worker.step();
@@ -425,15 +1096,97 @@ where
             timer = std::time::Instant::now();
 
             let mut changed_path = args.output_folder.to_owned();
-            changed_path
-                .push(&format!("incremental_materialization_{}_worker{}.nt", i + 1, index)[..]);
+            changed_path.push(
+                &format!(
+                    "incremental_materialization_{}_worker{}.{}",
+                    i + 1,
+                    index,
+                    args.output.extension()
+                )[..],
+            );
 
+            let aliases = alias_trace
+                .as_mut()
+                .map(|(same_as, trace)| Aliases::from_trace(trace, 2 + i, *same_as));
             save_to_file_through_trace::<E, _, _, _>(
-                &safe_encoder.get_map().as_ref().unwrap(),
+                safe_encoder.get_map().as_ref().unwrap(),
                 changed_path,
                 &mut result_trace,
                 2 + i,
+                aliases.as_ref(),
+                args.output,
+                asserted_trace.as_mut(),
             );
+            if let Some(trace) = supports_trace.as_mut().filter(|_| index == 0) {
+                let mut path = args.output_folder.clone();
+                path.push(format!(
+                    "incremental_materialization_{}_explanations.txt",
+                    i + 1
+                ));
+                save_explanations::<E, _, _, _, _>(
+                    safe_encoder.get_map().as_ref().unwrap(),
+                    path,
+                    trace,
+                    2 + i,
+                    &explained,
+                );
+            }
+            if let Some((asserted, materialized)) = verified_traces.as_mut().filter(|_| index == 0)
+            {
+                let mut path = args.output_folder.clone();
+                path.push(format!(
+                    "incremental_materialization_{}_verification.txt",
+                    i + 1
+                ));
+                verify_materialization::<E, _, _, _, _>(
+                    safe_encoder.get_map().as_ref().unwrap(),
+                    path,
+                    asserted,
+                    materialized,
+                    2 + i,
+                    |facts| reasoner.materialize_set(facts, &vocabulary),
+                )?;
+            }
+            if let Some(lookup) = lookup.as_mut() {
+                let mut path = args.output_folder.clone();
+                path.push(format!(
+                    "incremental_materialization_{}_lookups_worker{}.txt",
+                    i + 1,
+                    index
+                ));
+                save_lookups::<E, _, _, _>(
+                    safe_encoder.get_map().as_ref().unwrap(),
+                    path,
+                    lookup,
+                    2 + i,
+                    &looked_up,
+                );
+            }
+            for (deltas, file) in deltas.iter_mut().zip(delta_files.iter_mut()) {
+                save_deltas::<E, _, _>(
+                    safe_encoder.get_map().as_ref().unwrap(),
+                    file,
+                    deltas,
+                    2 + i,
+                );
+            }
+            if index == 0 {
+                for ((name, query), trace) in queries.iter().zip(solutions_traces.iter_mut()) {
+                    let mut path = args.output_folder.clone();
+                    path.push(format!(
+                        "incremental_materialization_{}_{}.tsv",
+                        i + 1,
+                        name
+                    ));
+                    save_solutions::<E, _, _, _>(
+                        safe_encoder.get_map().as_ref().unwrap(),
+                        path,
+                        query,
+                        trace,
+                        2 + i,
+                    );
+                }
+            }
             let save_persistent_time = timer.elapsed().as_millis();
             info!(
                 "Worker {}\t Update #{} Save to File Time: {}ms",
@@ -447,41 +1200,217 @@ where
                 load_time,
                 mat_time,
                 save_persistent_time,
+                arranged_records: arranged_records.get(),
+                ignored_triples: ignored,
             };
 
-            let update_stats_path = std::path::PathBuf::from(path);
-            let filename = update_stats_path.file_name().unwrap().to_str().unwrap();
-            let len = filename.len();
-            let subfilename = &filename[0..len - 14];
             let mut out = args.output_folder.clone();
             out.push("update_stats/");
-            out.push(format!("{}_stats/", subfilename));
+            out.push(format!("{}_stats/", name));
             increm_stats.write_to_file(out, Some(index), Some(peers))
         }
+        Ok::<(), VocabularyError>(())
     })?
     // Main thread waits for all the workers to finish job so this guarantees that the evaluation
     // has been written and the main function can proceed an process them.
-    .join();
+    .join()
+    .into_iter()
+    .collect::<Result<Result<Vec<()>, VocabularyError>, String>>()?
+    .map_err(|e| e.to_string())?;
+
+    let mut dred_folder = another_args.output_folder.clone();
+    dred_folder.push("dred/");
+    if let Some(dred) = dred {
+        maintain_with_dred::<E, _, _>(
+            dred,
+            dred_encoder.get_map().as_ref().unwrap(),
+            &dred_input,
+            &dred_updates,
+            &dred_folder,
+        );
+    }
 
     // Print evaluation of time spent per worker
     let mut stats_folder = another_args.output_folder.clone();
     stats_folder.push("stats/");
-    crate::eval::output_figures(stats_folder);
+    crate::eval::output_figures(stats_folder.clone());
+    if another_args.dred {
+        compare_engines(stats_folder, dred_folder.join("stats/"));
+    }
 
-    for path in another_args.incremental_file_paths.clone() {
-        let folder = get_folder(&path.0, &another_args.output_folder);
-        crate::eval::output_figures(folder);
+    for update in another_args.incremental_file_paths.iter() {
+        let folder = get_folder(update, &another_args.output_folder);
+        crate::eval::output_figures(folder.clone());
+        if another_args.dred {
+            compare_engines(folder, get_folder(update, &dred_folder));
+        }
     }
 
     Ok(())
 }
 
-fn get_folder(buf: &std::path::PathBuf, output: &std::path::PathBuf) -> std::path::PathBuf {
+// The timings of the dataflow next to the ones of Delete/Rederive, in the figures of the former
+fn compare_engines(dataflow: std::path::PathBuf, dred: std::path::PathBuf) {
+    let figures = dataflow.join("figures/");
+    crate::eval::output_engine_figures(
+        &[("Differential Dataflow", dataflow), ("DRed", dred)],
+        figures,
+    );
+}
+
+/// Materializes the starting data, given as the encoded files with their sources, and applies
+/// the updates with [`DRed`] on the calling thread. The materializations and the statistics are
+/// written in `folder`, as the dataflow writes them in the output folder with a single worker.
+pub fn maintain_with_dred<E, K, V>(
+    mut dred: DRed<<E::EncodedDataSet as IntoIterator>::Item, V>,
+    map: &E::MapStructure,
+    starting: &[(String, String)],
+    updates: &[(Update, String)],
+    folder: &std::path::Path,
+) where
+    E: EncoderTrait<K, V>,
+    <E::EncodedDataSet as IntoIterator>::Item:
+        std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static + Triple<V>,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Copy,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    std::fs::create_dir_all(folder).expect("Could not create the DRed output directory");
+    let mut base_facts = BaseFacts::new();
+    // The changes of the base facts are applied at once, insertions and deletions apart
+    let maintain =
+        |dred: &mut DRed<_, V>,
+         changes: Vec<(<E::EncodedDataSet as IntoIterator>::Item, isize)>| {
+            let (inserted, deleted): (Vec<_>, Vec<_>) =
+                changes.into_iter().partition(|(_, diff)| *diff > 0);
+            dred.update(
+                inserted.into_iter().map(|(triple, _)| triple),
+                deleted.into_iter().map(|(triple, _)| triple),
+            )
+        };
+    let save = |dred: &DRed<_, V>, name: &str| {
+        let mut file = eval::open_truncate(folder.join(name));
+        for triple in dred.materialized() {
+            let statement = decode_statement(map, triple);
+            if writable(&statement) {
+                writeln!(file, "{}", statement).expect("Could not write the DRed materialization");
+            }
+        }
+    };
+    // The triples held in memory stand for the records of the arrangements
+    let held = |dred: &DRed<_, V>| dred.len() + dred.asserted().len();
+
+    let mut timer = Instant::now();
+    let starting: Vec<(Vec<_>, &str)> = starting
+        .iter()
+        .map(|(path, source)| {
+            let triples = E::load_encoded_from_persistent(path, None, None)
+                .into_iter()
+                .collect();
+            (triples, source.as_str())
+        })
+        .collect();
+    let load_time = timer.elapsed().as_millis();
+    timer = Instant::now();
+
+    let mut changes = vec![];
+    let mut ignored = 0;
+    for (triples, source) in starting {
+        for triple in triples {
+            if !base_facts.insert(triple, source, &mut changes) {
+                ignored += 1;
+            }
+        }
+    }
+    let maintenance = maintain(&mut dred, changes);
+    let mat_time = timer.elapsed().as_millis();
+    info!(
+        "DRed\t Full Materialization time: {}ms, {} triples",
+        mat_time, maintenance.inserted
+    );
+    timer = Instant::now();
+
+    save(&dred, "full_materialization.nt");
+    Statistics {
+        load_time,
+        mat_time,
+        save_persistent_time: timer.elapsed().as_millis(),
+        arranged_records: held(&dred),
+        ignored_triples: ignored,
+    }
+    .write_to_file(folder.to_path_buf(), None, None);
+
+    for (i, (update, name)) in updates.iter().enumerate() {
+        timer = Instant::now();
+        let data: Vec<_> = match update {
+            Update::File { path, .. } => E::load_encoded_from_persistent(path, None, None)
+                .into_iter()
+                .collect(),
+            Update::Retraction { .. } => vec![],
+        };
+        let load_time = timer.elapsed().as_millis();
+        timer = Instant::now();
+
+        let mut changes = vec![];
+        let ignored = match update {
+            Update::File {
+                mode: IncrementalMode::Addition,
+                source,
+                ..
+            } => {
+                let source = source.as_deref().unwrap_or(name);
+                data.into_iter()
+                    .filter(|triple| !base_facts.insert(triple.clone(), source, &mut changes))
+                    .count()
+            }
+            Update::File {
+                mode: IncrementalMode::Deletion,
+                source,
+                ..
+            } => data
+                .into_iter()
+                .filter(|triple| {
+                    !base_facts.remove(triple.clone(), source.as_deref(), &mut changes)
+                })
+                .count(),
+            Update::Retraction { source } => {
+                let (deleted, kept) = base_facts.retract(source, &mut changes);
+                info!(
+                    "DRed\t Retracted source {}: {} triples deleted",
+                    source, deleted
+                );
+                kept
+            }
+        };
+        let maintenance = maintain(&mut dred, changes);
+        let mat_time = timer.elapsed().as_millis();
+        info!(
+            "DRed\t Update #{} Update time: {}ms, {} overdeleted, {} rederived, {} inserted",
+            i + 1,
+            mat_time,
+            maintenance.overdeleted,
+            maintenance.rederived,
+            maintenance.inserted
+        );
+        timer = Instant::now();
+
+        save(&dred, &format!("incremental_materialization_{}.nt", i + 1));
+        let mut out = folder.join("update_stats/");
+        out.push(format!("{}_stats/", name));
+        Statistics {
+            load_time,
+            mat_time,
+            save_persistent_time: timer.elapsed().as_millis(),
+            arranged_records: held(&dred),
+            ignored_triples: ignored,
+        }
+        .write_to_file(out, None, None);
+    }
+}
+
+fn get_folder(update: &Update, output: &std::path::PathBuf) -> std::path::PathBuf {
     let mut result = output.clone();
-    let filename = buf.file_name().unwrap().to_str().unwrap();
-    let index = filename.find('.').expect("Wrong format name of folder");
     result.push("update_stats/");
-    result.push(format!("{}_stats/", &filename[0..index]));
+    result.push(format!("{}_stats/", update.name()));
     result.push("stats/");
     result
 }
@@ -522,105 +1451,316 @@ fn get_timely_args(args: std::sync::Arc<Args>) -> Vec<String> {
     }
     result
 }
+/// The base facts asserted through the input of a worker, with the sources that assert them.
+/// They keep the input a set: a triple is inserted when its first source asserts it and deleted
+/// when its last one no longer does, so inserting a triple that a source already asserts, or
+/// deleting one that is not asserted, is dropped instead. The triples derived from a deleted
+/// one that other base facts still derive stay in the materialization.
+///
+/// Every worker inserts the whole TBox, while each ABox triple goes through the worker its hash
+/// points to (see [`owned_by`]), so that its insertions and deletions meet in the same set.
+pub struct BaseFacts<T> {
+    // The sources asserting each triple, as indexes in `sources`
+    asserted: HashMap<T, Vec<usize>>,
+    sources: Vec<String>,
+}
+
+impl<T> BaseFacts<T>
+where
+    T: std::hash::Hash + Eq + Clone + Ord + std::fmt::Debug + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            asserted: HashMap::new(),
+            sources: vec![],
+        }
+    }
+
+    fn source_index(&self, source: &str) -> Option<usize> {
+        self.sources.iter().position(|known| known == source)
+    }
+
+    /// Asserts the triple from `source`, inserting it unless another source already asserts it.
+    /// Returns whether the source did not assert it yet.
+    pub fn insert(&mut self, triple: T, source: &str, data_input: &mut impl Changes<T>) -> bool {
+        let source = self.source_index(source).unwrap_or_else(|| {
+            self.sources.push(source.to_string());
+            self.sources.len() - 1
+        });
+        let sources = self.asserted.entry(triple.clone()).or_default();
+        if sources.contains(&source) {
+            return false;
+        }
+        if sources.is_empty() {
+            data_input.change(triple, 1);
+        }
+        sources.push(source);
+        true
+    }
+
+    /// Withdraws the triple from `source`, or from all of its sources if `source` is `None`,
+    /// and deletes it if no source asserts it anymore. Returns whether it was withdrawn.
+    pub fn remove(
+        &mut self,
+        triple: T,
+        source: Option<&str>,
+        data_input: &mut impl Changes<T>,
+    ) -> bool {
+        let sources = match self.asserted.get_mut(&triple) {
+            Some(sources) => sources,
+            None => return false,
+        };
+        if let Some(source) = source {
+            let index = self.sources.iter().position(|known| known == source);
+            match index.and_then(|index| sources.iter().position(|s| *s == index)) {
+                Some(position) => {
+                    sources.swap_remove(position);
+                }
+                None => return false,
+            }
+            if !sources.is_empty() {
+                return true;
+            }
+        }
+        self.asserted.remove(&triple);
+        data_input.change(triple, -1);
+        true
+    }
+
+    /// Withdraws every triple asserted by `source`. Returns the number of triples deleted, and
+    /// the number of triples kept because other sources assert them too.
+    pub fn retract(&mut self, source: &str, data_input: &mut impl Changes<T>) -> (usize, usize) {
+        let index = match self.source_index(source) {
+            Some(index) => index,
+            None => return (0, 0),
+        };
+        let (mut deleted, mut kept) = (0, 0);
+        self.asserted.retain(|triple, sources| {
+            match sources.iter().position(|s| *s == index) {
+                Some(position) => {
+                    sources.swap_remove(position);
+                }
+                None => return true,
+            }
+            if sources.is_empty() {
+                data_input.change(triple.clone(), -1);
+                deleted += 1;
+                false
+            } else {
+                kept += 1;
+                true
+            }
+        });
+        (deleted, kept)
+    }
+
+    /// The sources asserting the triple
+    pub fn sources(&self, triple: &T) -> Vec<&str> {
+        self.asserted
+            .get(triple)
+            .into_iter()
+            .flatten()
+            .map(|index| self.sources[*index].as_str())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.asserted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.asserted.is_empty()
+    }
+}
+
+impl<T> Default for BaseFacts<T>
+where
+    T: std::hash::Hash + Eq + Clone + Ord + std::fmt::Debug + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where [`BaseFacts`] send the insertions and deletions of triples: the input of the dataflow,
+/// or the updates given to another engine
+pub trait Changes<T> {
+    /// Inserts the triple if `diff` is positive, deletes it if it is negative
+    fn change(&mut self, triple: T, diff: isize);
+}
+
+impl<T: differential_dataflow::Data> Changes<T> for InputSession<usize, T, isize> {
+    fn change(&mut self, triple: T, diff: isize) {
+        self.update(triple, diff);
+    }
+}
+
+impl<T> Changes<T> for Vec<(T, isize)> {
+    fn change(&mut self, triple: T, diff: isize) {
+        self.push((triple, diff));
+    }
+}
+
+/// Whether the ABox triple goes through the input of worker `index` out of `peers`
+pub fn owned_by<T: std::hash::Hash>(triple: &T, index: usize, peers: usize) -> bool {
+    triple.hashed() % peers as u64 == index as u64
+}
+
 /// insert data provided by the abox or tbox into the dataflow through
-/// the input handles.
-pub fn insert_starting_data<E, K, V>(
-    a_box: E::EncodedDataSet,
+/// the input handles, as asserted by their sources. Returns the number of triples ignored because
+/// their source already asserted them.
+pub fn insert_starting_data<E, K, V, I>(
+    a_box: I,
     data_input: &mut InputSession<
         usize,
         <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
         isize,
     >,
-    t_box: E::EncodedDataSet,
-) where
+    t_box: I,
+    base_facts: &mut BaseFacts<
+        <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
+    >,
+    t_box_source: &str,
+    a_box_source: &str,
+) -> usize
+where
     E: EncoderTrait<K, V>,
     E::EncodedDataSet: std::iter::IntoIterator,
+    I: IntoIterator<Item = <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item>,
     <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item:
-        std::fmt::Debug + Clone + Ord + 'static,
-    // [IMPROVEMENT]:
-    // Try to understand why V has to be 'static and think of the impact that
-    // a static V has on performance.
+        std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static,
     V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
     K: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
 {
-    for triple in t_box {
-        data_input.insert(triple);
-    }
-    for triple in a_box {
-        data_input.insert(triple);
+    let mut ignored = 0;
+    let t_box = t_box.into_iter().map(|triple| (triple, t_box_source));
+    let a_box = a_box.into_iter().map(|triple| (triple, a_box_source));
+    for (triple, source) in t_box.chain(a_box) {
+        if !base_facts.insert(triple, source, data_input) {
+            ignored += 1;
+        }
     }
 
     // initial data are inserted all with timestamp 0, so we advance at time 1 and schedule the worker
     data_input.advance_to(1);
     data_input.flush();
+    ignored
 }
 
-pub fn add_data<E, K, V>(
-    batch: E::EncodedDataSet,
+/// Asserts the batch from `source`. Returns the number of triples ignored because the source
+/// already asserted them.
+pub fn add_data<E, K, V, I>(
+    batch: I,
     data_input: &mut InputSession<
         usize,
         <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
         isize,
     >,
+    base_facts: &mut BaseFacts<
+        <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
+    >,
+    source: &str,
     time_to_advance_to: usize,
-) where
+) -> usize
+where
     E: EncoderTrait<K, V>,
     E::EncodedDataSet: std::iter::IntoIterator,
+    I: IntoIterator<Item = <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item>,
     <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item:
-        std::fmt::Debug + Clone + Ord + 'static,
-    // [IMPROVEMENT]:
-    // Try to understand why V has to be 'static and think of the impact that
-    // a static V has on performance.
+        std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static,
     V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
     K: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
 {
+    let mut ignored = 0;
     for triple in batch {
-        data_input.insert(triple);
+        if !base_facts.insert(triple, source, data_input) {
+            ignored += 1;
+        }
     }
 
     data_input.advance_to(time_to_advance_to);
     data_input.flush();
+    ignored
 }
 
-pub fn remove_data<E, K, V>(
-    batch: E::EncodedDataSet,
+/// Withdraws the batch from `source`, or from every source if it is `None`. Returns the number
+/// of triples ignored because they were not asserted by it.
+pub fn remove_data<E, K, V, I>(
+    batch: I,
     data_input: &mut InputSession<
         usize,
         <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
         isize,
     >,
+    base_facts: &mut BaseFacts<
+        <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
+    >,
+    source: Option<&str>,
     time_to_advance_to: usize,
-) where
+) -> usize
+where
     E: EncoderTrait<K, V>,
     E::EncodedDataSet: std::iter::IntoIterator,
+    I: IntoIterator<Item = <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item>,
     <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item:
-        std::fmt::Debug + Clone + Ord + 'static,
-    // [IMPROVEMENT]:
-    // Try to understand why V has to be 'static and think of the impact that
-    // a static V has on performance.
+        std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static,
     V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
     K: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
 {
+    let mut ignored = 0;
     for triple in batch {
-        data_input.remove(triple);
+        if !base_facts.remove(triple, source, data_input) {
+            ignored += 1;
+        }
     }
 
     data_input.advance_to(time_to_advance_to);
     data_input.flush();
+    ignored
+}
+
+/// Withdraws every triple asserted by `source`. Returns the number of triples ignored because
+/// another source still asserts them.
+pub fn retract_source<E, K, V>(
+    source: &str,
+    data_input: &mut InputSession<
+        usize,
+        <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
+        isize,
+    >,
+    base_facts: &mut BaseFacts<
+        <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
+    >,
+    time_to_advance_to: usize,
+) -> usize
+where
+    E: EncoderTrait<K, V>,
+    E::EncodedDataSet: std::iter::IntoIterator,
+    <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item:
+        std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
+{
+    let (deleted, kept) = base_facts.retract(source, data_input);
+    info!("Retracted source {}: {} triples deleted", source, deleted);
+
+    data_input.advance_to(time_to_advance_to);
+    data_input.flush();
+    kept
 }
 
 /// Save the full materialization fo file
 pub fn save_to_file_through_trace<E, K, V, W: AsRef<std::path::Path>>(
     map: &E::MapStructure,
     path: W,
-    trace: &mut TraceAgent<
-        OrdKeySpine<
-            <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
-            usize,
-            isize,
-        >,
-    >,
+    trace: &mut TripleTrace<E::EncodedDataSet>,
     time: usize,
+    // When present, every triple is written once per combination of the aliases of its terms.
+    // The asserted triples are written as they were stated.
+    aliases: Option<&Aliases<V>>,
+    mode: OutputMode,
+    // The base facts, arranged like `trace`. Required by every mode but `OutputMode::All`.
+    asserted: Option<&mut TripleTrace<E::EncodedDataSet>>,
 ) where
     E: EncoderTrait<K, V>,
     E::EncodedDataSet: std::iter::IntoIterator,
@@ -629,7 +1769,7 @@ pub fn save_to_file_through_trace<E, K, V, W: AsRef<std::path::Path>>(
     // [IMPROVEMENT]:
     // Try to understand why V has to be 'static and think of the impact that
     // a static V has on performance.
-    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Copy,
     K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
 {
     let mut full_materialization_file = OpenOptions::new()
@@ -641,26 +1781,70 @@ pub fn save_to_file_through_trace<E, K, V, W: AsRef<std::path::Path>>(
         // Instead of expecting return a Result<()>
         .expect("Something wrong happened with the ouput file");
 
+    let mut write = |triples: Vec<(V, V, V)>, graph: Option<&str>| {
+        for (s, p, o) in triples {
+            let s = map.get_left(&s).expect("Could not find the subject");
+            let p = map.get_left(&p).expect("Could not find the property");
+            let o = map.get_left(&o).expect("Could not find the object");
+            let statement = match graph {
+                Some(graph) => format!("{} {} {} {} .", s, p, o, graph),
+                None => format!("{} {} {} .", s, p, o),
+            };
+            if !writable(&statement) {
+                continue;
+            }
+            if let Err(e) = writeln!(full_materialization_file, "{}", statement) {
+                panic!("Couldn't write to file: {}", e);
+            }
+        }
+    };
+    let expand = |key: &<<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item| {
+        match aliases {
+            Some(aliases) => aliases.expand(key),
+            None => vec![(*key.s(), *key.p(), *key.o())],
+        }
+    };
+
+    let mut asserted = asserted.map(|trace| {
+        trace
+            .cursor_through(&[time])
+            .expect("COULDN'T GET CURSOR ON THE ASSERTED TRIPLES")
+    });
+    if mode != OutputMode::All && asserted.is_none() {
+        panic!("The {:?} output requires the asserted triples", mode);
+    }
+
+    if mode == OutputMode::Asserted {
+        let (cursor, storage) = asserted.as_mut().unwrap();
+        while let Some(key) = cursor.get_key(storage) {
+            if present(cursor, storage, time) {
+                write(vec![(*key.s(), *key.p(), *key.o())], None);
+            }
+            cursor.step_key(storage);
+        }
+        return;
+    }
+
     if let Some((mut cursor, storage)) = trace.cursor_through(&[time]) {
         while let Some(key) = cursor.get_key(&storage) {
             while let Some(&()) = cursor.get_val(&storage) {
-                let mut count = 0;
-                cursor.map_times(&storage, |t, diff| {
-                    // println!("{}, DIFF:{:?} ", key, diff);
-                    if t.less_equal(&(time - 1)) {
-                        count += diff;
-                    }
-                });
-                if count > 0 {
-                    // println!("{:?}", key);
-                    // key.print_easy_reading();
+                if present(&mut cursor, &storage, time) {
+                    // Keys come in order in both traces, so the asserted cursor only moves forward
+                    let is_asserted = asserted.as_mut().is_some_and(|(asserted, storage)| {
+                        asserted.seek_key(storage, key);
+                        asserted.get_key(storage) == Some(key) && present(asserted, storage, time)
+                    });
                     // [IMPROVEMENT]:
                     // Error handling instead of unwraps and expects!
-                    let s = map.get_left(key.s()).expect("Could not find the subject");
-                    let p = map.get_left(key.p()).expect("Could not find the property");
-                    let o = map.get_left(key.o()).expect("Could not find the object");
-                    if let Err(e) = writeln!(full_materialization_file, "{} {} {} .", s, p, o) {
-                        panic!("Couldn't write to file: {}", e);
+                    match (mode, is_asserted) {
+                        (OutputMode::All, _) | (OutputMode::Inferred, false) => {
+                            write(expand(key), None)
+                        }
+                        (OutputMode::Graphs, true) => {
+                            write(vec![(*key.s(), *key.p(), *key.o())], Some(ASSERTED_GRAPH))
+                        }
+                        (OutputMode::Graphs, false) => write(expand(key), Some(INFERRED_GRAPH)),
+                        _ => {}
                     }
                 }
                 cursor.step_val(&storage);
@@ -672,16 +1856,338 @@ pub fn save_to_file_through_trace<E, K, V, W: AsRef<std::path::Path>>(
     }
 }
 
+/// Writes a derivation tree of each triple of `explained`, as found in the supports gathered in
+/// `trace`, or why there is none
+pub fn save_explanations<E, K, V, T, W: AsRef<std::path::Path>>(
+    map: &E::MapStructure,
+    path: W,
+    trace: &mut TraceAgent<OrdValSpine<T, Support<T>, usize, isize>>,
+    time: usize,
+    // Each triple as it was given, with its encoding if all of its terms have one
+    explained: &[(String, Option<T>)],
+) where
+    E: EncoderTrait<K, V>,
+    T: std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static + Triple<V>,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Copy,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    let mut supports = HashMap::new();
+    if let Some((mut cursor, storage)) = trace.cursor_through(&[time]) {
+        while let Some(key) = cursor.get_key(&storage) {
+            while let Some(support) = cursor.get_val(&storage) {
+                if present(&mut cursor, &storage, time) {
+                    supports
+                        .entry(key.clone())
+                        .or_insert_with(Vec::new)
+                        .push(support.clone());
+                }
+                cursor.step_val(&storage);
+            }
+            cursor.step_key(&storage);
+        }
+    }
+
+    let decode = |triple: &T| decode_statement(map, triple);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .expect("Something wrong happened with the ouput file");
+    for (statement, triple) in explained {
+        let explanation = match triple {
+            None => format!("{}  # a term is not in the dictionary\n", statement),
+            Some(triple) => match explain(triple, &supports) {
+                Some(explanation) => explanation.render(&decode),
+                None => format!("{}  # not in the materialization\n", statement),
+            },
+        };
+        if let Err(e) = writeln!(file, "{}", explanation) {
+            panic!("Couldn't write to file: {}", e);
+        }
+    }
+}
+
+/// Writes the triples of `lookup` that match each pattern of `patterns`, given with the terms of
+/// the dictionary, `None` standing for any term
+pub fn save_lookups<E, K, V, W: AsRef<std::path::Path>>(
+    map: &E::MapStructure,
+    path: W,
+    lookup: &mut TripleLookup<V>,
+    time: usize,
+    // Each pattern as it was given, with its terms
+    patterns: &[(String, [Option<K>; 3])],
+) where
+    E: EncoderTrait<K, V>,
+    V: ExchangeData + Hashable + Copy + std::hash::Hash + std::fmt::Debug,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .expect("Something wrong happened with the ouput file");
+    for (pattern, [s, p, o]) in patterns {
+        let triples = lookup.lookup(map, (s.as_ref(), p.as_ref(), o.as_ref()), time);
+        let mut lines = format!("# {}: {} triples\n", pattern, triples.len());
+        for (s, p, o) in triples {
+            lines.push_str(&format!("{} {} {} .\n", s, p, o));
+        }
+        if let Err(e) = writeln!(file, "{}", lines) {
+            panic!("Couldn't write to file: {}", e);
+        }
+    }
+}
+
+/// Writes the solutions of `query` in `trace` at `time` as tab-separated values, with a header
+/// naming the selected variables. A solution is repeated as many times as it is derived, an
+/// unbound variable is an empty cell and the LIMIT of the query caps the number of rows.
+pub fn save_solutions<E, K, V, W: AsRef<std::path::Path>>(
+    map: &E::MapStructure,
+    path: W,
+    query: &CompiledQuery<V>,
+    trace: &mut TraceAgent<OrdKeySpine<Vec<Option<V>>, usize, isize>>,
+    time: usize,
+) where
+    E: EncoderTrait<K, V>,
+    V: Ord + Clone + std::hash::Hash + std::fmt::Debug + 'static,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .expect("Something wrong happened with the ouput file");
+    let header: Vec<String> = query
+        .variables()
+        .iter()
+        .map(|v| format!("?{}", v))
+        .collect();
+    let mut lines = header.join("\t");
+    lines.push('\n');
+    let limit = query.limit().unwrap_or(usize::MAX);
+    let mut rows = 0;
+    if let Some((mut cursor, storage)) = trace.cursor_through(&[time]) {
+        while let Some(solution) = cursor.get_key(&storage).filter(|_| rows < limit) {
+            let count = multiplicity(&mut cursor, &storage, time).max(0) as usize;
+            let row = solution
+                .iter()
+                .map(|value| {
+                    value.as_ref().map_or_else(String::new, |value| {
+                        map.get_left(value)
+                            .expect("Could not find the term")
+                            .to_string()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .join("\t");
+            for _ in 0..count.min(limit - rows) {
+                lines.push_str(&row);
+                lines.push('\n');
+            }
+            rows += count.min(limit - rows);
+            cursor.step_key(&storage);
+        }
+    }
+    if let Err(e) = write!(file, "{}", lines) {
+        panic!("Couldn't write to file: {}", e);
+    }
+}
+
+/// Appends the changes of the answer of a standing query before `time` to `file`, one line per
+/// added or retracted binding with the update it comes from, `+` or `-`, and the terms of the
+/// binding
+pub fn save_deltas<E, K, V>(
+    map: &E::MapStructure,
+    file: &mut std::fs::File,
+    deltas: &mut Deltas<V>,
+    time: usize,
+) where
+    E: EncoderTrait<K, V>,
+    V: Ord + Clone + std::hash::Hash + std::fmt::Debug,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    let mut lines = String::new();
+    deltas.deliver(time, |delta| {
+        let binding: Vec<String> = delta
+            .decode(map)
+            .iter()
+            .map(|term| term.to_string())
+            .collect();
+        let sign = if delta.diff > 0 { '+' } else { '-' };
+        for _ in 0..delta.diff.abs() {
+            lines.push_str(&format!(
+                "{}\t{}\t{}\n",
+                delta.time,
+                sign,
+                binding.join("\t")
+            ));
+        }
+    });
+    if let Err(e) = write!(file, "{}", lines) {
+        panic!("Couldn't write to file: {}", e);
+    }
+}
+
+/// Compares the materialization with the closure that `reference` computes from the base facts,
+/// both gathered in traces, and writes the triples that the materialization misses and the ones
+/// it has in excess. Returns the number of differences.
+pub fn verify_materialization<E, K, V, T, W: AsRef<std::path::Path>>(
+    map: &E::MapStructure,
+    path: W,
+    asserted: &mut TraceAgent<OrdKeySpine<T, usize, isize>>,
+    materialized: &mut TraceAgent<OrdKeySpine<T, usize, isize>>,
+    time: usize,
+    reference: impl FnOnce(HashSet<T>) -> Result<HashSet<T>, VocabularyError>,
+) -> Result<usize, VocabularyError>
+where
+    E: EncoderTrait<K, V>,
+    T: std::fmt::Debug + Clone + Ord + std::hash::Hash + 'static + Triple<V>,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Copy,
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+{
+    let collect = |trace: &mut TraceAgent<OrdKeySpine<T, usize, isize>>| {
+        let mut triples = HashSet::new();
+        if let Some((mut cursor, storage)) = trace.cursor_through(&[time]) {
+            while let Some(key) = cursor.get_key(&storage) {
+                if present(&mut cursor, &storage, time) {
+                    triples.insert(key.clone());
+                }
+                cursor.step_key(&storage);
+            }
+        }
+        triples
+    };
+    let expected = reference(collect(asserted))?;
+    let found = collect(materialized);
+
+    let mut missing: Vec<&T> = expected.difference(&found).collect();
+    let mut extra: Vec<&T> = found.difference(&expected).collect();
+    missing.sort();
+    extra.sort();
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .expect("Something wrong happened with the ouput file");
+    for (heading, triples) in [("missing", &missing), ("extra", &extra)] {
+        let mut lines = format!("# {} {} triples\n", triples.len(), heading);
+        for triple in triples.iter() {
+            lines.push_str(&decode_statement(map, *triple));
+            lines.push('\n');
+        }
+        if let Err(e) = writeln!(file, "{}", lines) {
+            panic!("Couldn't write to file: {}", e);
+        }
+    }
+
+    if missing.is_empty() && extra.is_empty() {
+        info!(
+            "Verified the materialization at time {}: {} triples",
+            time,
+            found.len()
+        );
+    } else {
+        warn!(
+            "The materialization at time {} differs from the recomputation: {} missing and {} \
+             extra triples",
+            time,
+            missing.len(),
+            extra.len()
+        );
+    }
+    Ok(missing.len() + extra.len())
+}
+
+// The triple written as in N-Triples, with the terms of the dictionary
+fn decode_statement<K, V, T, M>(map: &M, triple: &T) -> String
+where
+    K: std::cmp::Eq + std::hash::Hash + std::fmt::Display + std::fmt::Debug,
+    V: std::cmp::Eq + std::hash::Hash + std::fmt::Debug + Copy,
+    T: Triple<V>,
+    M: BiMapTrait<K, V>,
+{
+    let term = |term: &V| {
+        map.get_left(term)
+            .expect("Could not find the term")
+            .to_string()
+    };
+    format!(
+        "{} {} {} .",
+        term(&triple.s()),
+        term(&triple.p()),
+        term(&triple.o())
+    )
+}
+
+// N-Triples only allows IRIs and blank nodes as subjects: the generalized triples of the RDFS
+// profile whose subject is a literal stay in the materialization, but are not written
+fn writable(statement: &str) -> bool {
+    !statement.starts_with('"')
+}
+
+// The pattern of `--query`, its variables numbered in order of first appearance
+fn parse_query<'a, V, F>(pattern: &'a str, mut encode: F) -> Option<[Term<V>; 3]>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    let (s, p, o) = split_statement(pattern)?;
+    let mut variables: Vec<&str> = vec![];
+    let mut term = |term: &'a str| match term.strip_prefix('?') {
+        Some(name) => {
+            let position = variables.iter().position(|v| *v == name);
+            Some(Term::Variable(position.unwrap_or_else(|| {
+                variables.push(name);
+                variables.len() - 1
+            })))
+        }
+        None => encode(term).map(Term::Constant),
+    };
+    Some([term(s)?, term(p)?, term(o)?])
+}
+
+// The subject, predicate and object of a statement written as in N-Triples. The object is
+// everything after the predicate, without the final `.`, so that it can be a literal with spaces.
+fn split_statement(statement: &str) -> Option<(&str, &str, &str)> {
+    let statement = statement.trim();
+    let statement = statement.strip_suffix('.').unwrap_or(statement).trim_end();
+    let (s, rest) = statement.split_once(char::is_whitespace)?;
+    let (p, o) = rest.trim_start().split_once(char::is_whitespace)?;
+    Some((s, p, o.trim()))
+}
+
+// Whether the triple under the cursor is in the collection at `time`
+pub(crate) fn present<K, V, C: Cursor<K, V, usize, isize>>(
+    cursor: &mut C,
+    storage: &C::Storage,
+    time: usize,
+) -> bool {
+    multiplicity(cursor, storage, time) > 0
+}
+
+// How many times the record under the cursor is in the collection at `time`
+fn multiplicity<K, V, C: Cursor<K, V, usize, isize>>(
+    cursor: &mut C,
+    storage: &C::Storage,
+    time: usize,
+) -> isize {
+    let mut count = 0;
+    cursor.map_times(storage, |t, diff| {
+        if t.less_equal(&(time - 1)) {
+            count += diff;
+        }
+    });
+    count
+}
+
 /// Saves the fragment of the materialization related to a worker in a vector so that it can be joined to create
 /// the full file. TODO: IS THIS A LITTLE EXPENSIVE
 pub fn return_vector<E, K, V>(
-    trace: &mut TraceAgent<
-        OrdKeySpine<
-            <<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item,
-            usize,
-            isize,
-        >,
-    >,
+    trace: &mut TripleTrace<E::EncodedDataSet>,
     time: usize,
 ) -> Vec<<<E as encoder::EncoderTrait<K, V>>::EncodedDataSet as std::iter::IntoIterator>::Item>
 where
@@ -725,6 +2231,477 @@ where
 
 #[cfg(test)]
 mod tests {
+    use crate::encoder::{BiMapEncoder, BijectiveMap};
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::arrange::ArrangeBySelf;
+    use differential_dataflow::operators::consolidate::Consolidate;
+    use differential_dataflow::Collection;
+    use std::cell::RefCell;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use timely::communication::allocator::thread::Thread;
+    use timely::dataflow::operators::Probe;
+    use timely::dataflow::scopes::child::Child;
+    use timely::worker::Worker;
+
+    pub(crate) type TestTriple = (u64, u64, u64);
+    type Batch = Vec<(TestTriple, isize)>;
+
+    /// Feeds each batch of updates at its own time to `materialization` and returns the
+    /// materialized set after each batch.
+    pub(crate) fn materialize_batches<F>(
+        batches: Vec<Vec<(TestTriple, isize)>>,
+        materialization: F,
+    ) -> Vec<BTreeSet<TestTriple>>
+    where
+        F: for<'a> Fn(
+                &Collection<Child<'a, Worker<Thread>, usize>, TestTriple>,
+            ) -> Collection<Child<'a, Worker<Thread>, usize>, TestTriple>
+            + Send
+            + Sync
+            + 'static,
+    {
+        timely::execute_directly(move |worker| {
+            let updates = Rc::new(RefCell::new(vec![]));
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let sink = updates.clone();
+            let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, data) = scope.new_collection();
+                materialization(&data)
+                    .consolidate()
+                    .inspect(move |update| sink.borrow_mut().push(*update))
+                    .probe_with(&mut probe);
+                input
+            });
+
+            for (time, batch) in batches.iter().enumerate() {
+                for (triple, diff) in batch {
+                    input.update(*triple, *diff);
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+            }
+
+            let updates = updates.borrow();
+            (0..batches.len())
+                .map(|time| {
+                    let mut counts = std::collections::BTreeMap::new();
+                    for (triple, t, diff) in updates.iter() {
+                        if *t <= time {
+                            *counts.entry(*triple).or_insert(0) += diff;
+                        }
+                    }
+                    counts
+                        .into_iter()
+                        .filter(|(_, count)| *count > 0)
+                        .map(|(triple, _)| triple)
+                        .collect()
+                })
+                .collect()
+        })
+    }
+
+    /// The base facts after each batch of updates, as given to `materialize_set`
+    pub(crate) fn facts_after_batches(
+        batches: &[Vec<(TestTriple, isize)>],
+    ) -> Vec<HashSet<TestTriple>> {
+        let mut counts = std::collections::HashMap::new();
+        batches
+            .iter()
+            .map(|batch| {
+                for (triple, diff) in batch {
+                    *counts.entry(*triple).or_insert(0) += diff;
+                }
+                counts
+                    .iter()
+                    .filter(|(_, count)| **count > 0)
+                    .map(|(triple, _)| *triple)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Feeds each batch of updates of the materialization and of the asserted triples at its
+    /// own time, then writes the triples after the last batch as `mode` and returns the lines
+    /// of the file, sorted
+    pub(crate) fn written_through_trace(
+        terms: &[&str],
+        batches: Vec<(Batch, Batch)>,
+        mode: super::OutputMode,
+    ) -> Vec<String> {
+        let map = BijectiveMap::new(
+            terms
+                .iter()
+                .zip(0..)
+                .map(|(term, encoding)| (Arc::new(term.to_string()), encoding))
+                .collect(),
+        );
+        static WRITTEN: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "reasoning_service_{}_{}.{}",
+            std::process::id(),
+            WRITTEN.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            mode.extension()
+        ));
+        let written = path.clone();
+        timely::execute_directly(move |worker| {
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let (mut inputs, mut trace, mut asserted) = worker.dataflow::<usize, _, _>(|scope| {
+                let (materialized_input, materialized) = scope.new_collection();
+                let (asserted_input, asserted) = scope.new_collection();
+                let materialized = materialized.arrange_by_self();
+                let asserted = asserted.arrange_by_self();
+                materialized.stream.probe_with(&mut probe);
+                asserted.stream.probe_with(&mut probe);
+                (
+                    (materialized_input, asserted_input),
+                    materialized.trace,
+                    asserted.trace,
+                )
+            });
+            for (time, (materialized, asserted)) in batches.iter().enumerate() {
+                for (triple, diff) in materialized {
+                    inputs.0.update(*triple, *diff);
+                }
+                for (triple, diff) in asserted {
+                    inputs.1.update(*triple, *diff);
+                }
+                inputs.0.advance_to(time + 1);
+                inputs.1.advance_to(time + 1);
+                inputs.0.flush();
+                inputs.1.flush();
+                while probe.less_than(inputs.0.time()) {
+                    worker.step();
+                }
+            }
+            super::save_to_file_through_trace::<BiMapEncoder, _, _, _>(
+                &map,
+                &path,
+                &mut trace,
+                batches.len(),
+                None,
+                mode,
+                Some(&mut asserted),
+            );
+        });
+        let mut lines: Vec<String> = std::fs::read_to_string(&written)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        std::fs::remove_file(&written).unwrap();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn literal_subjects_are_not_written() {
+        let terms = [
+            "<a>",
+            "<p>",
+            "\"x\"",
+            "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>",
+            "<http://www.w3.org/2001/XMLSchema#string>",
+        ];
+        let statement = ((0, 1, 2), 1);
+        // rdfD1 types the literal
+        let typed = ((2, 3, 4), 1);
+        let lines = written_through_trace(
+            &terms,
+            vec![(vec![statement, typed], vec![statement])],
+            super::OutputMode::All,
+        );
+        assert_eq!(lines, vec!["<a> <p> \"x\" ."]);
+    }
+
+    #[test]
+    fn output_modes_split_asserted_and_inferred_triples() {
+        use super::OutputMode;
+        let terms = [
+            "<a>",
+            "<b>",
+            "<Student>",
+            "<Person>",
+            "<type>",
+            "<subClassOf>",
+        ];
+        let schema = ((2, 5, 3), 1);
+        let student = ((0, 4, 2), 1);
+        let person = ((0, 4, 3), 1);
+        // <b> is stated to be a person, and so is <a> until the statement is deleted, when it is
+        // still derived from <a> being a student
+        let stated = ((1, 4, 3), 1);
+        let batches = || {
+            vec![
+                (
+                    vec![schema, student, person, stated],
+                    vec![schema, student, person, stated],
+                ),
+                (vec![], vec![((0, 4, 3), -1)]),
+            ]
+        };
+        assert_eq!(
+            written_through_trace(&terms, batches(), OutputMode::Asserted),
+            vec![
+                "<Student> <subClassOf> <Person> .",
+                "<a> <type> <Student> .",
+                "<b> <type> <Person> .",
+            ]
+        );
+        assert_eq!(
+            written_through_trace(&terms, batches(), OutputMode::Inferred),
+            vec!["<a> <type> <Person> ."]
+        );
+        assert_eq!(
+            written_through_trace(&terms, batches(), OutputMode::Graphs),
+            vec![
+                format!(
+                    "<Student> <subClassOf> <Person> {} .",
+                    super::ASSERTED_GRAPH
+                ),
+                format!("<a> <type> <Person> {} .", super::INFERRED_GRAPH),
+                format!("<a> <type> <Student> {} .", super::ASSERTED_GRAPH),
+                format!("<b> <type> <Person> {} .", super::ASSERTED_GRAPH),
+            ]
+        );
+    }
+
+    #[test]
+    fn verification_agrees_with_the_rho_df_dataflow() {
+        use crate::model::{RDFS_RANGE, RDFS_SUB_CLASS_OF, RDF_TYPE};
+        use crate::reasoners::rho_df;
+        use crate::vocabulary::Vocabulary;
+
+        let terms = [
+            RDFS_SUB_CLASS_OF,
+            "<http://www.w3.org/2000/01/rdf-schema#subPropertyOf>",
+            RDF_TYPE,
+            "<http://www.w3.org/2000/01/rdf-schema#domain>",
+            RDFS_RANGE,
+            "<Student>",
+            "<Person>",
+            "<Course>",
+            "<Class>",
+            "<takes>",
+            "<x>",
+            "<y>",
+            "<z>",
+        ];
+        let (sco, typ, range) = (0, 2, 4);
+        let (student, person, course, class, takes) = (5, 6, 7, 8, 9);
+        let (x, y, z) = (10, 11, 12);
+        let encoding: HashMap<&str, u64> = terms.iter().zip(0..).map(|(t, e)| (*t, e)).collect();
+        let vocabulary =
+            Vocabulary::resolve(&rho_df::vocabulary(), |iri| encoding.get(iri).copied()).unwrap();
+        let map = BijectiveMap::new(
+            terms
+                .iter()
+                .zip(0..)
+                .map(|(term, encoding)| (Arc::new(term.to_string()), encoding))
+                .collect(),
+        );
+        // The range of rdf:type types the classes of the derived types too
+        let batches: Vec<Vec<(TestTriple, isize)>> = vec![
+            vec![
+                ((typ, range, class), 1),
+                ((takes, range, course), 1),
+                ((student, sco, person), 1),
+                ((x, typ, student), 1),
+                ((x, takes, y), 1),
+            ],
+            vec![
+                ((x, takes, y), -1),
+                ((z, takes, y), 1),
+                ((z, typ, person), 1),
+            ],
+        ];
+
+        // The closure the rules reach, applied as they are written until they derive nothing new
+        let fixpoint = rho_df::program(&vocabulary).unwrap();
+        let expected: Vec<HashSet<TestTriple>> = facts_after_batches(&batches)
+            .into_iter()
+            .map(|facts| fixpoint.materialize_set(facts))
+            .collect();
+        let terms_of_rules = vocabulary.clone();
+        let derived = materialize_batches(batches.clone(), move |data| {
+            rho_df::materialize(data, &terms_of_rules, Default::default()).unwrap()
+        });
+
+        let report = std::env::temp_dir().join(format!(
+            "reasoning_service_verification_{}.txt",
+            std::process::id()
+        ));
+        let written = report.clone();
+        let differences = timely::execute_directly(move |worker| {
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let reference = vocabulary.clone();
+            let (mut input, mut asserted, mut materialized) =
+                worker.dataflow::<usize, _, _>(|scope| {
+                    let (input, data) = scope.new_collection::<TestTriple, isize>();
+                    let asserted = data.arrange_by_self();
+                    let materialized = rho_df::materialize(&data, &vocabulary, Default::default())
+                        .unwrap()
+                        .arrange_by_self();
+                    asserted.stream.probe_with(&mut probe);
+                    materialized.stream.probe_with(&mut probe);
+                    (input, asserted.trace, materialized.trace)
+                });
+            let mut differences = vec![];
+            for (time, batch) in batches.iter().enumerate() {
+                for (triple, diff) in batch {
+                    input.update(*triple, *diff);
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+                differences.push(
+                    super::verify_materialization::<BiMapEncoder, _, _, _, _>(
+                        &map,
+                        &report,
+                        &mut asserted,
+                        &mut materialized,
+                        time + 1,
+                        |facts| Ok(rho_df::program(&reference)?.materialize_set(facts)),
+                    )
+                    .unwrap(),
+                );
+            }
+            differences
+        });
+        let report = std::fs::read_to_string(&written).unwrap();
+        std::fs::remove_file(&written).unwrap();
+
+        assert_eq!(differences, vec![0, 0]);
+        assert!(report.starts_with("# 0 missing triples"));
+        for (derived, expected) in derived.iter().zip(&expected) {
+            assert_eq!(derived.iter().copied().collect::<HashSet<_>>(), *expected);
+        }
+        // rule_6 types <y>, then the class of that type
+        assert!(expected[0].contains(&(y, typ, course)));
+        assert!(derived[0].contains(&(y, typ, course)));
+        assert!(derived[0].contains(&(student, typ, class)));
+        assert!(derived[0].contains(&(course, typ, class)));
+    }
+
+    #[test]
+    fn base_facts_ignore_duplicates_and_absent_deletions() {
+        let updates = timely::execute_directly(|worker| {
+            let updates = Rc::new(RefCell::new(vec![]));
+            let sink = updates.clone();
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, data) = scope.new_collection::<TestTriple, isize>();
+                data.consolidate()
+                    .inspect(move |update| sink.borrow_mut().push(*update))
+                    .probe_with(&mut probe);
+                input
+            });
+
+            let mut base_facts = super::BaseFacts::new();
+            let applied = [
+                base_facts.insert((1, 2, 3), "feed", &mut input),
+                base_facts.insert((1, 2, 3), "feed", &mut input),
+                base_facts.remove((4, 5, 6), None, &mut input),
+            ];
+            assert_eq!(applied, [true, false, false]);
+            input.advance_to(1);
+            base_facts.remove((1, 2, 3), None, &mut input);
+            assert!(!base_facts.remove((1, 2, 3), None, &mut input));
+            base_facts.insert((4, 5, 6), "feed", &mut input);
+            input.advance_to(2);
+            input.flush();
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+            assert_eq!(base_facts.len(), 1);
+
+            let mut updates = updates.borrow().clone();
+            updates.sort();
+            updates
+        });
+
+        assert_eq!(
+            updates,
+            vec![((1, 2, 3), 0, 1), ((1, 2, 3), 1, -1), ((4, 5, 6), 1, 1)]
+        );
+    }
+
+    #[test]
+    fn retracting_a_source_keeps_the_triples_of_the_others() {
+        let updates = timely::execute_directly(|worker| {
+            let updates = Rc::new(RefCell::new(vec![]));
+            let sink = updates.clone();
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, data) = scope.new_collection::<TestTriple, isize>();
+                data.consolidate()
+                    .inspect(move |update| sink.borrow_mut().push(*update))
+                    .probe_with(&mut probe);
+                input
+            });
+
+            let mut base_facts = super::BaseFacts::new();
+            base_facts.insert((1, 1, 1), "a", &mut input);
+            base_facts.insert((2, 2, 2), "a", &mut input);
+            base_facts.insert((2, 2, 2), "b", &mut input);
+            base_facts.insert((3, 3, 3), "b", &mut input);
+            input.advance_to(1);
+            // Withdrawing the claim of one source leaves the triple asserted by the other
+            assert!(base_facts.insert((3, 3, 3), "c", &mut input));
+            assert!(base_facts.remove((3, 3, 3), Some("c"), &mut input));
+            assert!(!base_facts.remove((3, 3, 3), Some("a"), &mut input));
+            assert_eq!(base_facts.retract("a", &mut input), (1, 1));
+            assert_eq!(base_facts.retract("a", &mut input), (0, 0));
+            assert_eq!(base_facts.sources(&(2, 2, 2)), vec!["b"]);
+            assert!(base_facts.sources(&(1, 1, 1)).is_empty());
+            input.advance_to(2);
+            input.flush();
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+
+            let mut updates = updates.borrow().clone();
+            updates.sort();
+            updates
+        });
+
+        assert_eq!(
+            updates,
+            vec![
+                ((1, 1, 1), 0, 1),
+                ((1, 1, 1), 1, -1),
+                ((2, 2, 2), 0, 1),
+                ((3, 3, 3), 0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_updates_with_sources() {
+        use super::{parse_update, IncrementalMode, Update};
+
+        match parse_update("data/feed.nt=insert_abox@upstream").unwrap() {
+            Update::File {
+                mode: IncrementalMode::Addition,
+                source,
+                ..
+            } => assert_eq!(source.as_deref(), Some("upstream")),
+            update => panic!("Unexpected update {:?}", update),
+        }
+        match parse_update("data/feed.nt=d_t").unwrap() {
+            update @ Update::File { source: None, .. } => assert_eq!(update.name(), "feed"),
+            update => panic!("Unexpected update {:?}", update),
+        }
+        let retraction = parse_update("upstream=retract").unwrap();
+        assert_eq!(retraction.name(), "retract_upstream");
+        assert!(parse_update("data/feed.nt=insert").is_err());
+    }
 
     #[test]
     fn it_works() {
@@ -0,0 +1,268 @@
+//! Lookups of triple patterns in the materialization, without walking all of it.
+//!
+//! The triples are arranged three times, by subject with their predicate and object (SPO), by
+//! predicate with their object and subject (POS) and by object with their subject and predicate
+//! (OSP). Every pattern binds a prefix of one of the three orders, so a lookup seeks the key of
+//! its first bound term and only reads the triples under it. Only the pattern without bound
+//! terms reads the whole materialization.
+//!
+//! The arrangements are spread over the workers by the hash of their key, like the ones of the
+//! dataflow: the answer to a pattern is the union of the lookups of every worker, and a pattern
+//! with a bound term is answered by the worker its key hashes to.
+use crate::encoder::{BiMapTrait, Triple};
+use crate::present;
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::operators::arrange::{ArrangeByKey, TraceAgent};
+use differential_dataflow::trace::implementations::ord::OrdValSpine;
+use differential_dataflow::trace::{cursor::Cursor, TraceReader};
+use differential_dataflow::{Collection, ExchangeData};
+use timely::dataflow::operators::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+
+/// Triples arranged by one of their terms, with the other two in the order of the arrangement
+pub type OrderTrace<V> = TraceAgent<OrdValSpine<V, (V, V), usize, isize>>;
+
+/// A triple pattern, `None` standing for any term
+pub type Pattern<V> = (Option<V>, Option<V>, Option<V>);
+
+/// The SPO, POS and OSP arrangements of a collection of triples
+pub struct TripleLookup<V>
+where
+    V: Ord + Clone + 'static,
+{
+    spo: OrderTrace<V>,
+    pos: OrderTrace<V>,
+    osp: OrderTrace<V>,
+}
+
+impl<V> TripleLookup<V>
+where
+    V: ExchangeData + Hashable + Copy,
+{
+    /// Arranges the triples of `triples` in the three orders
+    pub fn new<G, T>(triples: &Collection<G, T>, probe: &mut ProbeHandle<usize>) -> Self
+    where
+        G: Scope<Timestamp = usize>,
+        T: ExchangeData + Triple<V>,
+    {
+        let mut arrange = |ordered: Collection<G, (V, (V, V))>| {
+            let arranged = ordered.arrange_by_key();
+            arranged.stream.probe_with(probe);
+            arranged.trace
+        };
+        TripleLookup {
+            spo: arrange(triples.map(|triple| (*triple.s(), (*triple.p(), *triple.o())))),
+            pos: arrange(triples.map(|triple| (*triple.p(), (*triple.o(), *triple.s())))),
+            osp: arrange(triples.map(|triple| (*triple.o(), (*triple.s(), *triple.p())))),
+        }
+    }
+
+    /// The triples this worker holds that match the encoded pattern at `time`, as subject,
+    /// predicate and object. As with the other readers of the traces, the triples at `time` are
+    /// the ones of the updates before it.
+    pub fn matching(&mut self, pattern: Pattern<V>, time: usize) -> Vec<(V, V, V)> {
+        match pattern {
+            // SPO: the subject, then the predicate, then the object
+            (Some(s), p, o) if p.is_some() || o.is_none() => {
+                scan(&mut self.spo, Some(s), p, o, time)
+                    .into_iter()
+                    .map(|(s, (p, o))| (s, p, o))
+                    .collect()
+            }
+            // OSP: the object, then the subject
+            (s, None, Some(o)) => scan(&mut self.osp, Some(o), s, None, time)
+                .into_iter()
+                .map(|(o, (s, p))| (s, p, o))
+                .collect(),
+            // POS: the predicate, then the object
+            (None, Some(p), o) => scan(&mut self.pos, Some(p), o, None, time)
+                .into_iter()
+                .map(|(p, (o, s))| (s, p, o))
+                .collect(),
+            (s, p, o) => scan(&mut self.spo, s, p, o, time)
+                .into_iter()
+                .map(|(s, (p, o))| (s, p, o))
+                .collect(),
+        }
+    }
+
+    /// The triples this worker holds that match the pattern at `time`, with the terms of the
+    /// dictionary. A term of the pattern that is not in the dictionary matches nothing.
+    pub fn lookup<'m, K, M>(
+        &mut self,
+        map: &'m M,
+        pattern: (Option<&K>, Option<&K>, Option<&K>),
+        time: usize,
+    ) -> Vec<(&'m K, &'m K, &'m K)>
+    where
+        K: Eq + std::hash::Hash + std::fmt::Debug,
+        V: std::hash::Hash + std::fmt::Debug,
+        M: BiMapTrait<K, V>,
+    {
+        let encode = |term: Option<&K>| match term {
+            Some(term) => map.get_right(term).map(|encoded| Some(*encoded)),
+            None => Some(None),
+        };
+        let encoded = match (encode(pattern.0), encode(pattern.1), encode(pattern.2)) {
+            (Some(s), Some(p), Some(o)) => (s, p, o),
+            _ => return vec![],
+        };
+        let decode = |term: &V| map.get_left(term).expect("Could not find the term");
+        self.matching(encoded, time)
+            .iter()
+            .map(|(s, p, o)| (decode(s), decode(p), decode(o)))
+            .collect()
+    }
+}
+
+// The entries of the trace whose key is `key` and whose values start with `first` and `second`,
+// the terms that are `None` matching any. Only the values under `key` are read when it is bound.
+fn scan<V>(
+    trace: &mut OrderTrace<V>,
+    key: Option<V>,
+    first: Option<V>,
+    second: Option<V>,
+    time: usize,
+) -> Vec<(V, (V, V))>
+where
+    V: Ord + Clone + 'static,
+{
+    let mut found = vec![];
+    let (mut cursor, storage) = match trace.cursor_through(&[time]) {
+        Some(cursor) => cursor,
+        None => return found,
+    };
+    if let Some(key) = key.as_ref() {
+        cursor.seek_key(&storage, key);
+    }
+    while let Some(current) = cursor.get_key(&storage) {
+        if key.as_ref().is_some_and(|key| key != current) {
+            break;
+        }
+        if let (Some(first), Some(second)) = (first.as_ref(), second.as_ref()) {
+            cursor.seek_val(&storage, &(first.clone(), second.clone()));
+        }
+        while let Some((a, b)) = cursor.get_val(&storage) {
+            // The values are sorted, so the ones with the first term have all been read
+            if first.as_ref().is_some_and(|first| first < a) {
+                break;
+            }
+            let matches = first.as_ref().is_none_or(|first| first == a)
+                && second.as_ref().is_none_or(|second| second == b);
+            if matches && present(&mut cursor, &storage, time) {
+                found.push((current.clone(), (a.clone(), b.clone())));
+            }
+            cursor.step_val(&storage);
+        }
+        if key.is_some() {
+            break;
+        }
+        cursor.step_key(&storage);
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TripleLookup;
+    use crate::encoder::BijectiveMap;
+    use bimap::BiMap;
+    use differential_dataflow::input::Input;
+
+    #[test]
+    fn looks_up_every_pattern_through_updates() {
+        let (a, b, c, knows, likes) = (0u64, 1, 2, 3, 4);
+        let batches = [
+            vec![
+                ((a, knows, b), 1),
+                ((a, knows, c), 1),
+                ((a, likes, b), 1),
+                ((b, knows, c), 1),
+                ((c, likes, a), 1),
+            ],
+            vec![((a, knows, c), -1), ((b, likes, b), 1)],
+        ];
+        let mut bimap = BiMap::new();
+        let terms = [
+            ("<a>", a),
+            ("<b>", b),
+            ("<c>", c),
+            ("<knows>", knows),
+            ("<likes>", likes),
+        ];
+        for (term, encoded) in terms {
+            bimap.insert(term.to_string(), encoded);
+        }
+        let map = BijectiveMap::new(bimap);
+
+        timely::execute_directly(move |worker| {
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let (mut input, mut lookup) = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, triples) = scope.new_collection::<(u64, u64, u64), isize>();
+                (input, TripleLookup::new(&triples, &mut probe))
+            });
+            for (time, batch) in batches.iter().enumerate() {
+                for (triple, diff) in batch {
+                    input.update(*triple, *diff);
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+            }
+
+            let mut matching = |pattern, time| {
+                let mut found = lookup.matching(pattern, time);
+                found.sort();
+                found
+            };
+            assert_eq!(
+                matching((Some(a), None, None), 1),
+                vec![(a, knows, b), (a, knows, c), (a, likes, b)]
+            );
+            assert_eq!(
+                matching((Some(a), None, None), 2),
+                vec![(a, knows, b), (a, likes, b)]
+            );
+            assert_eq!(
+                matching((Some(a), Some(knows), None), 1),
+                vec![(a, knows, b), (a, knows, c)]
+            );
+            assert_eq!(
+                matching((Some(a), None, Some(b)), 2),
+                vec![(a, knows, b), (a, likes, b)]
+            );
+            assert_eq!(
+                matching((None, Some(likes), Some(b)), 2),
+                vec![(a, likes, b), (b, likes, b)]
+            );
+            assert_eq!(
+                matching((None, Some(knows), None), 2),
+                vec![(a, knows, b), (b, knows, c)]
+            );
+            assert_eq!(
+                matching((None, None, Some(c)), 1),
+                vec![(a, knows, c), (b, knows, c)]
+            );
+            assert_eq!(matching((Some(b), Some(likes), Some(b)), 1), vec![]);
+            assert_eq!(
+                matching((Some(b), Some(likes), Some(b)), 2),
+                vec![(b, likes, b)]
+            );
+            assert_eq!(matching((None, None, None), 2).len(), 5);
+
+            let (knows, x) = ("<knows>".to_string(), "<x>".to_string());
+            let mut found = lookup.lookup(&map, (None, Some(&knows), None), 2);
+            found.sort();
+            assert_eq!(
+                found,
+                vec![
+                    (&"<a>".to_string(), &knows, &"<b>".to_string()),
+                    (&"<b>".to_string(), &knows, &"<c>".to_string())
+                ]
+            );
+            assert!(lookup.lookup(&map, (Some(&x), None, None), 2).is_empty());
+        });
+    }
+}
@@ -25,6 +25,148 @@ pub static RDFS_DOMAIN: &str = "<http://www.w3.org/2000/01/rdf-schema#domain>";
 pub static RDFS_RANGE: &str = "<http://www.w3.org/2000/01/rdf-schema#range>";
 /// URI of rdf:type
 pub static RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+/// URI of rdf:Property
+pub static RDF_PROPERTY: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#Property>";
+/// URI of rdf:langString, the datatype of the literals with a language tag
+pub static RDF_LANG_STRING: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#langString>";
+/// Prefix of the container membership properties rdf:_1, rdf:_2, ...
+pub static RDF_CONTAINER_MEMBERSHIP_PREFIX: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#_";
+/// URI of rdfs:Resource
+pub static RDFS_RESOURCE: &str = "<http://www.w3.org/2000/01/rdf-schema#Resource>";
+/// URI of rdfs:Class
+pub static RDFS_CLASS: &str = "<http://www.w3.org/2000/01/rdf-schema#Class>";
+/// URI of rdfs:Literal
+pub static RDFS_LITERAL: &str = "<http://www.w3.org/2000/01/rdf-schema#Literal>";
+/// URI of rdfs:Datatype
+pub static RDFS_DATATYPE: &str = "<http://www.w3.org/2000/01/rdf-schema#Datatype>";
+/// URI of rdfs:ContainerMembershipProperty
+pub static RDFS_CONTAINER_MEMBERSHIP_PROPERTY: &str =
+    "<http://www.w3.org/2000/01/rdf-schema#ContainerMembershipProperty>";
+/// URI of rdfs:member
+pub static RDFS_MEMBER: &str = "<http://www.w3.org/2000/01/rdf-schema#member>";
+/// URI of rdf:first
+pub static RDF_FIRST: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#first>";
+/// URI of rdf:rest
+pub static RDF_REST: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#rest>";
+/// URI of rdf:nil
+pub static RDF_NIL: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#nil>";
+/// URI of owl:Thing
+pub static OWL_THING: &str = "<http://www.w3.org/2002/07/owl#Thing>";
+/// URI of owl:sameAs
+pub static OWL_SAME_AS: &str = "<http://www.w3.org/2002/07/owl#sameAs>";
+/// URI of owl:inverseOf
+pub static OWL_INVERSE_OF: &str = "<http://www.w3.org/2002/07/owl#inverseOf>";
+/// URI of owl:TransitiveProperty
+pub static OWL_TRANSITIVE_PROPERTY: &str = "<http://www.w3.org/2002/07/owl#TransitiveProperty>";
+/// URI of owl:SymmetricProperty
+pub static OWL_SYMMETRIC_PROPERTY: &str = "<http://www.w3.org/2002/07/owl#SymmetricProperty>";
+/// URI of owl:equivalentClass
+pub static OWL_EQUIVALENT_CLASS: &str = "<http://www.w3.org/2002/07/owl#equivalentClass>";
+/// URI of owl:equivalentProperty
+pub static OWL_EQUIVALENT_PROPERTY: &str = "<http://www.w3.org/2002/07/owl#equivalentProperty>";
+/// URI of owl:intersectionOf
+pub static OWL_INTERSECTION_OF: &str = "<http://www.w3.org/2002/07/owl#intersectionOf>";
+/// URI of owl:someValuesFrom
+pub static OWL_SOME_VALUES_FROM: &str = "<http://www.w3.org/2002/07/owl#someValuesFrom>";
+/// URI of owl:onProperty
+pub static OWL_ON_PROPERTY: &str = "<http://www.w3.org/2002/07/owl#onProperty>";
+/// URI of owl:propertyChainAxiom
+pub static OWL_PROPERTY_CHAIN_AXIOM: &str = "<http://www.w3.org/2002/07/owl#propertyChainAxiom>";
+/// URI of owl:maxCardinality
+pub static OWL_MAX_CARDINALITY: &str = "<http://www.w3.org/2002/07/owl#maxCardinality>";
+/// URI of owl:cardinality
+pub static OWL_CARDINALITY: &str = "<http://www.w3.org/2002/07/owl#cardinality>";
+/// URI of owl:Nothing, the empty class: an individual in it makes the data inconsistent
+pub static OWL_NOTHING: &str = "<http://www.w3.org/2002/07/owl#Nothing>";
+/// URI of xsd:string, the datatype of the literals with neither datatype nor language tag
+pub static XSD_STRING: &str = "<http://www.w3.org/2001/XMLSchema#string>";
+/// URI of xsd:integer
+pub static XSD_INTEGER: &str = "<http://www.w3.org/2001/XMLSchema#integer>";
+/// URI of xsd:decimal
+pub static XSD_DECIMAL: &str = "<http://www.w3.org/2001/XMLSchema#decimal>";
+
+/// Whether the term is one of the container membership properties rdf:_1, rdf:_2, ...
+pub fn is_container_membership_property(term: &str) -> bool {
+    term.strip_prefix(RDF_CONTAINER_MEMBERSHIP_PREFIX)
+        .and_then(|rest| rest.strip_suffix('>'))
+        .is_some_and(|index| {
+            !index.is_empty()
+                && !index.starts_with('0')
+                && index.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+// Local names of the XSD datatypes whose values are numbers
+const XSD_NUMERIC_TYPES: [&str; 16] = [
+    "integer",
+    "decimal",
+    "double",
+    "float",
+    "int",
+    "long",
+    "short",
+    "byte",
+    "nonNegativeInteger",
+    "positiveInteger",
+    "negativeInteger",
+    "nonPositiveInteger",
+    "unsignedLong",
+    "unsignedInt",
+    "unsignedShort",
+    "unsignedByte",
+];
+
+/// An RDF literal, split in its components
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct Literal {
+    /// lexical form, without the quotes
+    pub lexical: String,
+    /// IRI of the datatype
+    pub datatype: String,
+    /// language tag, if any
+    pub language: Option<String>,
+}
+
+impl Literal {
+    /// Splits a literal written as in the N-Triples data (`"1"^^<...>`, `"chat"@fr`, `"x"`).
+    /// Returns `None` if the term is not a literal.
+    pub fn parse(term: &str) -> Option<Self> {
+        let rest = term.strip_prefix('"')?;
+        let end = rest.rfind('"')?;
+        let (lexical, suffix) = (&rest[..end], &rest[end + 1..]);
+        let (datatype, language) = if suffix.is_empty() {
+            (XSD_STRING.to_string(), None)
+        } else if let Some(language) = suffix.strip_prefix('@') {
+            (RDF_LANG_STRING.to_string(), Some(language.to_string()))
+        } else {
+            (suffix.strip_prefix("^^")?.to_string(), None)
+        };
+        Some(Literal {
+            lexical: lexical.to_string(),
+            datatype,
+            language,
+        })
+    }
+
+    /// The value of the literal if its datatype is one of the XSD numeric types and its lexical
+    /// form is valid
+    pub fn as_number(&self) -> Option<f64> {
+        let local = self
+            .datatype
+            .strip_prefix("<http://www.w3.org/2001/XMLSchema#")?
+            .strip_suffix('>')?;
+        if XSD_NUMERIC_TYPES.contains(&local) {
+            self.lexical.trim().parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Whether the literal is a plain string, with or without a language tag
+    pub fn is_string(&self) -> bool {
+        self.datatype == XSD_STRING || self.datatype == RDF_LANG_STRING
+    }
+}
 
 impl Triple {
     /// Prints only the local name with no namespace, just for easy reading
@@ -72,30 +214,187 @@ impl fmt::Display for Triple {
 
 /// This struct represents a rule for our specific problem:
 /// it has a head, that is a single literal and it has a
-/// body that is two literals
-#[derive(PartialEq, Eq, Hash, Debug)]
+/// body that is a conjunction of one or more literals, possibly
+/// restricted by negated literals and builtin filters
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct CustomRule {
     /// head of the rule
     pub head: CustomLiteral,
     /// body of the rule
-    pub body: [CustomLiteral; 2],
+    pub body: Vec<CustomLiteral>,
+    /// literals that must not hold, negation as failure
+    pub negated: Vec<CustomLiteral>,
+    /// conditions on the values bound by the body
+    pub filters: Vec<CustomBuiltin>,
+    /// conditions on aggregates of the values bound by the body
+    pub aggregates: Vec<CustomAggregate>,
 }
 
 impl std::fmt::Display for CustomRule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "T({}) <= T({}),T({})",
-            self.head, self.body[0], self.body[1]
+        write!(f, "T({}) <=", self.head)?;
+        let body = self.body.iter().map(|literal| format!("T({})", literal));
+        let negated = self
+            .negated
+            .iter()
+            .map(|literal| format!("not T({})", literal));
+        let filters = self.filters.iter().map(|filter| filter.to_string());
+        let aggregates = self
+            .aggregates
+            .iter()
+            .map(|aggregate| aggregate.to_string());
+        for (i, item) in body
+            .chain(negated)
+            .chain(filters)
+            .chain(aggregates)
+            .enumerate()
+        {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, " {}", item)?;
+        }
+        Ok(())
+    }
+}
+
+/// A builtin predicate applied to two terms of a rule, e.g. `?age >= 18` or
+/// `contains(?name, "Smith")`
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct CustomBuiltin {
+    /// the predicate
+    pub predicate: BuiltinPredicate,
+    /// the two arguments of the predicate
+    pub arguments: [PossibleTerm; 2],
+}
+
+impl std::fmt::Display for CustomBuiltin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [left, right] = &self.arguments;
+        if self.predicate.is_comparison() {
+            write!(f, "{} {} {}", left, self.predicate, right)
+        } else {
+            write!(f, "{}({}, {})", self.predicate, left, right)
+        }
+    }
+}
+
+/// A comparison between an aggregate and a term of a rule, e.g.
+/// `count(?m : T(?m, <http://example.org/memberOf>, ?d)) > 50`. The aggregate is computed over
+/// the bindings of its literals, grouped by the variables they share with the rest of the rule.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct CustomAggregate {
+    /// the aggregate function
+    pub function: AggregateFunction,
+    /// the aggregated variable
+    pub variable: String,
+    /// the literals the aggregated values come from
+    pub body: Vec<CustomLiteral>,
+    /// the comparison applied to the aggregate
+    pub predicate: BuiltinPredicate,
+    /// the term the aggregate is compared to
+    pub bound: PossibleTerm,
+}
+
+impl std::fmt::Display for CustomAggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(?{} :", self.function, self.variable)?;
+        for (i, literal) in self.body.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, " T({})", literal)?;
+        }
+        write!(f, ") {} {}", self.predicate, self.bound)
+    }
+}
+
+/// The aggregate functions of the rule language
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum AggregateFunction {
+    /// number of bindings
+    Count,
+    /// smallest numeric value
+    Min,
+    /// largest numeric value
+    Max,
+    /// sum of the numeric values
+    Sum,
+}
+
+impl std::fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Sum => "sum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The builtin predicates of the rule language
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum BuiltinPredicate {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `contains(string, substring)`
+    Contains,
+    /// `strstarts(string, prefix)`
+    StartsWith,
+    /// `strends(string, suffix)`
+    EndsWith,
+    /// `regex(string, pattern)`
+    Regex,
+}
+
+impl BuiltinPredicate {
+    /// Whether the predicate is written infix
+    pub fn is_comparison(&self) -> bool {
+        !matches!(
+            self,
+            BuiltinPredicate::Contains
+                | BuiltinPredicate::StartsWith
+                | BuiltinPredicate::EndsWith
+                | BuiltinPredicate::Regex
         )
     }
 }
 
+impl std::fmt::Display for BuiltinPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BuiltinPredicate::Equal => "=",
+            BuiltinPredicate::NotEqual => "!=",
+            BuiltinPredicate::Less => "<",
+            BuiltinPredicate::LessOrEqual => "<=",
+            BuiltinPredicate::Greater => ">",
+            BuiltinPredicate::GreaterOrEqual => ">=",
+            BuiltinPredicate::Contains => "contains",
+            BuiltinPredicate::StartsWith => "strstarts",
+            BuiltinPredicate::EndsWith => "strends",
+            BuiltinPredicate::Regex => "regex",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
 /// This struct represents a literal for our specific problem,
 /// so no predicate is needed as the only predicate in our case is the
 /// belonging to the data set
 /// Our literals will have one predicate and a vector of three terms
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct CustomLiteral {
     // for now I'm going to model all of this with strings
     /// terms of the literal
@@ -111,32 +410,32 @@ impl std::fmt::Display for CustomLiteral {
         )
     }
 }
-/// a constant value is an alias for a string for now
+/// a constant value is an alias for a string for now: the IRI (or the literal) exactly as it
+/// appears in the N-Triples data, e.g. `<http://www.w3.org/2002/07/owl#sameAs>`
 pub type ConstantValue = String;
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 /// Possible terms
 pub enum PossibleTerm {
     /// A variable
     LiteralVariable(String),
     /// A rho-df property
     RhoDFProperty(RhoDFWord),
-    // We don't have constant values in our case..
     /// constant value
-    ConstantValue,
+    ConstantValue(ConstantValue),
 }
 
 impl std::fmt::Display for PossibleTerm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PossibleTerm::LiteralVariable(s) => write!(f, "{}", s),
+            PossibleTerm::LiteralVariable(s) => write!(f, "?{}", s),
             PossibleTerm::RhoDFProperty(r) => write!(f, "{}", r),
-            PossibleTerm::ConstantValue => write!(f, "{}", self),
+            PossibleTerm::ConstantValue(c) => write!(f, "{}", c),
         }
     }
 }
 
 /// Words in rho-df. Maybe better if they were integer ids.
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum RhoDFWord {
     /// rdfs:subPropertyOf
     SPO,
@@ -150,6 +449,19 @@ pub enum RhoDFWord {
     RANGE,
 }
 
+impl RhoDFWord {
+    /// The IRI the keyword stands for
+    pub fn iri(&self) -> &'static str {
+        match self {
+            RhoDFWord::SCO => RDFS_SUB_CLASS_OF,
+            RhoDFWord::SPO => RDFS_SUB_PROPERTY_OF,
+            RhoDFWord::TYPE => RDF_TYPE,
+            RhoDFWord::DOMAIN => RDFS_DOMAIN,
+            RhoDFWord::RANGE => RDFS_RANGE,
+        }
+    }
+}
+
 impl std::fmt::Display for RhoDFWord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
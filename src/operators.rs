@@ -0,0 +1,455 @@
+//! Dataflow operators shared by the reasoners
+use crate::encoder::Triple;
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::{Arrange, ArrangeByKey, Arranged, TraceAgent};
+use differential_dataflow::operators::join::{Join, JoinCore};
+use differential_dataflow::operators::reduce::{Count, Threshold};
+use differential_dataflow::trace::implementations::ord::{OrdKeySpine, OrdValSpine};
+use differential_dataflow::{AsCollection, Collection, Data, ExchangeData};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Broadcast, Partition};
+use timely::dataflow::scopes::ScopeParent;
+use timely::dataflow::Scope;
+
+/// Triples arranged by one of their terms
+pub type TripleArrangement<G, V, T> =
+    Arranged<G, TraceAgent<OrdValSpine<V, T, <G as ScopeParent>::Timestamp, isize>>>;
+
+/// Pairs arranged by their key on the worker that holds them
+pub type LocalIndex<G, K, V> =
+    Arranged<G, TraceAgent<OrdValSpine<K, V, <G as ScopeParent>::Timestamp, isize>>>;
+
+/// A collection of triples arranged by subject, by predicate and by object.
+///
+/// The three arrangements are built once and shared by every rule that joins on one of the
+/// positions: a rule that only needs the triples of a given predicate takes a filtered view of
+/// one of them instead of filtering and re-keying the collection on its own.
+pub struct TripleIndex<G, V, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData,
+    T: ExchangeData,
+{
+    /// The triples keyed by their subject
+    pub by_subject: TripleArrangement<G, V, T>,
+    /// The triples keyed by their predicate
+    pub by_predicate: TripleArrangement<G, V, T>,
+    /// The triples keyed by their object
+    pub by_object: TripleArrangement<G, V, T>,
+}
+
+impl<G, V, T> TripleIndex<G, V, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    /// Arranges the triples of the collection by each of their terms
+    pub fn new(triples: &Collection<G, T>) -> Self {
+        TripleIndex {
+            by_subject: triples.map(|triple| (*triple.s(), triple)).arrange_by_key(),
+            by_predicate: triples.map(|triple| (*triple.p(), triple)).arrange_by_key(),
+            by_object: triples.map(|triple| (*triple.o(), triple)).arrange_by_key(),
+        }
+    }
+}
+
+/// Set semantics over a collection that might contain removals.
+pub trait SetSemantics<G: Scope, D> {
+    /// A distinct that keeps the removals as -1 instead of turning them into additions: every
+    /// positive multiplicity becomes 1 and every negative one becomes -1.
+    fn set_semantics(&self) -> Collection<G, D>;
+}
+
+impl<G, D> SetSemantics<G, D> for Collection<G, D>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    D: ExchangeData + Hashable,
+{
+    fn set_semantics(&self) -> Collection<G, D> {
+        //  VERY IMPORTANT: THE DISTINCT PUTS THE REMOVAL INTO ADDITION
+        // SO WE REWRITE THE DISTINCT TO KEEP THE REMOVAL -1
+        self.threshold(|_, c| {
+            if c > &0 {
+                1
+            } else if c < &0 {
+                -1
+            } else {
+                0
+            }
+        })
+    }
+}
+
+/// The triples of a collection split by predicate, so that a rule only reads the triples of the
+/// properties it mentions instead of filtering the whole collection.
+///
+/// The split is done by a single operator, in one partition per property known beforehand and
+/// one partition per pair of property and object asked for, such as the types of a class. The
+/// triples of the other properties are only found in [`all`](Partitions::all).
+pub struct Partitions<G, V, T>
+where
+    G: Scope,
+{
+    triples: Collection<G, T>,
+    by_predicate: HashMap<V, Collection<G, T>>,
+    by_predicate_object: HashMap<(V, V), Collection<G, T>>,
+}
+
+impl<G, V, T> Partitions<G, V, T>
+where
+    G: Scope,
+    V: ExchangeData + Hash,
+    T: ExchangeData + Triple<V>,
+{
+    /// A single partition holding every triple
+    pub fn unpartitioned(triples: &Collection<G, T>) -> Self {
+        Partitions {
+            triples: triples.clone(),
+            by_predicate: HashMap::new(),
+            by_predicate_object: HashMap::new(),
+        }
+    }
+
+    /// Splits the triples in one partition per property of `properties`, the triples whose
+    /// predicate and object are one of `pairs` going to a partition of their own
+    pub fn new(triples: &Collection<G, T>, properties: &[V], pairs: &[(V, V)]) -> Self {
+        let mut keys: Vec<(V, Option<V>)> = properties.iter().map(|p| (p.clone(), None)).collect();
+        keys.extend(pairs.iter().map(|(p, o)| (p.clone(), Some(o.clone()))));
+        let positions: HashMap<(V, Option<V>), u64> = keys
+            .iter()
+            .enumerate()
+            .map(|(position, key)| (key.clone(), position as u64))
+            .collect();
+        let rest = keys.len() as u64;
+
+        let mut streams = triples
+            .inner
+            .partition(rest + 1, move |(triple, time, diff)| {
+                let position = positions
+                    .get(&((*triple.p()).clone(), Some((*triple.o()).clone())))
+                    .or_else(|| positions.get(&((*triple.p()).clone(), None)))
+                    .copied()
+                    .unwrap_or(rest);
+                (position, (triple, time, diff))
+            })
+            .into_iter()
+            .map(|stream| stream.as_collection());
+
+        // The properties come first: a pair only joins the partition of its property when the
+        // property has one, otherwise the other triples of the property are in none
+        let mut by_predicate: HashMap<V, Collection<G, T>> = HashMap::new();
+        let mut by_predicate_object = HashMap::new();
+        for (predicate, object) in keys {
+            let partition = streams.next().expect("One stream per partition");
+            match object {
+                None => {
+                    by_predicate.insert(predicate, partition);
+                }
+                Some(object) => {
+                    if let Some(others) = by_predicate.get_mut(&predicate) {
+                        *others = others.concat(&partition);
+                    }
+                    by_predicate_object.insert((predicate, object), partition);
+                }
+            }
+        }
+
+        Partitions {
+            triples: triples.clone(),
+            by_predicate,
+            by_predicate_object,
+        }
+    }
+
+    /// Every triple
+    pub fn all(&self) -> &Collection<G, T> {
+        &self.triples
+    }
+
+    /// A collection holding at least the triples with `predicate` and, if given, `object`: the
+    /// smallest partition that contains them, or every triple if none does.
+    pub fn matching(&self, predicate: &V, object: Option<&V>) -> &Collection<G, T> {
+        object
+            .and_then(|object| {
+                self.by_predicate_object
+                    .get(&(predicate.clone(), object.clone()))
+            })
+            .or_else(|| self.by_predicate.get(predicate))
+            .unwrap_or(&self.triples)
+    }
+}
+
+/// Indexes that are read without exchanging data between workers.
+///
+/// Joining two arrangements built by this trait only matches the records each worker holds:
+/// when one of the two sides is [replicated](Local::replicate), every worker holds all of it
+/// and the join behaves as a local lookup of the records the worker already has.
+pub trait Local<G, K, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    /// Sends every pair to every worker and indexes them by key. Meant for small collections,
+    /// such as the closure of a schema, that every worker needs in full.
+    fn replicate(&self, name: &str) -> LocalIndex<G, K, V>;
+    /// Indexes by key the pairs the worker already holds, without exchanging them
+    fn arrange_locally(&self, name: &str) -> LocalIndex<G, K, V>;
+}
+
+impl<G, K, V> Local<G, K, V> for Collection<G, (K, V)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    K: ExchangeData + Hashable,
+    V: ExchangeData,
+{
+    fn replicate(&self, name: &str) -> LocalIndex<G, K, V> {
+        self.inner.broadcast().as_collection().arrange_locally(name)
+    }
+
+    fn arrange_locally(&self, name: &str) -> LocalIndex<G, K, V> {
+        self.arrange_core::<_, OrdValSpine<_, _, _, _>>(Pipeline, name)
+    }
+}
+
+/// How joins on the values of the data distribute the work among the workers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JoinStrategy {
+    /// Every key is sent to the worker its hash points to
+    #[default]
+    Hash,
+    /// The keys held by more than `threshold` records are spread over all the workers, see
+    /// [`SkewedJoin`]
+    Skewed {
+        /// The number of records above which a key is considered heavy
+        threshold: usize,
+    },
+}
+
+/// A join that does not send all the records of a popular key to a single worker.
+///
+/// The records of both sides are counted per key, first on each worker and then globally, and
+/// the keys with more records than the threshold are replicated on every worker. The records
+/// of those heavy keys are then laid on a grid with at least one cell per worker: a record of
+/// the left side picks a row from its hash and is copied on every column, a record of the right
+/// side picks a column and is copied on every row, so that each pair of records meets in exactly
+/// one cell. The other keys are joined as usual. A key moving above or below the threshold only
+/// moves its records between cells, the result of the join does not change.
+pub trait SkewedJoin<G, K, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+{
+    /// Joins the pairs with the same key, like `join_map`, treating the keys with more than
+    /// `threshold` records as heavy
+    fn join_skewed<V2, D, L>(
+        &self,
+        other: &Collection<G, (K, V2)>,
+        threshold: usize,
+        logic: L,
+    ) -> Collection<G, D>
+    where
+        V2: ExchangeData + Hash,
+        D: Data,
+        L: FnMut(&K, &V, &V2) -> D + 'static;
+}
+
+impl<G, K, V> SkewedJoin<G, K, V> for Collection<G, (K, V)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    K: ExchangeData + Hash,
+    V: ExchangeData + Hash,
+{
+    fn join_skewed<V2, D, L>(
+        &self,
+        other: &Collection<G, (K, V2)>,
+        threshold: usize,
+        mut logic: L,
+    ) -> Collection<G, D>
+    where
+        V2: ExchangeData + Hash,
+        D: Data,
+        L: FnMut(&K, &V, &V2) -> D + 'static,
+    {
+        let peers = self.scope().peers();
+        let side = (1..).find(|side| side * side >= peers).unwrap_or(1);
+
+        // Each worker counts the keys it holds, so that only the counts are exchanged
+        let heavy = self
+            .map(|(key, _)| key)
+            .concat(&other.map(|(key, _)| key))
+            .arrange_core::<_, OrdKeySpine<_, _, _>>(Pipeline, "LocalKeyCounts")
+            .count()
+            .explode(|(key, count)| Some((key, count)))
+            .count()
+            .filter(move |(_, count)| *count as usize > threshold)
+            .map(|(key, _)| (key, ()))
+            .replicate("HeavyKeys");
+
+        let left = salt(self, &heavy, move |value| {
+            let row = cell(value, side);
+            (0..side).map(move |column| (row, column))
+        });
+        let right = salt(other, &heavy, move |value| {
+            let column = cell(value, side);
+            (0..side).map(move |row| (row, column))
+        });
+
+        left.join_map(&right, move |(key, _, _), value, other| {
+            logic(key, value, other)
+        })
+    }
+}
+
+fn cell<V: Hash>(value: &V, side: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    (hasher.finish() % side as u64) as usize
+}
+
+// Keys the light pairs by the first cell and copies the heavy ones on the cells of `cells`
+fn salt<G, K, V, I, F>(
+    pairs: &Collection<G, (K, V)>,
+    heavy: &LocalIndex<G, K, ()>,
+    cells: F,
+) -> Collection<G, ((K, usize, usize), V)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    K: ExchangeData + Hash,
+    V: ExchangeData,
+    I: IntoIterator<Item = (usize, usize)>,
+    F: Fn(&V) -> I + 'static,
+{
+    let heavy_pairs = pairs
+        .arrange_locally("PairsByKey")
+        .join_core(heavy, |key, value, _| Some((key.clone(), value.clone())));
+
+    pairs
+        .concat(&heavy_pairs.negate())
+        .map(|(key, value)| ((key, 0, 0), value))
+        .concat(&heavy_pairs.flat_map(move |(key, value)| {
+            cells(&value)
+                .into_iter()
+                .map(|(row, column)| ((key.clone(), row, column), value.clone()))
+                .collect::<Vec<_>>()
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SkewedJoin;
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::join::Join;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    type Joined = (u64, u64, u64);
+    // A pair of the left side or of the right one, with its key and value
+    type Pair = (bool, u64, u64);
+
+    // The join of the pairs of both sides after each batch, with their multiplicity, on
+    // `workers` workers. The pairs are fed by the first worker.
+    fn joined(
+        workers: usize,
+        skewed: bool,
+        batches: Vec<Vec<(Pair, isize)>>,
+    ) -> Vec<BTreeMap<Joined, isize>> {
+        let times = batches.len();
+        let updates = Arc::new(Mutex::new(vec![]));
+        let sink = updates.clone();
+        timely::execute(timely::Configuration::Process(workers), move |worker| {
+            let sink = sink.clone();
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, pairs) = scope.new_collection::<Pair, isize>();
+                let left = pairs
+                    .filter(|(left, _, _)| *left)
+                    .map(|(_, key, value)| (key, value));
+                let right = pairs
+                    .filter(|(left, _, _)| !*left)
+                    .map(|(_, key, value)| (key, value));
+                let joined = if skewed {
+                    left.join_skewed(&right, 3, |key, l, r| (*key, *l, *r))
+                } else {
+                    left.join_map(&right, |key, l, r| (*key, *l, *r))
+                };
+                joined
+                    .inspect(move |update| sink.lock().unwrap().push(*update))
+                    .probe_with(&mut probe);
+                input
+            });
+            for (time, batch) in batches.iter().enumerate() {
+                if worker.index() == 0 {
+                    for (pair, diff) in batch {
+                        input.update(*pair, *diff);
+                    }
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+            }
+        })
+        .unwrap();
+
+        let updates = updates.lock().unwrap();
+        (0..times)
+            .map(|time| {
+                let mut counts = BTreeMap::new();
+                for (joined, t, diff) in updates.iter() {
+                    if *t <= time {
+                        *counts.entry(*joined).or_insert(0) += diff;
+                    }
+                }
+                counts.retain(|_, count| *count != 0);
+                counts
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skewed_joins_match_hash_joins_through_retractions() {
+        let (heavy, light) = (1, 2);
+        // The heavy key is held by nine records, then four, then none
+        let batches = vec![
+            (0..6)
+                .map(|value| ((true, heavy, value), 1))
+                .chain((10..13).map(|value| ((false, heavy, value), 1)))
+                .chain(vec![((true, light, 0), 1), ((false, light, 10), 1)])
+                .collect(),
+            (0..4)
+                .map(|value| ((true, heavy, value), -1))
+                .chain(vec![((false, heavy, 10), -1), ((true, light, 0), 1)])
+                .collect(),
+            vec![
+                ((true, heavy, 4), -1),
+                ((true, heavy, 5), -1),
+                ((false, heavy, 11), -1),
+                ((false, heavy, 12), -1),
+                ((true, light, 1), 1),
+            ],
+        ];
+
+        let expected = joined(1, false, batches.clone());
+        assert_eq!(expected[0].len(), 6 * 3 + 1);
+        assert_eq!(expected[1].len(), 2 * 2 + 1);
+        assert_eq!(expected[1][&(light, 0, 10)], 2);
+        assert_eq!(expected[2].len(), 2);
+        for workers in [1, 2, 4] {
+            assert_eq!(joined(workers, true, batches.clone()), expected);
+        }
+    }
+}
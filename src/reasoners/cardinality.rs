@@ -0,0 +1,229 @@
+//! Checks of the `owl:maxCardinality` and `owl:cardinality` restrictions on the closure computed
+//! by a profile:
+//!
+//! ```text
+//! cls-maxc:  T(u, TYPE, Nothing) <= T(x, maxCardinality, n), T(x, onProperty, p), T(u, TYPE, x),
+//!                                   #{ y | T(u, p, y) } > n
+//! cls-card:  T(u, TYPE, Nothing) <= T(x, cardinality, n), T(x, onProperty, p), T(u, TYPE, x),
+//!                                   #{ y | T(u, p, y) } > n
+//! ```
+//!
+//! An individual in `owl:Nothing` makes the data inconsistent. Under the open world assumption
+//! an individual with fewer values than `owl:cardinality` is not a violation, so only the upper
+//! bound is checked.
+//!
+//! The number of values is maintained with `count` on the pairs of individual and property that
+//! are subject to a restriction, so adding or removing a value, a type or a restriction updates
+//! the violations. The bounds are the numeric literals of the data, known through the indexed
+//! vocabulary.
+use crate::encoder::Triple;
+use crate::model::Literal;
+use crate::operators::SetSemantics;
+use crate::vocabulary::{
+    Cardinality, MaxCardinality, Nothing, OnProperty, Type, Vocabulary, VocabularyDeclaration,
+    VocabularyError,
+};
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::join::Join;
+use differential_dataflow::operators::reduce::{Count, Threshold};
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use timely::dataflow::Scope;
+
+/// The terms the checks refer to. The literals are indexed to read the bounds.
+pub fn vocabulary() -> VocabularyDeclaration {
+    VocabularyDeclaration::new()
+        .with::<Type>()
+        .with::<OnProperty>()
+        .with::<MaxCardinality>()
+        .with::<Cardinality>()
+        .with::<Nothing>()
+        .indexing_terms()
+}
+
+/// The violations of the restrictions in `closure`, as (individual, restriction)
+pub fn violations<G, T, V>(
+    closure: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<Collection<G, (V, V)>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let typ = vocabulary.term::<Type>()?;
+    let on_property = vocabulary.term::<OnProperty>()?;
+    let max_cardinality = vocabulary.term::<MaxCardinality>()?;
+    let cardinality = vocabulary.term::<Cardinality>()?;
+    let bounds = vocabulary.literals();
+
+    // (restriction, bound), the bound as the bits of an f64 so that it can be exchanged
+    let limits = closure
+        .filter(move |triple| *triple.p() == max_cardinality || *triple.p() == cardinality)
+        .flat_map(move |triple| {
+            bounds
+                .get(&triple.o())
+                .and_then(Literal::as_number)
+                .map(|bound| (*triple.s(), bound.to_bits()))
+        });
+    let properties = closure
+        .filter(move |triple| *triple.p() == on_property)
+        .map(|triple| (*triple.s(), *triple.o()));
+    let members = closure
+        .filter(move |triple| *triple.p() == typ)
+        .map(|triple| (*triple.o(), *triple.s()));
+
+    // ((individual, property), (restriction, bound))
+    let restricted = limits
+        .join_map(&properties, |&restriction, &bound, &property| {
+            (restriction, (property, bound))
+        })
+        .join_map(&members, |&restriction, &(property, bound), &individual| {
+            ((individual, property), (restriction, bound))
+        });
+
+    let values = closure
+        .map(|triple| ((*triple.s(), *triple.p()), *triple.o()))
+        .semijoin(&restricted.map(|(key, _)| key).distinct())
+        .map(|(key, _)| key)
+        .count();
+
+    Ok(restricted
+        .join_map(
+            &values,
+            |&(individual, _), &(restriction, bound), &count| {
+                (
+                    individual,
+                    restriction,
+                    count as f64 > f64::from_bits(bound),
+                )
+            },
+        )
+        .filter(|(_, _, violated)| *violated)
+        .map(|(individual, restriction, _)| (individual, restriction))
+        .distinct())
+}
+
+/// Adds `T(u, TYPE, Nothing)` to `closure` for every individual `u` that violates a restriction
+pub fn materialize<G, T, V>(
+    closure: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<Collection<G, T>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let typ = vocabulary.term::<Type>()?;
+    let nothing = vocabulary.term::<Nothing>()?;
+    let inconsistent = violations(closure, vocabulary)?
+        .map(move |(individual, _)| T::from_spo(individual, typ, nothing));
+    Ok(closure.concat(&inconsistent).set_semantics())
+}
+
+/// The result of [`materialize`], on a closure held in memory
+pub fn materialize_set<T, V>(
+    mut closure: HashSet<T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<HashSet<T>, VocabularyError>
+where
+    V: Eq + Hash + Copy,
+    T: Eq + Hash + Triple<V>,
+{
+    let typ = vocabulary.term::<Type>()?;
+    let on_property = vocabulary.term::<OnProperty>()?;
+    let max_cardinality = vocabulary.term::<MaxCardinality>()?;
+    let cardinality = vocabulary.term::<Cardinality>()?;
+    let nothing = vocabulary.term::<Nothing>()?;
+    let bounds = vocabulary.literals();
+
+    let mut limits: HashMap<V, Vec<f64>> = HashMap::new();
+    let mut properties: HashMap<V, Vec<V>> = HashMap::new();
+    let mut members: HashMap<V, Vec<V>> = HashMap::new();
+    let mut values: HashMap<(V, V), HashSet<V>> = HashMap::new();
+    for triple in &closure {
+        let (s, p, o) = (*triple.s(), *triple.p(), *triple.o());
+        if p == max_cardinality || p == cardinality {
+            if let Some(bound) = bounds.get(&o).and_then(Literal::as_number) {
+                limits.entry(s).or_default().push(bound);
+            }
+        } else if p == on_property {
+            properties.entry(s).or_default().push(o);
+        } else if p == typ {
+            members.entry(o).or_default().push(s);
+        }
+        values.entry((s, p)).or_default().insert(o);
+    }
+
+    let mut inconsistent = vec![];
+    for (restriction, limits) in &limits {
+        let properties = properties.get(restriction).into_iter().flatten();
+        for property in properties {
+            for individual in members.get(restriction).into_iter().flatten() {
+                let count = match values.get(&(*individual, *property)) {
+                    Some(values) => values.len(),
+                    None => continue,
+                };
+                if limits.iter().any(|bound| count as f64 > *bound) {
+                    inconsistent.push(T::from_spo(*individual, typ, nothing));
+                }
+            }
+        }
+    }
+    closure.extend(inconsistent);
+    Ok(closure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{materialize, vocabulary};
+    use crate::tests::materialize_batches;
+    use crate::vocabulary::{MaxCardinality, Nothing, OnProperty, Type, Vocabulary};
+
+    #[test]
+    fn reports_and_retracts_violations() {
+        let declaration = vocabulary();
+        let iris: Vec<String> = declaration
+            .terms()
+            .iter()
+            .map(|(_, iri)| iri.clone())
+            .collect();
+        let encoding = |iri: &str| iris.iter().position(|known| known == iri).map(|i| i as u64);
+        let mut vocabulary = Vocabulary::resolve(&declaration, encoding).unwrap();
+        let (one, restriction, head_of, person, a, b) = (20, 21, 22, 23, 24, 25);
+        vocabulary
+            .index_terms(
+                vec![(
+                    "\"1\"^^<http://www.w3.org/2001/XMLSchema#nonNegativeInteger>",
+                    one,
+                )],
+                |iri| Some(iri.len() as u64 + 100),
+            )
+            .unwrap();
+        let typ = vocabulary.term::<Type>().unwrap();
+        let nothing = vocabulary.term::<Nothing>().unwrap();
+        let max = vocabulary.term::<MaxCardinality>().unwrap();
+        let on_property = vocabulary.term::<OnProperty>().unwrap();
+
+        let batches = vec![
+            vec![
+                ((restriction, max, one), 1),
+                ((restriction, on_property, head_of), 1),
+                ((person, typ, restriction), 1),
+                ((person, head_of, a), 1),
+            ],
+            vec![((person, head_of, b), 1)],
+            vec![((person, head_of, a), -1)],
+        ];
+        let results =
+            materialize_batches(batches, move |data| materialize(data, &vocabulary).unwrap());
+
+        assert!(!results[0].contains(&(person, typ, nothing)));
+        assert!(results[1].contains(&(person, typ, nothing)));
+        assert_eq!(results[1].len(), 6);
+        assert!(!results[2].contains(&(person, typ, nothing)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Explanations of the materialized triples.
+//!
+//! Next to the materialization, each reasoner can compute the *supports* of its triples: every
+//! application of one of its rules to triples of the materialization, with the triple it derives.
+//! The supports are a collection like any other, so they follow the updates of the data. A base
+//! fact is supported by [`ASSERTED`], without premises.
+//!
+//! [`explain`] then walks the supports from a triple down to base facts, and returns the first
+//! derivation tree it finds that does not go through the triple it explains.
+use abomonation::Abomonation;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Name of the support of the base facts
+pub const ASSERTED: &str = "asserted";
+
+/// One way a triple is derived: the rule applied and the triples it is applied to, in the order
+/// of the atoms of its body
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Support<T> {
+    /// Name of the rule, [`ASSERTED`] for the base facts
+    pub rule: String,
+    /// The triples matched by the body of the rule
+    pub premises: Vec<T>,
+}
+
+impl<T> Support<T> {
+    /// The support of `rule` applied to `premises`
+    pub fn new<S: Into<String>>(rule: S, premises: Vec<T>) -> Self {
+        Self {
+            rule: rule.into(),
+            premises,
+        }
+    }
+
+    /// The support of a base fact
+    pub fn asserted() -> Self {
+        Self::new(ASSERTED, vec![])
+    }
+}
+
+// Written field by field, as the derive would
+impl<T: Abomonation> Abomonation for Support<T> {
+    unsafe fn entomb<W: std::io::Write>(&self, write: &mut W) -> std::io::Result<()> {
+        self.rule.entomb(write)?;
+        self.premises.entomb(write)
+    }
+
+    unsafe fn exhume<'b>(&mut self, bytes: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        let bytes = self.rule.exhume(bytes)?;
+        self.premises.exhume(bytes)
+    }
+
+    fn extent(&self) -> usize {
+        self.rule.extent() + self.premises.extent()
+    }
+}
+
+/// A derivation tree of a triple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<T> {
+    /// The explained triple
+    pub triple: T,
+    /// The rule that derives it, [`ASSERTED`] for a base fact
+    pub rule: String,
+    /// The explanations of the premises of the rule
+    pub premises: Vec<Explanation<T>>,
+}
+
+impl<T> Explanation<T> {
+    /// One line per triple of the tree, indented by depth, with the rule that derives it, e.g.
+    ///
+    /// ```text
+    /// <x> a <Person> .  # rule_3
+    ///   <Student> rdfs:subClassOf <Person> .  # asserted
+    ///   <x> a <Student> .  # asserted
+    /// ```
+    pub fn render<F: Fn(&T) -> String>(&self, decode: &F) -> String {
+        let mut lines = String::new();
+        self.render_at(decode, 0, &mut lines);
+        lines
+    }
+
+    fn render_at<F: Fn(&T) -> String>(&self, decode: &F, depth: usize, lines: &mut String) {
+        lines.push_str(&format!(
+            "{}{}  # {}\n",
+            "  ".repeat(depth),
+            decode(&self.triple),
+            self.rule
+        ));
+        for premise in &self.premises {
+            premise.render_at(decode, depth + 1, lines);
+        }
+    }
+}
+
+/// A derivation tree of `triple` from the base facts, if `supports` has one. The base facts are
+/// preferred, then the supports with the fewest premises.
+pub fn explain<T>(triple: &T, supports: &HashMap<T, Vec<Support<T>>>) -> Option<Explanation<T>>
+where
+    T: Clone + Eq + Hash,
+{
+    explain_avoiding(triple, supports, &mut vec![], &mut HashMap::new())
+}
+
+// `path` holds the triples being explained, which their premises can't go through. The trees
+// found are kept in `explained`: they don't depend on the path they were found on.
+fn explain_avoiding<T>(
+    triple: &T,
+    supports: &HashMap<T, Vec<Support<T>>>,
+    path: &mut Vec<T>,
+    explained: &mut HashMap<T, Explanation<T>>,
+) -> Option<Explanation<T>>
+where
+    T: Clone + Eq + Hash,
+{
+    if let Some(explanation) = explained.get(triple) {
+        return Some(explanation.clone());
+    }
+    if path.contains(triple) {
+        return None;
+    }
+    let mut candidates: Vec<&Support<T>> = supports.get(triple)?.iter().collect();
+    candidates.sort_by_key(|support| (support.rule != ASSERTED, support.premises.len()));
+
+    path.push(triple.clone());
+    let found = candidates.into_iter().find_map(|support| {
+        let premises = support
+            .premises
+            .iter()
+            .map(|premise| explain_avoiding(premise, supports, path, explained))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Explanation {
+            triple: triple.clone(),
+            rule: support.rule.clone(),
+            premises,
+        })
+    });
+    path.pop();
+
+    if let Some(explanation) = &found {
+        explained.insert(triple.clone(), explanation.clone());
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, Support, ASSERTED};
+    use std::collections::HashMap;
+
+    #[test]
+    fn explains_down_to_base_facts_around_cycles() {
+        let (a, b, c, d) = ((0, 0, 0), (1, 1, 1), (2, 2, 2), (3, 3, 3));
+        let mut supports = HashMap::new();
+        supports.insert(a, vec![Support::asserted()]);
+        // b and c support each other, only c also has a derivation from a
+        supports.insert(b, vec![Support::new("cycle", vec![c])]);
+        supports.insert(
+            c,
+            vec![
+                Support::new("cycle", vec![b]),
+                Support::new("base", vec![a, a]),
+            ],
+        );
+
+        let explanation = explain(&b, &supports).unwrap();
+        assert_eq!(explanation.rule, "cycle");
+        assert_eq!(explanation.premises[0].rule, "base");
+        assert_eq!(explanation.premises[0].premises[0].rule, ASSERTED);
+        assert_eq!(
+            explanation.render(&|triple: &(u64, u64, u64)| format!("{:?}", triple)),
+            "(1, 1, 1)  # cycle\n  (2, 2, 2)  # base\n    (0, 0, 0)  # asserted\n    \
+             (0, 0, 0)  # asserted\n"
+        );
+        assert!(explain(&d, &supports).is_none());
+
+        supports.remove(&a);
+        assert!(explain(&b, &supports).is_none());
+    }
+}
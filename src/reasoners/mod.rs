@@ -0,0 +1,402 @@
+//! Ready to use reasoners. Each profile exposes a `materialization` function that builds its
+//! closure in the scope of [`run_materialization`](crate::run_materialization), which runs it as a
+//! [`Reasoner`], and the rules it is made of so that they can be reused by other profiles.
+
+pub mod cardinality;
+pub mod explanation;
+pub mod owl2rl;
+pub mod rdfs;
+pub mod rho_df;
+pub mod same_as;
+
+use crate::datalog::{CompiledProgram, Program};
+use crate::encoder::Triple;
+use crate::operators::JoinStrategy;
+use crate::reasoners::explanation::Support;
+use crate::vocabulary::{Vocabulary, VocabularyDeclaration, VocabularyError};
+use crate::{Args, MaterializationScope, ParseModeError};
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::HashSet;
+use std::hash::Hash;
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+
+/// The profiles that can be selected for a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// [`rho_df`]
+    RhoDf,
+    /// [`rdfs`]
+    Rdfs,
+    /// [`owl2rl`]
+    Owl2Rl,
+}
+
+impl Profile {
+    /// The vocabulary of the profile
+    pub fn vocabulary(&self) -> VocabularyDeclaration {
+        match self {
+            Profile::RhoDf => rho_df::vocabulary(),
+            Profile::Rdfs => rdfs::vocabulary(),
+            Profile::Owl2Rl => owl2rl::vocabulary(),
+        }
+    }
+
+    /// The closure of `data_input` under the rules of the profile, evaluated as `evaluation`
+    /// says
+    pub fn materialize<G, T, V>(
+        &self,
+        data_input: &Collection<G, T>,
+        vocabulary: &Vocabulary<V>,
+        evaluation: Evaluation,
+    ) -> Result<Collection<G, T>, VocabularyError>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        V: ExchangeData + Hashable + Hash + Copy,
+        T: ExchangeData + Hashable + Hash + Triple<V>,
+    {
+        match self {
+            Profile::RhoDf => rho_df::materialize(data_input, vocabulary, evaluation),
+            Profile::Rdfs => rdfs::materialize(data_input, vocabulary, evaluation),
+            Profile::Owl2Rl => owl2rl::materialize(data_input, vocabulary, evaluation),
+        }
+    }
+
+    /// The supports of the triples of `materialized`, the closure computed by
+    /// [`materialize`](Self::materialize), see [`explanation`]
+    pub fn supports<G, T, V>(
+        &self,
+        materialized: &Collection<G, T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<Collection<G, (T, Support<T>)>, VocabularyError>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        V: ExchangeData + Hashable + Hash + Copy,
+        T: ExchangeData + Hashable + Hash + Triple<V>,
+    {
+        match self {
+            Profile::RhoDf => rho_df::supports(materialized, vocabulary),
+            Profile::Rdfs => rdfs::supports(materialized, vocabulary),
+            Profile::Owl2Rl => owl2rl::supports(materialized, vocabulary),
+        }
+    }
+
+    /// The closure computed by [`materialize`](Self::materialize), on a set of triples held in
+    /// memory without dataflow
+    pub fn materialize_set<T, V>(
+        &self,
+        facts: HashSet<T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<HashSet<T>, VocabularyError>
+    where
+        V: ExchangeData + Hash + Copy,
+        T: ExchangeData + Hash + Triple<V>,
+    {
+        match self {
+            Profile::RhoDf => rho_df::materialize_set(facts, vocabulary),
+            Profile::Rdfs => rdfs::materialize_set(facts, vocabulary),
+            Profile::Owl2Rl => owl2rl::materialize_set(facts, vocabulary),
+        }
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lc = s.to_lowercase();
+        if lc == "rho-df" || lc == "rhodf" || lc == "rho_df" {
+            Ok(Profile::RhoDf)
+        } else if lc == "rdfs" {
+            Ok(Profile::Rdfs)
+        } else if lc == "owl2rl" || lc == "owl2-rl" || lc == "owl-rl" {
+            Ok(Profile::Owl2Rl)
+        } else {
+            Err(format!("{} is not a correct profile [rho-df / rdfs / owl2rl].", s).into())
+        }
+    }
+}
+
+/// How the rules of a reasoner are evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Evaluation {
+    /// How the atoms of the rules are joined. The rules of [`rho_df`] look the schema up
+    /// locally and have no join to distribute.
+    pub joins: JoinStrategy,
+    /// Whether the triples are split in one collection per property of the data before the
+    /// rules read them, see [`Partitions`](crate::operators::Partitions)
+    pub partitioned: bool,
+}
+
+impl Evaluation {
+    /// Applies the evaluation to a program of the rule language
+    pub fn configure<V>(
+        &self,
+        program: CompiledProgram<V>,
+        vocabulary: &Vocabulary<V>,
+    ) -> CompiledProgram<V>
+    where
+        V: Copy + Eq + Hash,
+    {
+        let program = program.with_joins(self.joins);
+        if self.partitioned {
+            program.with_partitions(vocabulary.properties().to_vec())
+        } else {
+            program
+        }
+    }
+}
+
+/// The reasoner selected for a run: a profile or a rule set loaded from a file, optionally on top
+/// of the `owl:sameAs` canonicalization and followed by the cardinality checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reasoner {
+    /// The rules
+    pub profile: Profile,
+    /// Rules that replace the ones of the profile
+    pub rules: Option<Program>,
+    /// Whether `owl:sameAs` links are handled through [`same_as`]
+    pub same_as: bool,
+    /// Whether the closure is checked against its [`cardinality`] restrictions
+    pub cardinality: bool,
+    /// How the rules are evaluated
+    pub evaluation: Evaluation,
+}
+
+impl Reasoner {
+    /// The reasoner described by the command line
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        if args.expand_aliases && !args.same_as {
+            return Err(String::from("--expand-aliases requires --same-as"));
+        }
+        if args.skew_threshold.is_some() && args.rules.is_none() && args.profile == Profile::RhoDf {
+            return Err(String::from(
+                "--skew-threshold has no effect on the rho-df rules, which join nothing on the \
+                 values of the data: use it with another --profile or with --rules",
+            ));
+        }
+        let rules = match &args.rules {
+            Some(path) => Some(Program::load(path).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        Ok(Self {
+            profile: args.profile,
+            rules,
+            same_as: args.same_as,
+            cardinality: args.check_cardinality,
+            evaluation: Evaluation {
+                joins: match args.skew_threshold {
+                    Some(threshold) => JoinStrategy::Skewed { threshold },
+                    None => JoinStrategy::Hash,
+                },
+                partitioned: args.partition_by_predicate,
+            },
+        })
+    }
+
+    /// The vocabulary of the reasoner
+    pub fn vocabulary(&self) -> VocabularyDeclaration {
+        let mut declaration = match &self.rules {
+            Some(program) => program.vocabulary(),
+            None => self.profile.vocabulary(),
+        };
+        if self.same_as {
+            declaration = declaration.merge(&same_as::vocabulary());
+        }
+        if self.cardinality {
+            declaration = declaration.merge(&cardinality::vocabulary());
+        }
+        declaration
+    }
+
+    /// The closure of `data_input`
+    pub fn materialize<G, T, V>(
+        &self,
+        data_input: &Collection<G, T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<Collection<G, T>, VocabularyError>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        V: ExchangeData + Hashable + Hash + Copy,
+        T: ExchangeData + Hashable + Hash + Triple<V>,
+    {
+        // The checks run on the canonical triples, where the values of a property are counted
+        // once per equivalence class
+        let closure = |data: &Collection<G, T>| {
+            let closure = match &self.rules {
+                Some(program) => self
+                    .evaluation
+                    .configure(program.resolve(vocabulary)?, vocabulary)
+                    .materialize(data),
+                None => self
+                    .profile
+                    .materialize(data, vocabulary, self.evaluation)?,
+            };
+            if self.cardinality {
+                cardinality::materialize(&closure, vocabulary)
+            } else {
+                Ok(closure)
+            }
+        };
+        if self.same_as {
+            same_as::materialize(data_input, vocabulary, closure)
+        } else {
+            closure(data_input)
+        }
+    }
+
+    /// The closure computed by [`materialize`](Self::materialize), on a set of triples held in
+    /// memory without dataflow. It is the reference `--verify` checks the materialization
+    /// against.
+    pub fn materialize_set<T, V>(
+        &self,
+        facts: HashSet<T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<HashSet<T>, VocabularyError>
+    where
+        V: ExchangeData + Hash + Copy,
+        T: ExchangeData + Hash + Triple<V>,
+    {
+        let closure = |facts: HashSet<T>| {
+            let closure = match &self.rules {
+                Some(program) => program.resolve(vocabulary)?.materialize_set(facts),
+                None => self.profile.materialize_set(facts, vocabulary)?,
+            };
+            if self.cardinality {
+                cardinality::materialize_set(closure, vocabulary)
+            } else {
+                Ok(closure)
+            }
+        };
+        if self.same_as {
+            same_as::materialize_set(facts, vocabulary, closure)
+        } else {
+            closure(facts)
+        }
+    }
+
+    /// The rules of the reasoner as a single program of the rule language, which engines other
+    /// than the dataflow, e.g. [`DRed`](crate::datalog::DRed), can evaluate. `None` when part of
+    /// the closure is not written as rules: the term rules of [`rdfs`], the list rules of
+    /// [`owl2rl`], the `owl:sameAs` canonicalization and the cardinality checks.
+    pub fn program<V>(
+        &self,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<Option<CompiledProgram<V>>, VocabularyError>
+    where
+        V: ExchangeData + Hash + Copy,
+    {
+        if self.same_as || self.cardinality {
+            return Ok(None);
+        }
+        match (&self.rules, self.profile) {
+            (Some(program), _) => Ok(Some(program.resolve(vocabulary)?)),
+            (None, Profile::RhoDf) => Ok(Some(rho_df::program(vocabulary)?)),
+            (None, _) => Ok(None),
+        }
+    }
+
+    /// The supports of the triples of `materialized`, the closure computed by
+    /// [`materialize`](Self::materialize), in the form expected by `run_materialization`. The
+    /// triples added by the `owl:sameAs` canonicalization and by the cardinality checks have no
+    /// support.
+    pub fn supports<G, T, V>(
+        &self,
+        materialized: &Collection<G, T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<Collection<G, (T, Support<T>)>, VocabularyError>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        V: ExchangeData + Hashable + Hash + Copy,
+        T: ExchangeData + Hashable + Hash + Triple<V>,
+    {
+        match &self.rules {
+            Some(program) => Ok(program.resolve(vocabulary)?.supports(materialized)),
+            None => self.profile.supports(materialized, vocabulary),
+        }
+    }
+
+    /// The closure of `data_input`, arranged and probed by `probe`. `run_materialization` builds
+    /// its dataflow on it, and reads the other methods of the reasoner for `--explain`, `--verify`,
+    /// `--dred` and `--query`:
+    ///
+    /// ```ignore
+    /// run_materialization(encoder, Reasoner::from_args(&Args::from_args())?)
+    /// ```
+    pub fn materialization<T, V>(
+        &self,
+        data_input: &Collection<MaterializationScope<'_>, T>,
+        probe: &mut ProbeHandle<usize>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<TraceAgent<OrdKeySpine<T, usize, isize>>, VocabularyError>
+    where
+        V: ExchangeData + Hashable + Hash + Copy,
+        T: ExchangeData + Hashable + Hash + Triple<V>,
+    {
+        let arrangement = self.materialize(data_input, vocabulary)?.arrange_by_self();
+
+        arrangement.stream.probe_with(probe);
+
+        Ok(arrangement.trace)
+    }
+}
+
+impl From<Profile> for Reasoner {
+    /// The rules of `profile`, on their own
+    fn from(profile: Profile) -> Self {
+        Self {
+            profile,
+            rules: None,
+            same_as: false,
+            cardinality: false,
+            evaluation: Evaluation::default(),
+        }
+    }
+}
+
+impl From<Program> for Reasoner {
+    /// The rules of `program`, on their own
+    fn from(program: Program) -> Self {
+        Self {
+            rules: Some(program),
+            ..Self::from(Profile::RhoDf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, Reasoner};
+    use crate::operators::JoinStrategy;
+    use crate::Args;
+    use structopt::StructOpt;
+
+    fn args(options: &[&str]) -> Args {
+        Args::from_iter(
+            ["reasoning", "tbox.nt", "abox.nt", "out"]
+                .iter()
+                .chain(options),
+        )
+    }
+
+    #[test]
+    fn rejects_a_skew_threshold_the_rules_ignore() {
+        assert!(Reasoner::from_args(&args(&["--skew-threshold", "100"])).is_err());
+        let reasoner =
+            Reasoner::from_args(&args(&["--skew-threshold", "100", "--profile", "rdfs"])).unwrap();
+        assert_eq!(reasoner.profile, Profile::Rdfs);
+        assert_eq!(
+            reasoner.evaluation.joins,
+            JoinStrategy::Skewed { threshold: 100 }
+        );
+    }
+}
@@ -0,0 +1,585 @@
+//! A subset of the OWL 2 RL rules:
+//!
+//! ```text
+//! prp-dom, prp-rng, prp-spo1, cax-sco, scm-sco, scm-spo   -- as in rho-df
+//! prp-symp:  T(y, p, x) <= T(p, TYPE, SymmetricProperty), T(x, p, y)
+//! prp-trp:   T(x, p, z) <= T(p, TYPE, TransitiveProperty), T(x, p, y), T(y, p, z)
+//! prp-inv1:  T(y, q, x) <= T(p, inverseOf, q), T(x, p, y)
+//! prp-inv2:  T(y, p, x) <= T(p, inverseOf, q), T(x, q, y)
+//! scm-eqc1:  T(c, SCO, d), T(d, SCO, c) <= T(c, equivalentClass, d)
+//! scm-eqc2:  T(c, equivalentClass, d) <= T(c, SCO, d), T(d, SCO, c)
+//! scm-eqp1:  T(p, SPO, q), T(q, SPO, p) <= T(p, equivalentProperty, q)
+//! scm-eqp2:  T(p, equivalentProperty, q) <= T(p, SPO, q), T(q, SPO, p)
+//! cls-svf1:  T(u, TYPE, x) <= T(x, someValuesFrom, y), T(x, onProperty, p), T(u, p, v), T(v, TYPE, y)
+//! cls-svf2:  T(u, TYPE, x) <= T(x, someValuesFrom, Thing), T(x, onProperty, p), T(u, p, v)
+//! cls-int1:  T(y, TYPE, c) <= T(c, intersectionOf, (c1 ... cn)), T(y, TYPE, c1), ..., T(y, TYPE, cn)
+//! cls-int2:  T(y, TYPE, ci) <= T(c, intersectionOf, (c1 ... cn)), T(y, TYPE, c)
+//! prp-spo2:  T(u1, p, un+1) <= T(p, propertyChainAxiom, (p1 ... pn)), T(u1, p1, u2), ..., T(un, pn, un+1)
+//! ```
+//!
+//! cax-eqc1/2 and prp-eqp1/2 follow from scm-eqc1/scm-eqp1 through cax-sco and prp-spo1.
+//!
+//! The rules with a fixed number of atoms are evaluated as a [`Program`] of the rule language,
+//! while cls-int1, cls-int2 and prp-spo2 range over RDF lists and are written as dataflow. The
+//! lists are read from the input triples only: they are part of the ontology and never inferred.
+use crate::datalog::Program;
+use crate::encoder::Triple;
+use crate::model::{
+    OWL_EQUIVALENT_CLASS, OWL_EQUIVALENT_PROPERTY, OWL_INVERSE_OF, OWL_ON_PROPERTY,
+    OWL_SOME_VALUES_FROM, OWL_SYMMETRIC_PROPERTY, OWL_THING, OWL_TRANSITIVE_PROPERTY,
+};
+use crate::operators::SetSemantics;
+use crate::reasoners::explanation::Support;
+use crate::reasoners::Evaluation;
+use crate::vocabulary::{
+    First, IntersectionOf, Nil, PropertyChainAxiom, Rest, Type, Vocabulary, VocabularyDeclaration,
+    VocabularyError,
+};
+use crate::MaterializationScope;
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::iterate::Iterate;
+use differential_dataflow::operators::join::Join;
+use differential_dataflow::operators::reduce::{Count, Threshold};
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+
+/// The rules of the profile that have a fixed number of atoms, in the rule language
+pub fn rules() -> String {
+    format!(
+        "
+        % prp-dom, prp-rng, prp-spo1, cax-sco, scm-sco, scm-spo
+        T(?x, TYPE, ?c) <= T(?p, DOMAIN, ?c), T(?x, ?p, ?y)
+        T(?y, TYPE, ?c) <= T(?p, RANGE, ?c), T(?x, ?p, ?y)
+        T(?x, ?q, ?y) <= T(?p, SPO, ?q), T(?x, ?p, ?y)
+        T(?x, TYPE, ?d) <= T(?c, SCO, ?d), T(?x, TYPE, ?c)
+        T(?c, SCO, ?e) <= T(?c, SCO, ?d), T(?d, SCO, ?e)
+        T(?p, SPO, ?r) <= T(?p, SPO, ?q), T(?q, SPO, ?r)
+        % prp-symp
+        T(?y, ?p, ?x) <= T(?p, TYPE, {symmetric}), T(?x, ?p, ?y)
+        % prp-trp
+        T(?x, ?p, ?z) <= T(?p, TYPE, {transitive}), T(?x, ?p, ?y), T(?y, ?p, ?z)
+        % prp-inv1, prp-inv2
+        T(?y, ?q, ?x) <= T(?p, {inverse}, ?q), T(?x, ?p, ?y)
+        T(?y, ?p, ?x) <= T(?p, {inverse}, ?q), T(?x, ?q, ?y)
+        % scm-eqc1, scm-eqc2
+        T(?c, SCO, ?d) <= T(?c, {eqc}, ?d)
+        T(?d, SCO, ?c) <= T(?c, {eqc}, ?d)
+        T(?c, {eqc}, ?d) <= T(?c, SCO, ?d), T(?d, SCO, ?c)
+        % scm-eqp1, scm-eqp2
+        T(?p, SPO, ?q) <= T(?p, {eqp}, ?q)
+        T(?q, SPO, ?p) <= T(?p, {eqp}, ?q)
+        T(?p, {eqp}, ?q) <= T(?p, SPO, ?q), T(?q, SPO, ?p)
+        % cls-svf1, cls-svf2
+        T(?u, TYPE, ?x) <= T(?x, {svf}, ?y), T(?x, {on}, ?p), T(?u, ?p, ?v), T(?v, TYPE, ?y)
+        T(?u, TYPE, ?x) <= T(?x, {svf}, {thing}), T(?x, {on}, ?p), T(?u, ?p, ?v)
+        ",
+        symmetric = OWL_SYMMETRIC_PROPERTY,
+        transitive = OWL_TRANSITIVE_PROPERTY,
+        inverse = OWL_INVERSE_OF,
+        eqc = OWL_EQUIVALENT_CLASS,
+        eqp = OWL_EQUIVALENT_PROPERTY,
+        svf = OWL_SOME_VALUES_FROM,
+        on = OWL_ON_PROPERTY,
+        thing = OWL_THING,
+    )
+}
+
+fn program() -> Program {
+    Program::parse(&rules()).expect("The OWL 2 RL rules are well formed")
+}
+
+/// The terms the OWL 2 RL rules refer to
+pub fn vocabulary() -> VocabularyDeclaration {
+    program()
+        .vocabulary()
+        .with::<Type>()
+        .with::<First>()
+        .with::<Rest>()
+        .with::<Nil>()
+        .with::<IntersectionOf>()
+        .with::<PropertyChainAxiom>()
+}
+
+/// The RDF lists of `data_input`: the elements as (list, position, element) and the lengths as
+/// (list, length). Every cell of a list is the head of a list itself.
+// A cyclic list never reaches a fixpoint, but it is not a well formed list in the first place.
+#[allow(clippy::type_complexity)]
+pub fn lists<G, T, V>(
+    data_input: &Collection<G, T>,
+    first: V,
+    rest: V,
+    nil: V,
+) -> (Collection<G, (V, usize, V)>, Collection<G, (V, usize)>)
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Triple<V>,
+{
+    let firsts = data_input
+        .filter(move |triple| *triple.p() == first)
+        .map(|triple| (*triple.s(), *triple.o()));
+    let rests = data_input
+        .filter(move |triple| *triple.p() == rest)
+        .map(|triple| (*triple.s(), *triple.o()));
+
+    // (list, position, cell): the cell found at `position` starting from `list`
+    let heads = firsts.map(|(cell, _)| (cell, 0usize, cell));
+    let cells = heads.iterate(|cells| {
+        let heads = heads.enter(&cells.scope());
+        let rests = rests.enter(&cells.scope());
+        cells
+            .map(|(list, position, cell)| (cell, (list, position)))
+            .join_map(&rests, |_, &(list, position), &next| {
+                (list, position + 1, next)
+            })
+            .concat(&heads)
+            .distinct()
+    });
+
+    let elements = cells
+        .map(|(list, position, cell)| (cell, (list, position)))
+        .join_map(&firsts, |_, &(list, position), &element| {
+            (list, position, element)
+        });
+    let lengths = cells
+        .filter(move |(_, _, cell)| *cell == nil)
+        .map(|(list, length, _)| (list, length));
+
+    (elements, lengths)
+}
+
+/// Computes the OWL 2 RL closure of the collection
+pub fn materialize<G, T, V>(
+    data_input: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+    evaluation: Evaluation,
+) -> Result<Collection<G, T>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Hash + Triple<V>,
+{
+    let program = evaluation.configure(program().resolve(vocabulary)?, vocabulary);
+    let typ = vocabulary.term::<Type>()?;
+    let intersection_of = vocabulary.term::<IntersectionOf>()?;
+    let chain_axiom = vocabulary.term::<PropertyChainAxiom>()?;
+    let (elements, lengths) = lists(
+        data_input,
+        vocabulary.term::<First>()?,
+        vocabulary.term::<Rest>()?,
+        vocabulary.term::<Nil>()?,
+    );
+    let elements = elements.map(|(list, position, element)| (list, (position, element)));
+
+    // (class, position, member) and (class, size) of every intersection
+    let intersections = data_input
+        .filter(move |triple| *triple.p() == intersection_of)
+        .map(|triple| (*triple.o(), *triple.s()));
+    let members = intersections.join_map(&elements, |_, &class, &(position, member)| {
+        (class, position, member)
+    });
+    let sizes = intersections.join_map(&lengths, |_, &class, &size| (class, size));
+
+    // (property, position, link) and (property, length) of every chain
+    let chains = data_input
+        .filter(move |triple| *triple.p() == chain_axiom)
+        .map(|triple| (*triple.o(), *triple.s()));
+    let links = chains.join_map(&elements, |_, &property, &(position, link)| {
+        (property, position, link)
+    });
+    let chain_lengths = chains.join_map(&lengths, |_, &property, &length| (property, length));
+
+    let data_input = data_input.set_semantics();
+    let closure = data_input.iterate(|inner| {
+        let base = data_input.enter(&inner.scope());
+        let members = members.enter(&inner.scope());
+        let sizes = sizes.enter(&inner.scope());
+        let links = links.enter(&inner.scope());
+        let chain_lengths = chain_lengths.enter(&inner.scope());
+
+        // (class, individual)
+        let types = inner
+            .filter(move |triple| *triple.p() == typ)
+            .map(|triple| (*triple.o(), *triple.s()));
+
+        // cls-int1: an individual is of the intersection once it is of all of its members
+        let intersection_types = members
+            .map(|(class, position, member)| (member, (class, position)))
+            .join_map(&types, |_, &(class, position), &individual| {
+                (individual, class, position)
+            })
+            .distinct()
+            .map(|(individual, class, _)| (individual, class))
+            .count()
+            .map(|((individual, class), matched)| ((class, matched as usize), individual))
+            .join_map(
+                &sizes.map(|size| (size, ())),
+                move |&(class, _), &individual, _| T::from_spo(individual, typ, class),
+            );
+
+        // cls-int2
+        let member_types = types.join_map(
+            &members.map(|(class, _, member)| (class, member)),
+            move |_, &individual, &member| T::from_spo(individual, typ, member),
+        );
+
+        // prp-spo2: (property, steps, start, end) paths along the chains
+        let starts = links
+            .filter(|(_, position, _)| *position == 0)
+            .map(|(property, _, link)| (link, property))
+            .join_map(
+                &inner.map(|triple| (*triple.p(), (*triple.s(), *triple.o()))),
+                |_, &property, &(start, end)| (property, 1, start, end),
+            );
+        let paths = starts.iterate(|paths| {
+            let starts = starts.enter(&paths.scope());
+            let links = links
+                .enter(&paths.scope())
+                .map(|(property, position, link)| ((property, position), link));
+            let edges = inner
+                .enter(&paths.scope())
+                .map(|triple| ((*triple.p(), *triple.s()), *triple.o()));
+            paths
+                .map(|(property, steps, start, end)| ((property, steps), (start, end)))
+                .join_map(&links, |&(property, steps), &(start, end), &link| {
+                    ((link, end), (property, steps, start))
+                })
+                .join_map(&edges, |_, &(property, steps, start), &next| {
+                    (property, steps + 1, start, next)
+                })
+                .concat(&starts)
+                .distinct()
+        });
+        let chain_triples = paths
+            .map(|(property, steps, start, end)| ((property, steps), (start, end)))
+            .join_map(
+                &chain_lengths.map(|length| (length, ())),
+                |&(property, _), &(start, end), _| T::from_spo(start, property, end),
+            );
+
+        base.concat(&program.derive(inner))
+            .concat(&intersection_types)
+            .concat(&member_types)
+            .concat(&chain_triples)
+            .set_semantics()
+    });
+
+    Ok(closure)
+}
+
+/// The supports of the triples of `materialized`, the closure computed by [`materialize`]. Only
+/// the rules of [`program`] have supports: the triples that need the lists, from cls-int1,
+/// cls-int2 and prp-spo2, are not explained.
+pub fn supports<G, T, V>(
+    materialized: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<Collection<G, (T, Support<T>)>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Hash + Triple<V>,
+{
+    Ok(program().resolve(vocabulary)?.supports(materialized))
+}
+
+/// The lists of `facts`, as [`lists`] computes them: the elements of each list as (position,
+/// element) and its lengths
+#[allow(clippy::type_complexity)]
+fn lists_of_set<T, V>(
+    facts: &HashSet<T>,
+    first: V,
+    rest: V,
+    nil: V,
+) -> (HashMap<V, HashSet<(usize, V)>>, HashMap<V, HashSet<usize>>)
+where
+    V: Eq + Hash + Copy,
+    T: Triple<V>,
+{
+    let mut firsts: HashMap<V, Vec<V>> = HashMap::new();
+    let mut rests: HashMap<V, Vec<V>> = HashMap::new();
+    for triple in facts {
+        if *triple.p() == first {
+            firsts.entry(*triple.s()).or_default().push(*triple.o());
+        } else if *triple.p() == rest {
+            rests.entry(*triple.s()).or_default().push(*triple.o());
+        }
+    }
+
+    let (mut elements, mut lengths) = (HashMap::new(), HashMap::new());
+    for &list in firsts.keys() {
+        let (elements, lengths) = (
+            elements.entry(list).or_insert_with(HashSet::new),
+            lengths.entry(list).or_insert_with(HashSet::new),
+        );
+        // The cells found at each position, a list can't be longer than the number of cells
+        let mut cells: HashSet<V> = [list].into_iter().collect();
+        for position in 0..=rests.len() {
+            for cell in &cells {
+                if *cell == nil {
+                    lengths.insert(position);
+                }
+                for element in firsts.get(cell).into_iter().flatten() {
+                    elements.insert((position, *element));
+                }
+            }
+            cells = cells
+                .iter()
+                .flat_map(|cell| rests.get(cell).into_iter().flatten().copied())
+                .collect();
+        }
+    }
+    (elements, lengths)
+}
+
+/// The closure computed by [`materialize`], on a set of triples held in memory: the rules of
+/// [`program`] and the ones over lists are applied together until they derive nothing new
+pub fn materialize_set<T, V>(
+    facts: HashSet<T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<HashSet<T>, VocabularyError>
+where
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let program = program().resolve(vocabulary)?;
+    let typ = vocabulary.term::<Type>()?;
+    let intersection_of = vocabulary.term::<IntersectionOf>()?;
+    let chain_axiom = vocabulary.term::<PropertyChainAxiom>()?;
+    let (elements, lengths) = lists_of_set(
+        &facts,
+        vocabulary.term::<First>()?,
+        vocabulary.term::<Rest>()?,
+        vocabulary.term::<Nil>()?,
+    );
+
+    // The members and sizes of the intersections, and the links and lengths of the chains, by
+    // class and by property
+    let mut members: HashMap<V, HashSet<(usize, V)>> = HashMap::new();
+    let mut sizes: HashMap<V, HashSet<usize>> = HashMap::new();
+    let mut links: HashMap<V, HashSet<(usize, V)>> = HashMap::new();
+    let mut chain_lengths: HashMap<V, HashSet<usize>> = HashMap::new();
+    for triple in &facts {
+        let (elements_of, lengths_of) = if *triple.p() == intersection_of {
+            (&mut members, &mut sizes)
+        } else if *triple.p() == chain_axiom {
+            (&mut links, &mut chain_lengths)
+        } else {
+            continue;
+        };
+        let list = *triple.o();
+        elements_of
+            .entry(*triple.s())
+            .or_default()
+            .extend(elements.get(&list).into_iter().flatten());
+        lengths_of
+            .entry(*triple.s())
+            .or_default()
+            .extend(lengths.get(&list).into_iter().flatten());
+    }
+
+    let mut facts = facts;
+    loop {
+        let mut derived = program.derive_set(&facts);
+
+        let mut types: HashMap<V, HashSet<V>> = HashMap::new();
+        for triple in facts.iter().filter(|triple| *triple.p() == typ) {
+            types.entry(*triple.s()).or_default().insert(*triple.o());
+        }
+        for (class, members) in &members {
+            for (individual, classes) in &types {
+                // cls-int1
+                let matched: HashSet<usize> = members
+                    .iter()
+                    .filter(|(_, member)| classes.contains(member))
+                    .map(|(position, _)| *position)
+                    .collect();
+                if sizes
+                    .get(class)
+                    .is_some_and(|sizes| sizes.contains(&matched.len()))
+                {
+                    derived.push(T::from_spo(*individual, typ, *class));
+                }
+                // cls-int2
+                if classes.contains(class) {
+                    for (_, member) in members {
+                        derived.push(T::from_spo(*individual, typ, *member));
+                    }
+                }
+            }
+        }
+
+        // prp-spo2: the (start, end) of the paths along each chain, one step at a time
+        let mut edges: HashMap<(V, V), Vec<V>> = HashMap::new();
+        for triple in &facts {
+            edges
+                .entry((*triple.p(), *triple.s()))
+                .or_default()
+                .push(*triple.o());
+        }
+        for (property, links) in &links {
+            let lengths = &chain_lengths[property];
+            let longest = lengths.iter().max().copied().unwrap_or(0);
+            let mut paths: HashSet<(V, V)> = HashSet::new();
+            for (_, link) in links.iter().filter(|(position, _)| *position == 0) {
+                for triple in facts.iter().filter(|triple| *triple.p() == *link) {
+                    paths.insert((*triple.s(), *triple.o()));
+                }
+            }
+            for steps in 1..=longest {
+                if lengths.contains(&steps) {
+                    for (start, end) in &paths {
+                        derived.push(T::from_spo(*start, *property, *end));
+                    }
+                }
+                let step: Vec<V> = links
+                    .iter()
+                    .filter(|(position, _)| *position == steps)
+                    .map(|(_, link)| *link)
+                    .collect();
+                paths = paths
+                    .iter()
+                    .flat_map(|(start, end)| {
+                        step.iter()
+                            .flat_map(|link| edges.get(&(*link, *end)).into_iter().flatten())
+                            .map(move |next| (*start, *next))
+                    })
+                    .collect();
+            }
+        }
+
+        let known = facts.len();
+        facts.extend(derived);
+        if facts.len() == known {
+            return Ok(facts);
+        }
+    }
+}
+
+/// Computes the full materialization of the collection and returns the trace of the result
+pub fn full_materialization<G, T, V>(
+    data_input: &Collection<G, T>,
+    probe: &mut ProbeHandle<G::Timestamp>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, G::Timestamp, isize>>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Hash + Triple<V>,
+{
+    let arrangement = materialize(data_input, vocabulary, Evaluation::default())?.arrange_by_self();
+
+    arrangement.stream.probe_with(probe);
+
+    Ok(arrangement.trace)
+}
+
+/// The OWL 2 RL profile, arranged and probed like the closure of
+/// [`Reasoner::materialization`](super::Reasoner::materialization). `run_materialization` runs
+/// it as:
+///
+/// ```ignore
+/// run_materialization(encoder, Reasoner::from(Profile::Owl2Rl))
+/// ```
+pub fn materialization<T, V>(
+    data_input: &Collection<MaterializationScope<'_>, T>,
+    probe: &mut ProbeHandle<usize>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, usize, isize>>, VocabularyError>
+where
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Hash + Triple<V>,
+{
+    full_materialization(data_input, probe, vocabulary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{materialize, materialize_set, vocabulary};
+    use crate::reasoners::Evaluation;
+    use crate::tests::{facts_after_batches, materialize_batches};
+    use crate::vocabulary::Vocabulary;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn materializes_lists_and_property_rules() {
+        let vocabulary = vocabulary();
+        let iris: Vec<String> = vocabulary
+            .terms()
+            .iter()
+            .map(|(_, iri)| iri.clone())
+            .collect();
+        let encoding = |iri: &str| iris.iter().position(|known| known == iri).map(|i| i as u64);
+        let vocabulary = Vocabulary::resolve(&vocabulary, encoding).unwrap();
+        let term = |name: &str| vocabulary.get(name).unwrap();
+        let typ = term(crate::model::RDF_TYPE);
+        let (first, rest, nil) = (term("rdf:first"), term("rdf:rest"), term("rdf:nil"));
+        let intersection_of = term("owl:intersectionOf");
+        let chain = term("owl:propertyChainAxiom");
+        let inverse = term(crate::model::OWL_INVERSE_OF);
+        let transitive = term(crate::model::OWL_TRANSITIVE_PROPERTY);
+
+        let (chair, person, head, department) = (100, 101, 102, 103);
+        let (class_list, class_rest, chain_list, chain_rest) = (110, 111, 112, 113);
+        let (works_for, sub_organization, has_head, affiliated) = (120, 121, 122, 123);
+        let (x, dep, university, region) = (130, 131, 132, 133);
+
+        let batches = vec![
+            vec![
+                // chair = person AND head
+                ((chair, intersection_of, class_list), 1),
+                ((class_list, first, person), 1),
+                ((class_list, rest, class_rest), 1),
+                ((class_rest, first, head), 1),
+                ((class_rest, rest, nil), 1),
+                // affiliated = worksFor o subOrganizationOf
+                ((affiliated, chain, chain_list), 1),
+                ((chain_list, first, works_for), 1),
+                ((chain_list, rest, chain_rest), 1),
+                ((chain_rest, first, sub_organization), 1),
+                ((chain_rest, rest, nil), 1),
+                ((works_for, inverse, has_head), 1),
+                ((sub_organization, typ, transitive), 1),
+                ((x, typ, person), 1),
+                ((x, typ, head), 1),
+                ((x, works_for, dep), 1),
+                ((dep, sub_organization, university), 1),
+                ((university, sub_organization, region), 1),
+                ((dep, typ, department), 1),
+            ],
+            vec![
+                ((x, typ, head), -1),
+                ((university, sub_organization, region), -1),
+            ],
+        ];
+        let facts = facts_after_batches(&batches);
+        let sequential = vocabulary.clone();
+        let results = materialize_batches(batches, move |data| {
+            materialize(data, &vocabulary, Evaluation::default()).unwrap()
+        });
+        for (facts, result) in facts.into_iter().zip(&results) {
+            let closure = materialize_set(facts, &sequential).unwrap();
+            assert_eq!(&closure.into_iter().collect::<BTreeSet<_>>(), result);
+        }
+
+        assert!(results[0].contains(&(x, typ, chair)));
+        assert!(results[0].contains(&(dep, has_head, x)));
+        assert!(results[0].contains(&(dep, sub_organization, region)));
+        assert!(results[0].contains(&(x, affiliated, university)));
+        assert!(results[0].contains(&(x, affiliated, region)));
+
+        assert!(!results[1].contains(&(x, typ, chair)));
+        assert!(!results[1].contains(&(x, affiliated, region)));
+        assert!(results[1].contains(&(x, affiliated, university)));
+    }
+}
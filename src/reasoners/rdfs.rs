@@ -0,0 +1,374 @@
+//! The RDFS entailment rules of RDF 1.1 Semantics:
+//!
+//! ```text
+//! rdfD1:  T(l, TYPE, d) <= T(x, p, l)            -- l is a literal of datatype d
+//! rdfD2:  T(p, TYPE, Property) <= T(x, p, y)
+//! rdfs1:  T(d, TYPE, Datatype) <= T(x, p, l)     -- l is a literal of datatype d
+//! rdfs2:  T(x, TYPE, c) <= T(p, DOMAIN, c), T(x, p, y)
+//! rdfs3:  T(y, TYPE, c) <= T(p, RANGE, c), T(x, p, y)
+//! rdfs4a: T(x, TYPE, Resource) <= T(x, p, y)
+//! rdfs4b: T(y, TYPE, Resource) <= T(x, p, y)
+//! rdfs5:  T(p, SPO, r) <= T(p, SPO, q), T(q, SPO, r)
+//! rdfs6:  T(p, SPO, p) <= T(p, TYPE, Property)
+//! rdfs7:  T(x, q, y) <= T(p, SPO, q), T(x, p, y)
+//! rdfs8:  T(c, SCO, Resource) <= T(c, TYPE, Class)
+//! rdfs9:  T(x, TYPE, d) <= T(c, SCO, d), T(x, TYPE, c)
+//! rdfs10: T(c, SCO, c) <= T(c, TYPE, Class)
+//! rdfs11: T(c, SCO, e) <= T(c, SCO, d), T(d, SCO, e)
+//! rdfs12: T(p, SPO, member) <= T(p, TYPE, ContainerMembershipProperty)
+//! rdfs13: T(d, SCO, Literal) <= T(d, TYPE, Datatype)
+//! ```
+//!
+//! plus the axiomatic triples `T(rdf:_n, TYPE, ContainerMembershipProperty)` for the container
+//! membership properties that appear in the data. The triples are generalized: rdfD1 and rdfs4b
+//! type literals too, so literals can appear as subjects of the inferred triples. Such triples
+//! take part in the materialization but are left out of the N-Triples output.
+//!
+//! The rules that only look at the triples are evaluated as a [`Program`] of the rule language;
+//! rdfD1, rdfs1 and the axioms depend on the terms themselves and use the term index of the
+//! vocabulary instead.
+use crate::datalog::Program;
+use crate::encoder::Triple;
+use crate::model::{
+    RDFS_CLASS, RDFS_CONTAINER_MEMBERSHIP_PROPERTY, RDFS_DATATYPE, RDFS_LITERAL, RDFS_MEMBER,
+    RDFS_RESOURCE, RDF_PROPERTY,
+};
+use crate::reasoners::explanation::Support;
+use crate::reasoners::Evaluation;
+use crate::vocabulary::{
+    ContainerMembershipProperty, Datatype, Type, Vocabulary, VocabularyDeclaration, VocabularyError,
+};
+use crate::MaterializationScope;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::HashSet;
+use std::hash::Hash;
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+
+/// The rules of the profile that only look at the triples, in the rule language
+pub fn rules() -> String {
+    format!(
+        "
+        % rdfD2
+        T(?p, TYPE, {property}) <= T(?x, ?p, ?y)
+        % rdfs2
+        T(?x, TYPE, ?c) <= T(?p, DOMAIN, ?c), T(?x, ?p, ?y)
+        % rdfs3
+        T(?y, TYPE, ?c) <= T(?p, RANGE, ?c), T(?x, ?p, ?y)
+        % rdfs4a
+        T(?x, TYPE, {resource}) <= T(?x, ?p, ?y)
+        % rdfs4b
+        T(?y, TYPE, {resource}) <= T(?x, ?p, ?y)
+        % rdfs5
+        T(?p, SPO, ?r) <= T(?p, SPO, ?q), T(?q, SPO, ?r)
+        % rdfs6
+        T(?p, SPO, ?p) <= T(?p, TYPE, {property})
+        % rdfs7
+        T(?x, ?q, ?y) <= T(?p, SPO, ?q), T(?x, ?p, ?y)
+        % rdfs8
+        T(?c, SCO, {resource}) <= T(?c, TYPE, {class})
+        % rdfs9
+        T(?x, TYPE, ?d) <= T(?c, SCO, ?d), T(?x, TYPE, ?c)
+        % rdfs10
+        T(?c, SCO, ?c) <= T(?c, TYPE, {class})
+        % rdfs11
+        T(?c, SCO, ?e) <= T(?c, SCO, ?d), T(?d, SCO, ?e)
+        % rdfs12
+        T(?p, SPO, {member}) <= T(?p, TYPE, {membership})
+        % rdfs13
+        T(?d, SCO, {literal}) <= T(?d, TYPE, {datatype})
+        ",
+        property = RDF_PROPERTY,
+        resource = RDFS_RESOURCE,
+        class = RDFS_CLASS,
+        member = RDFS_MEMBER,
+        membership = RDFS_CONTAINER_MEMBERSHIP_PROPERTY,
+        literal = RDFS_LITERAL,
+        datatype = RDFS_DATATYPE,
+    )
+}
+
+fn program() -> Program {
+    Program::parse(&rules()).expect("The RDFS rules are well formed")
+}
+
+/// The terms the RDFS rules refer to. The terms of the data are indexed as well, to find the
+/// literals and the container membership properties.
+pub fn vocabulary() -> VocabularyDeclaration {
+    program()
+        .vocabulary()
+        .with::<Type>()
+        .with::<Datatype>()
+        .with::<ContainerMembershipProperty>()
+        .indexing_terms()
+}
+
+/// Computes the RDFS closure of the collection
+pub fn materialize<G, T, V>(
+    data_input: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+    evaluation: Evaluation,
+) -> Result<Collection<G, T>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let program = evaluation.configure(program().resolve(vocabulary)?, vocabulary);
+    let typ = vocabulary.term::<Type>()?;
+    let datatype = vocabulary.term::<Datatype>()?;
+    let membership = vocabulary.term::<ContainerMembershipProperty>()?;
+
+    let datatypes = vocabulary.literal_datatypes();
+    let membership_properties: HashSet<V> = vocabulary
+        .container_membership_properties()
+        .iter()
+        .copied()
+        .collect();
+
+    // The terms of the inferred triples all come from the input, so the rules that depend on
+    // the terms only need to look at the input triples.
+    let term_rules = data_input.flat_map(move |triple| {
+        let mut derived = vec![];
+        if let Some(literal_datatype) = datatypes.get(&triple.o()) {
+            // rdfD1
+            derived.push(T::from_spo(*triple.o(), typ, *literal_datatype));
+            // rdfs1
+            derived.push(T::from_spo(*literal_datatype, typ, datatype));
+        }
+        for term in [triple.s(), triple.p(), triple.o()] {
+            if membership_properties.contains(&term) {
+                derived.push(T::from_spo(*term, typ, membership));
+            }
+        }
+        derived
+    });
+
+    Ok(program.materialize(&data_input.concat(&term_rules)))
+}
+
+/// The supports of the triples of `materialized`, the closure computed by [`materialize`]: the
+/// applications of the rules of [`program`] and of rdfD1, rdfs1 and the axioms, whose premise is
+/// the triple the term appears in
+pub fn supports<G, T, V>(
+    materialized: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<Collection<G, (T, Support<T>)>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let program = program().resolve(vocabulary)?;
+    let typ = vocabulary.term::<Type>()?;
+    let datatype = vocabulary.term::<Datatype>()?;
+    let membership = vocabulary.term::<ContainerMembershipProperty>()?;
+
+    let datatypes = vocabulary.literal_datatypes();
+    let membership_properties: HashSet<V> = vocabulary
+        .container_membership_properties()
+        .iter()
+        .copied()
+        .collect();
+
+    let term_rules = materialized.flat_map(move |triple| {
+        let mut supports = vec![];
+        if let Some(literal_datatype) = datatypes.get(&triple.o()) {
+            supports.push((
+                T::from_spo(*triple.o(), typ, *literal_datatype),
+                Support::new("rdfD1", vec![triple.clone()]),
+            ));
+            supports.push((
+                T::from_spo(*literal_datatype, typ, datatype),
+                Support::new("rdfs1", vec![triple.clone()]),
+            ));
+        }
+        for term in [triple.s(), triple.p(), triple.o()] {
+            if membership_properties.contains(&term) {
+                supports.push((
+                    T::from_spo(*term, typ, membership),
+                    Support::new("axiom", vec![triple.clone()]),
+                ));
+            }
+        }
+        supports
+    });
+
+    Ok(program.supports(materialized).concat(&term_rules))
+}
+
+/// The closure computed by [`materialize`], on a set of triples held in memory, see
+/// [`CompiledProgram::materialize_set`](crate::datalog::CompiledProgram::materialize_set)
+pub fn materialize_set<T, V>(
+    facts: HashSet<T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<HashSet<T>, VocabularyError>
+where
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let program = program().resolve(vocabulary)?;
+    let typ = vocabulary.term::<Type>()?;
+    let datatype = vocabulary.term::<Datatype>()?;
+    let membership = vocabulary.term::<ContainerMembershipProperty>()?;
+
+    let datatypes = vocabulary.literal_datatypes();
+    let membership_properties = vocabulary.container_membership_properties();
+
+    let mut term_rules = vec![];
+    for triple in &facts {
+        if let Some(literal_datatype) = datatypes.get(&triple.o()) {
+            term_rules.push(T::from_spo(*triple.o(), typ, *literal_datatype));
+            term_rules.push(T::from_spo(*literal_datatype, typ, datatype));
+        }
+        for term in [triple.s(), triple.p(), triple.o()] {
+            if membership_properties.contains(&term) {
+                term_rules.push(T::from_spo(*term, typ, membership));
+            }
+        }
+    }
+    let mut facts = facts;
+    facts.extend(term_rules);
+
+    Ok(program.materialize_set(facts))
+}
+
+/// Computes the full materialization of the collection and returns the trace of the result
+pub fn full_materialization<G, T, V>(
+    data_input: &Collection<G, T>,
+    probe: &mut ProbeHandle<G::Timestamp>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, G::Timestamp, isize>>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let arrangement = materialize(data_input, vocabulary, Evaluation::default())?.arrange_by_self();
+
+    arrangement.stream.probe_with(probe);
+
+    Ok(arrangement.trace)
+}
+
+/// The RDFS profile, arranged and probed like the closure of
+/// [`Reasoner::materialization`](super::Reasoner::materialization). `run_materialization` runs
+/// it as:
+///
+/// ```ignore
+/// run_materialization(encoder, Reasoner::from(Profile::Rdfs))
+/// ```
+pub fn materialization<T, V>(
+    data_input: &Collection<MaterializationScope<'_>, T>,
+    probe: &mut ProbeHandle<usize>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, usize, isize>>, VocabularyError>
+where
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    full_materialization(data_input, probe, vocabulary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{materialize, vocabulary};
+    use crate::model::{
+        RDFS_CLASS, RDFS_CONTAINER_MEMBERSHIP_PROPERTY, RDFS_DATATYPE, RDFS_DOMAIN, RDFS_LITERAL,
+        RDFS_MEMBER, RDFS_RANGE, RDFS_RESOURCE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF,
+        RDF_PROPERTY, RDF_TYPE,
+    };
+    use crate::reasoners::Evaluation;
+    use crate::tests::materialize_batches;
+    use crate::vocabulary::Vocabulary;
+
+    const INTEGER: &str = "<http://www.w3.org/2001/XMLSchema#integer>";
+    const FIRST: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#_1>";
+
+    fn encoding(iri: &str) -> Option<u64> {
+        [
+            RDFS_SUB_CLASS_OF,
+            RDFS_SUB_PROPERTY_OF,
+            RDF_TYPE,
+            RDFS_DOMAIN,
+            RDFS_RANGE,
+            RDFS_RESOURCE,
+            RDFS_CLASS,
+            RDF_PROPERTY,
+            RDFS_LITERAL,
+            RDFS_DATATYPE,
+            RDFS_CONTAINER_MEMBERSHIP_PROPERTY,
+            RDFS_MEMBER,
+            INTEGER,
+            FIRST,
+        ]
+        .iter()
+        .position(|keyword| *keyword == iri)
+        .map(|i| i as u64)
+    }
+
+    #[test]
+    fn materializes_the_rdfs_rules() {
+        let typ = encoding(RDF_TYPE).unwrap();
+        let sco = encoding(RDFS_SUB_CLASS_OF).unwrap();
+        let spo = encoding(RDFS_SUB_PROPERTY_OF).unwrap();
+        let resource = encoding(RDFS_RESOURCE).unwrap();
+        let class = encoding(RDFS_CLASS).unwrap();
+        let property = encoding(RDF_PROPERTY).unwrap();
+        let literal = encoding(RDFS_LITERAL).unwrap();
+        let member = encoding(RDFS_MEMBER).unwrap();
+        let integer = encoding(INTEGER).unwrap();
+        let first = encoding(FIRST).unwrap();
+        let (bag, student, age, x, forty_two) = (100, 101, 102, 103, 104);
+
+        let mut vocabulary = Vocabulary::resolve(&vocabulary(), encoding).unwrap();
+        vocabulary
+            .index_terms(
+                vec![
+                    (String::from(FIRST), first),
+                    (format!("\"42\"^^{}", INTEGER), forty_two),
+                ],
+                encoding,
+            )
+            .unwrap();
+
+        let batches = vec![
+            vec![
+                ((student, typ, class), 1),
+                ((x, age, forty_two), 1),
+                ((bag, first, x), 1),
+            ],
+            vec![((bag, first, x), -1)],
+        ];
+        let results = materialize_batches(batches, move |data| {
+            materialize(data, &vocabulary, Evaluation::default()).unwrap()
+        });
+
+        // rdfs8, rdfs10
+        assert!(results[0].contains(&(student, sco, resource)));
+        assert!(results[0].contains(&(student, sco, student)));
+        // rdfD2, rdfs6, rdfs4a, rdfs4b
+        assert!(results[0].contains(&(age, typ, property)));
+        assert!(results[0].contains(&(age, spo, age)));
+        assert!(results[0].contains(&(x, typ, resource)));
+        assert!(results[0].contains(&(forty_two, typ, resource)));
+        // rdfD1, rdfs13, rdfs9
+        assert!(results[0].contains(&(forty_two, typ, integer)));
+        assert!(results[0].contains(&(integer, sco, literal)));
+        assert!(results[0].contains(&(forty_two, typ, literal)));
+        // container membership, rdfs12, rdfs7
+        assert!(results[0].contains(&(first, spo, member)));
+        assert!(results[0].contains(&(bag, member, x)));
+
+        assert!(!results[1].contains(&(bag, member, x)));
+        assert!(!results[1].contains(&(first, spo, member)));
+        assert!(results[1].contains(&(forty_two, typ, literal)));
+    }
+}
@@ -0,0 +1,828 @@
+//! The rho-df fragment of RDFS:
+//!
+//! ```text
+//! T(a, SCO, c) <= T(a, SCO, b),T(b, SCO, c)
+//! T(a, SPO, c) <= T(a, SPO, b),T(b, SPO, c)
+//! T(x, TYPE, b) <= T(a, SCO, b),T(x, TYPE, a)
+//! T(x, p, y) <= T(p1, SPO, p),T(x, p1, y)
+//! T(a, TYPE, D) <= T(p, DOMAIN, D),T(a, p, b)
+//! T(b, TYPE, R) <= T(p, RANGE, R),T(a, p, b)
+//! ```
+//!
+//! The first two rules close the schema, reading the same
+//! [`TripleIndex`](crate::operators::TripleIndex) of the SCO and SPO triples. The closure is then
+//! replicated on every worker as a [`Schema`], and the remaining rules look it up locally for
+//! each triple of the data.
+//!
+//! The schema and the remaining rules are evaluated until they derive nothing new, so that the
+//! closure is the fixpoint of the six rules, the one [`program`] computes with the rule language.
+use crate::datalog::{CompiledProgram, Program};
+use crate::encoder::Triple;
+use crate::model::{RDFS_DOMAIN, RDFS_RANGE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE};
+use crate::operators::{Local, LocalIndex, Partitions, SetSemantics, TripleIndex};
+use crate::reasoners::explanation::Support;
+use crate::reasoners::Evaluation;
+use crate::vocabulary::{
+    Domain, Range, SubClassOf, SubPropertyOf, Type, Vocabulary, VocabularyDeclaration,
+    VocabularyError,
+};
+use crate::MaterializationScope;
+use differential_dataflow::collection::concatenate;
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::arrangement::ArrangeBySelf;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::iterate::{Iterate, Variable};
+use differential_dataflow::operators::join::{Join, JoinCore};
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::HashSet;
+use std::hash::Hash;
+use timely::dataflow::operators::probe::Probe;
+use timely::dataflow::{ProbeHandle, Scope};
+use timely::order::Product;
+
+/// First rule: T(a, SCO, c) <= T(a, SCO, b),T(b, SCO, c)
+pub fn rule_1<G, T, V>(index: &TripleIndex<G, V, T>, sco_value: V) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    let ending = index
+        .by_object
+        .filter(move |_, triple| *triple.p() == sco_value);
+    let starting = index
+        .by_subject
+        .filter(move |_, triple| *triple.p() == sco_value);
+
+    ending.join_core(&starting, |_b, first, second| {
+        Some(T::from_spo(*first.s(), *first.p(), *second.o()))
+    })
+}
+
+/// Second rule: T(a, SPO, c) <= T(a, SPO, b),T(b, SPO, c)
+pub fn rule_2<G, T, V>(index: &TripleIndex<G, V, T>, spo_value: V) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    // Same shape as the transitive closure of rule_1, on a different property
+    rule_1(index, spo_value)
+}
+
+/// Third rule: T(x, TYPE, b) <= T(a, SCO, b),T(x, TYPE, a)
+pub fn rule_3<G, T, V>(
+    data_collection: &Collection<G, T>,
+    schema: &Schema<G, T, V>,
+    type_value: V,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    data_collection
+        .filter(move |triple| *triple.p() == type_value)
+        .map(|triple| (*triple.o(), triple))
+        .arrange_locally("TypesByClass")
+        .join_core(&schema.super_classes, |_a, triple, b| {
+            Some(T::from_spo(*triple.s(), *triple.p(), *b))
+        })
+}
+
+/// Fourth rule: T(x, p, b) <= T(p1, SPO, p),T(x, p1, y)
+pub fn rule_4<G, T, V>(
+    by_predicate: &LocalIndex<G, V, T>,
+    schema: &Schema<G, T, V>,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    by_predicate.join_core(&schema.super_properties, |_p1, triple, p| {
+        Some(T::from_spo(*triple.s(), *p, *triple.o()))
+    })
+}
+
+/// Fifth rule: T(a, TYPE, D) <= T(p, DOMAIN, D),T(a, p, b)
+pub fn rule_5<G, T, V>(
+    by_predicate: &LocalIndex<G, V, T>,
+    schema: &Schema<G, T, V>,
+    type_value: V,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    by_predicate.join_core(&schema.domains, move |_p, triple, d| {
+        Some(T::from_spo(*triple.s(), type_value, *d))
+    })
+}
+
+/// Sixth rule: T(b, TYPE, R) <= T(p, RANGE, R),T(a, p, b)
+pub fn rule_6<G, T, V>(
+    by_predicate: &LocalIndex<G, V, T>,
+    schema: &Schema<G, T, V>,
+    type_value: V,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    by_predicate.join_core(&schema.ranges, move |_p, triple, r| {
+        Some(T::from_spo(*triple.o(), type_value, *r))
+    })
+}
+
+/// The closure of the schema of a collection, held in full by every worker.
+///
+/// The closure is computed once over the schema triples, which are few compared with the rest
+/// of the data, and every worker receives an indexed copy of it that is kept up to date as the
+/// schema changes. The rules that read the schema then look it up on the worker that holds each
+/// triple instead of exchanging the triples to join them.
+pub struct Schema<G, T, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData,
+    T: ExchangeData,
+{
+    /// The SCO and SPO triples, closed under transitivity
+    pub closure: Collection<G, T>,
+    /// Every class with each of its super classes
+    pub super_classes: LocalIndex<G, V, V>,
+    /// Every property with each of its super properties
+    pub super_properties: LocalIndex<G, V, V>,
+    /// Every property with the classes of its subjects: the domains of the property and of its
+    /// super properties, together with their super classes
+    pub domains: LocalIndex<G, V, V>,
+    /// Every property with the classes of its objects, as `domains` does for the subjects
+    pub ranges: LocalIndex<G, V, V>,
+}
+
+impl<G, T, V> Schema<G, T, V>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    /// Computes the closure of the SCO, SPO, DOMAIN and RANGE triples of the collection
+    pub fn new(
+        data_collection: &Partitions<G, V, T>,
+        vocabulary: &Vocabulary<V>,
+    ) -> Result<Self, VocabularyError> {
+        let sco = vocabulary.term::<SubClassOf>()?;
+        let spo = vocabulary.term::<SubPropertyOf>()?;
+        let domain = vocabulary.term::<Domain>()?;
+        let range = vocabulary.term::<Range>()?;
+
+        // Every worker inserts the whole TBox, the copies are merged before computing on them
+        let hierarchy = data_collection
+            .matching(&sco, None)
+            .filter(move |triple| *triple.p() == sco)
+            .concat(
+                &data_collection
+                    .matching(&spo, None)
+                    .filter(move |triple| *triple.p() == spo),
+            )
+            .set_semantics();
+        let closure = hierarchy.iterate(|inner| {
+            let index = TripleIndex::new(inner);
+
+            hierarchy
+                .enter(&inner.scope())
+                .concat(&rule_1(&index, sco))
+                .concat(&rule_2(&index, spo))
+                .set_semantics()
+        });
+
+        let super_classes = closure
+            .filter(move |triple| *triple.p() == sco)
+            .map(|triple| (*triple.s(), *triple.o()));
+        let super_properties = closure
+            .filter(move |triple| *triple.p() == spo)
+            .map(|triple| (*triple.s(), *triple.o()));
+        let classes_of = |property: V| {
+            let declared = data_collection
+                .matching(&property, None)
+                .filter(move |triple| *triple.p() == property)
+                .map(|triple| (*triple.s(), *triple.o()));
+            let declared = super_properties
+                .map(|(p1, p)| (p, p1))
+                .join_map(&declared, |_p, p1, class| (*p1, *class))
+                .concat(&declared);
+
+            declared
+                .map(|(property, class)| (class, property))
+                .join_map(&super_classes, |_class, property, super_class| {
+                    (*property, *super_class)
+                })
+                .concat(&declared)
+                .map(move |(property_value, class)| T::from_spo(property_value, property, class))
+                .set_semantics()
+                .map(|triple| (*triple.s(), *triple.o()))
+        };
+        let domains = classes_of(domain).replicate("Domains");
+        let ranges = classes_of(range).replicate("Ranges");
+
+        Ok(Schema {
+            closure,
+            super_classes: super_classes.replicate("SuperClasses"),
+            super_properties: super_properties.replicate("SuperProperties"),
+            domains,
+            ranges,
+        })
+    }
+}
+
+/// The terms the rho-df rules refer to
+pub fn vocabulary() -> VocabularyDeclaration {
+    VocabularyDeclaration::new()
+        .with::<SubClassOf>()
+        .with::<SubPropertyOf>()
+        .with::<Type>()
+        .with::<Domain>()
+        .with::<Range>()
+}
+
+/// Computes the rho-df closure of the collection
+pub fn materialize<G, T, V>(
+    data_input: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+    evaluation: Evaluation,
+) -> Result<Collection<G, T>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    let typ = vocabulary.term::<Type>()?;
+    // What the rules derive can be read by them again when the data uses the vocabulary, e.g.
+    // a range declared on rdf:type types the classes of the derived types, so they are applied
+    // until they derive nothing new. The rounds after the first one only see the triples the
+    // previous round added, which are few unless the data does so.
+    let mut scope = data_input.scope();
+    scope.iterative::<u64, _, _>(|inner| {
+        let partition = |triples: &Collection<_, T>| {
+            if evaluation.partitioned {
+                Partitions::new(triples, vocabulary.properties(), &[])
+            } else {
+                Partitions::unpartitioned(triples)
+            }
+        };
+        let base = data_input.enter(inner);
+        let triples = Variable::new_from(base.clone(), Product::new(Default::default(), 1));
+        let partitions = partition(&triples);
+        let schema = Schema::new(&partitions, vocabulary)?;
+
+        // The rules that only read the schema look it up on the worker that holds each
+        // triple: no data is exchanged between workers until the results are deduplicated
+        let data = triples.concat(&schema.closure);
+        let by_predicate = data
+            .map(|triple| (*triple.p(), triple))
+            .arrange_locally("TriplesByPredicate");
+
+        // The domains and ranges already include the super classes of the types they assign,
+        // and rule_3 gives all the super classes of a type at once: only the types of the base
+        // facts and the ones that come from the super properties still need rule_3
+        let properties = rule_4(&by_predicate, &schema);
+        let types = partition(&base).matching(&typ, None).concat(&properties);
+        let derived = concatenate(
+            &mut data.scope(),
+            vec![
+                properties,
+                rule_3(&types, &schema, typ),
+                rule_5(&by_predicate, &schema, typ),
+                rule_6(&by_predicate, &schema, typ),
+            ],
+        );
+
+        let closure = base
+            .concat(&schema.closure)
+            .concat(&derived)
+            .set_semantics();
+        triples.set(&closure);
+        Ok(closure.leave())
+    })
+}
+
+/// The supports of the triples of `materialized`, the closure computed by [`materialize`]: every
+/// application of the six rules to its triples
+pub fn supports<G, T, V>(
+    materialized: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<Collection<G, (T, Support<T>)>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    let sco = vocabulary.term::<SubClassOf>()?;
+    let spo = vocabulary.term::<SubPropertyOf>()?;
+    let typ = vocabulary.term::<Type>()?;
+    let domain = vocabulary.term::<Domain>()?;
+    let range = vocabulary.term::<Range>()?;
+
+    let with = |property: V| materialized.filter(move |triple| *triple.p() == property);
+    let by_subject = |property: V| with(property).map(|triple| (*triple.s(), triple));
+    let by_predicate = materialized.map(|triple| (*triple.p(), triple));
+    let transitive = |property: V, rule: &'static str| {
+        with(property).map(|triple| (*triple.o(), triple)).join_map(
+            &by_subject(property),
+            move |_b, first, second| {
+                let derived = T::from_spo(*first.s(), *first.p(), *second.o());
+                (
+                    derived,
+                    Support::new(rule, vec![first.clone(), second.clone()]),
+                )
+            },
+        )
+    };
+
+    let rule_3 = by_subject(sco).join_map(
+        &with(typ).map(|triple| (*triple.o(), triple)),
+        move |_a, sco_triple, type_triple| {
+            let derived = T::from_spo(*type_triple.s(), typ, *sco_triple.o());
+            let premises = vec![sco_triple.clone(), type_triple.clone()];
+            (derived, Support::new("rule_3", premises))
+        },
+    );
+    let rule_4 = by_subject(spo).join_map(&by_predicate, |_p1, spo_triple, triple| {
+        let derived = T::from_spo(*triple.s(), *spo_triple.o(), *triple.o());
+        let premises = vec![spo_triple.clone(), triple.clone()];
+        (derived, Support::new("rule_4", premises))
+    });
+    let rule_5 = by_subject(domain).join_map(&by_predicate, move |_p, domain_triple, triple| {
+        let derived = T::from_spo(*triple.s(), typ, *domain_triple.o());
+        let premises = vec![domain_triple.clone(), triple.clone()];
+        (derived, Support::new("rule_5", premises))
+    });
+    let rule_6 = by_subject(range).join_map(&by_predicate, move |_p, range_triple, triple| {
+        let derived = T::from_spo(*triple.o(), typ, *range_triple.o());
+        let premises = vec![range_triple.clone(), triple.clone()];
+        (derived, Support::new("rule_6", premises))
+    });
+
+    Ok(concatenate(
+        &mut materialized.scope(),
+        vec![
+            transitive(sco, "rule_1"),
+            transitive(spo, "rule_2"),
+            rule_3,
+            rule_4,
+            rule_5,
+            rule_6,
+        ],
+    ))
+}
+
+/// The six rules, in the rule language
+pub fn rules() -> String {
+    String::from(
+        "
+        T(?a, SCO, ?c) <= T(?a, SCO, ?b), T(?b, SCO, ?c)
+        T(?a, SPO, ?c) <= T(?a, SPO, ?b), T(?b, SPO, ?c)
+        T(?x, TYPE, ?b) <= T(?a, SCO, ?b), T(?x, TYPE, ?a)
+        T(?x, ?p, ?y) <= T(?p1, SPO, ?p), T(?x, ?p1, ?y)
+        T(?a, TYPE, ?d) <= T(?p, DOMAIN, ?d), T(?a, ?p, ?b)
+        T(?b, TYPE, ?r) <= T(?p, RANGE, ?r), T(?a, ?p, ?b)
+        ",
+    )
+}
+
+/// The [`rules`] compiled against the rho-df vocabulary
+pub fn program<V>(vocabulary: &Vocabulary<V>) -> Result<CompiledProgram<V>, VocabularyError>
+where
+    V: ExchangeData + Hash + Copy,
+{
+    let terms = [
+        (RDFS_SUB_CLASS_OF, vocabulary.term::<SubClassOf>()?),
+        (RDFS_SUB_PROPERTY_OF, vocabulary.term::<SubPropertyOf>()?),
+        (RDF_TYPE, vocabulary.term::<Type>()?),
+        (RDFS_DOMAIN, vocabulary.term::<Domain>()?),
+        (RDFS_RANGE, vocabulary.term::<Range>()?),
+    ];
+    Ok(Program::parse(&rules())
+        .and_then(|program| {
+            program.compile(|iri| {
+                terms
+                    .iter()
+                    .find(|(keyword, _)| *keyword == iri)
+                    .map(|(_, encoded)| *encoded)
+            })
+        })
+        .expect("The rho-df rules only use the rho-df vocabulary"))
+}
+
+/// The closure computed by [`materialize`], on a set of triples held in memory: the [`rules`] are
+/// applied as they are written until they derive nothing new, see
+/// [`CompiledProgram::materialize_set`](crate::datalog::CompiledProgram::materialize_set)
+pub fn materialize_set<T, V>(
+    facts: HashSet<T>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<HashSet<T>, VocabularyError>
+where
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    Ok(program(vocabulary)?.materialize_set(facts))
+}
+
+/// Computes the full materialization of the collection and returns the trace of the result
+pub fn full_materialization<G, T, V>(
+    data_input: &Collection<G, T>,
+    probe: &mut ProbeHandle<G::Timestamp>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, G::Timestamp, isize>>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    let arrangement = materialize(data_input, vocabulary, Evaluation::default())?.arrange_by_self();
+
+    arrangement.stream.probe_with(probe);
+
+    Ok(arrangement.trace)
+}
+
+/// The rho-df profile, arranged and probed like the closure of
+/// [`Reasoner::materialization`](super::Reasoner::materialization). `run_materialization` runs
+/// it as:
+///
+/// ```ignore
+/// run_materialization(encoder, Reasoner::from(Profile::RhoDf))
+/// ```
+pub fn materialization<T, V>(
+    data_input: &Collection<MaterializationScope<'_>, T>,
+    probe: &mut ProbeHandle<usize>,
+    vocabulary: &Vocabulary<V>,
+) -> Result<TraceAgent<OrdKeySpine<T, usize, isize>>, VocabularyError>
+where
+    V: ExchangeData + Hashable + Hash + Copy,
+    T: ExchangeData + Hashable + Triple<V>,
+{
+    full_materialization(data_input, probe, vocabulary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{materialize, materialize_set, program, vocabulary};
+    use crate::datalog::{DRed, MagicSets, Term};
+    use crate::model::{
+        RDFS_DOMAIN, RDFS_RANGE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_TYPE,
+    };
+    use crate::reasoners::{Evaluation, Profile, Reasoner};
+    use crate::tests::{facts_after_batches, materialize_batches, TestTriple};
+    use crate::vocabulary::Vocabulary;
+    use differential_dataflow::input::Input;
+    use std::collections::{BTreeSet, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    const SCO: u64 = 0;
+    const SPO: u64 = 1;
+    const TYPE: u64 = 2;
+    const DOMAIN: u64 = 3;
+    const RANGE: u64 = 4;
+
+    fn resolved() -> Vocabulary<u64> {
+        Vocabulary::resolve(&vocabulary(), |iri| {
+            [
+                (RDFS_SUB_CLASS_OF, SCO),
+                (RDFS_SUB_PROPERTY_OF, SPO),
+                (RDF_TYPE, TYPE),
+                (RDFS_DOMAIN, DOMAIN),
+                (RDFS_RANGE, RANGE),
+            ]
+            .iter()
+            .find(|(keyword, _)| *keyword == iri)
+            .map(|(_, encoded)| *encoded)
+        })
+        .unwrap()
+    }
+
+    // The materialization after each batch on `workers` workers. The updates are fed by the
+    // first worker, so the schema is only replicated if the other workers read it from there.
+    fn materialized_on(
+        workers: usize,
+        batches: Vec<Vec<(TestTriple, isize)>>,
+        evaluation: Evaluation,
+    ) -> Vec<HashSet<TestTriple>> {
+        let times = batches.len();
+        let updates = Arc::new(Mutex::new(vec![]));
+        let sink = updates.clone();
+        timely::execute(timely::Configuration::Process(workers), move |worker| {
+            let sink = sink.clone();
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, data) = scope.new_collection::<TestTriple, isize>();
+                materialize(&data, &resolved(), evaluation)
+                    .unwrap()
+                    .inspect(move |update| sink.lock().unwrap().push(*update))
+                    .probe_with(&mut probe);
+                input
+            });
+            for (time, batch) in batches.iter().enumerate() {
+                if worker.index() == 0 {
+                    for (triple, diff) in batch {
+                        input.update(*triple, *diff);
+                    }
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+            }
+        })
+        .unwrap();
+
+        let updates = updates.lock().unwrap();
+        (0..times)
+            .map(|time| {
+                let mut counts = std::collections::HashMap::new();
+                for (triple, t, diff) in updates.iter() {
+                    if *t <= time {
+                        *counts.entry(*triple).or_insert(0) += diff;
+                    }
+                }
+                counts
+                    .into_iter()
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(triple, _)| triple)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn materializes_and_retracts() {
+        let (sco, spo, typ, domain, range) = (0, 1, 2, 3, 4);
+        let (student, person, takes, attends, course, x, y) = (10, 11, 12, 13, 14, 20, 21);
+
+        let batches = vec![
+            vec![
+                ((student, sco, person), 1),
+                ((takes, spo, attends), 1),
+                ((attends, domain, student), 1),
+                ((attends, range, course), 1),
+                ((x, takes, y), 1),
+            ],
+            vec![((takes, spo, attends), -1)],
+        ];
+        let vocabulary = Vocabulary::resolve(&vocabulary(), |iri| {
+            [
+                (RDFS_SUB_CLASS_OF, sco),
+                (RDFS_SUB_PROPERTY_OF, spo),
+                (RDF_TYPE, typ),
+                (RDFS_DOMAIN, domain),
+                (RDFS_RANGE, range),
+            ]
+            .iter()
+            .find(|(keyword, _)| *keyword == iri)
+            .map(|(_, encoded)| *encoded)
+        })
+        .unwrap();
+        let results = materialize_batches(batches, move |data| {
+            materialize(data, &vocabulary, Evaluation::default()).unwrap()
+        });
+
+        assert!(results[0].contains(&(x, attends, y)));
+        assert!(results[0].contains(&(x, typ, person)));
+        assert!(results[0].contains(&(y, typ, course)));
+        assert_eq!(results[0].len(), 9);
+        assert_eq!(results[1].len(), 4);
+    }
+
+    #[test]
+    fn agrees_with_the_set_materialization_through_deletions() {
+        let (graduate_student, student, person, professor) = (10, 11, 12, 13);
+        let (graduate_course, course, work) = (14, 15, 16);
+        let (takes_graduate, takes, advisor, knows) = (20, 21, 22, 23);
+        let (x1, x2, x3, p1, c1, c2) = (30, 31, 32, 33, 34, 35);
+
+        let schema = vec![
+            (graduate_student, SCO, student),
+            (student, SCO, person),
+            (professor, SCO, person),
+            (graduate_course, SCO, course),
+            (takes_graduate, SPO, takes),
+            (advisor, SPO, knows),
+            (takes, DOMAIN, student),
+            (takes, RANGE, course),
+            (takes_graduate, RANGE, graduate_course),
+            (advisor, DOMAIN, student),
+            (advisor, RANGE, professor),
+        ];
+        let batches: Vec<Vec<(TestTriple, isize)>> = vec![
+            schema
+                .into_iter()
+                .chain(vec![
+                    (x1, takes_graduate, c1),
+                    (x1, advisor, p1),
+                    (x1, TYPE, student),
+                    (x2, TYPE, graduate_student),
+                    (x3, takes, c2),
+                ])
+                .map(|triple| (triple, 1))
+                .collect(),
+            // An asserted type that is still derived, a new triple and a longer chain of classes
+            vec![
+                ((x1, TYPE, student), -1),
+                ((x2, takes, c1), 1),
+                ((course, SCO, work), 1),
+            ],
+            // The schema shrinks: the derived types and properties go with it
+            vec![
+                ((student, SCO, person), -1),
+                ((takes_graduate, SPO, takes), -1),
+            ],
+            vec![
+                ((student, SCO, person), 1),
+                ((x1, takes_graduate, c1), -1),
+                ((x1, advisor, p1), -1),
+            ],
+            vec![
+                ((x2, TYPE, graduate_student), -1),
+                ((x2, takes, c1), -1),
+                ((x3, takes, c2), -1),
+            ],
+        ];
+
+        let vocabulary = resolved();
+        let expected: Vec<_> = facts_after_batches(&batches)
+            .into_iter()
+            .map(|facts| materialize_set(facts, &vocabulary).unwrap())
+            .collect();
+        assert!(expected[0].contains(&(x1, TYPE, person)));
+        assert!(expected[0].contains(&(c1, TYPE, graduate_course)));
+        assert!(!expected[2].contains(&(x1, TYPE, person)));
+        assert!(!expected[2].contains(&(x1, takes, c1)));
+
+        for workers in [1, 3] {
+            for partitioned in [false, true] {
+                let evaluation = Evaluation {
+                    partitioned,
+                    ..Evaluation::default()
+                };
+                assert_eq!(
+                    materialized_on(workers, batches.clone(), evaluation),
+                    expected,
+                    "{} workers, partitioned: {}",
+                    workers,
+                    partitioned
+                );
+            }
+        }
+    }
+
+    const AGENT: u64 = 12;
+    const CLASS: u64 = 13;
+    const COURSE: u64 = 14;
+    const CLASSIFIED: u64 = 22;
+    const X: u64 = 30;
+
+    // Updates whose schema uses the vocabulary itself, so that what the rules derive is read by
+    // them again
+    fn on_the_vocabulary() -> Vec<Vec<(TestTriple, isize)>> {
+        let (student, person, takes, narrower, y) = (10, 11, 20, 21, 31);
+        vec![
+            vec![
+                ((student, SCO, person), 1),
+                ((takes, DOMAIN, student), 1),
+                ((takes, RANGE, COURSE), 1),
+                // The classes of the types are typed, including the derived ones
+                ((TYPE, RANGE, CLASS), 1),
+                // The types are also classifications
+                ((TYPE, SPO, CLASSIFIED), 1),
+                // A sub property of rdfs:subClassOf adds to the class hierarchy
+                ((narrower, SPO, SCO), 1),
+                ((person, narrower, AGENT), 1),
+                ((X, takes, y), 1),
+            ],
+            vec![((TYPE, RANGE, CLASS), -1)],
+            vec![((narrower, SPO, SCO), -1), ((TYPE, RANGE, CLASS), 1)],
+        ]
+    }
+
+    #[test]
+    fn reaches_the_fixpoint_of_the_rules_on_the_vocabulary() {
+        let batches = on_the_vocabulary();
+        let vocabulary = resolved();
+        let rules = program(&vocabulary).unwrap();
+        let expected: Vec<_> = facts_after_batches(&batches)
+            .into_iter()
+            .map(|facts| rules.materialize_set(facts))
+            .collect();
+        assert!(expected[0].contains(&(COURSE, TYPE, CLASS)));
+        assert!(expected[0].contains(&(AGENT, TYPE, CLASS)));
+        assert!(expected[0].contains(&(X, TYPE, AGENT)));
+        assert!(expected[0].contains(&(X, CLASSIFIED, AGENT)));
+        assert!(!expected[1].contains(&(COURSE, TYPE, CLASS)));
+        assert!(!expected[2].contains(&(X, TYPE, AGENT)));
+
+        for workers in [1, 3] {
+            for partitioned in [false, true] {
+                let evaluation = Evaluation {
+                    partitioned,
+                    ..Evaluation::default()
+                };
+                assert_eq!(
+                    materialized_on(workers, batches.clone(), evaluation),
+                    expected,
+                    "{} workers, partitioned: {}",
+                    workers,
+                    partitioned
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dred_maintains_the_same_materialization() {
+        let batches = on_the_vocabulary();
+        let rules = Reasoner::from(Profile::RhoDf)
+            .program(&resolved())
+            .unwrap()
+            .unwrap();
+        let mut dred = DRed::new(rules).unwrap();
+        let maintained: Vec<HashSet<TestTriple>> = batches
+            .iter()
+            .map(|batch| {
+                let inserted = batch.iter().filter(|(_, diff)| *diff > 0);
+                let deleted = batch.iter().filter(|(_, diff)| *diff < 0);
+                dred.update(
+                    inserted.map(|(triple, _)| *triple),
+                    deleted.map(|(triple, _)| *triple),
+                );
+                dred.materialized().copied().collect()
+            })
+            .collect();
+        assert!(maintained[0].contains(&(COURSE, TYPE, CLASS)));
+
+        for workers in [1, 3] {
+            assert_eq!(
+                materialized_on(workers, batches.clone(), Evaluation::default()),
+                maintained,
+                "{} workers",
+                workers
+            );
+        }
+    }
+
+    #[test]
+    fn magic_sets_answer_as_the_dataflow() {
+        let batches = on_the_vocabulary();
+        let rules = program(&resolved()).unwrap();
+        let materialized = materialize_batches(batches.clone(), |data| {
+            materialize(data, &resolved(), Evaluation::default()).unwrap()
+        });
+        let queries = vec![
+            [
+                Term::Variable(0),
+                Term::Constant(TYPE),
+                Term::Constant(CLASS),
+            ],
+            [Term::Constant(X), Term::Variable(0), Term::Constant(AGENT)],
+            [
+                Term::Variable(0),
+                Term::Constant(CLASSIFIED),
+                Term::Variable(1),
+            ],
+        ];
+        for query in queries {
+            let magic = MagicSets::new(rules.clone(), query.clone()).unwrap();
+            let answers = materialize_batches(batches.clone(), move |data| magic.answer(data));
+            for (answers, materialized) in answers.into_iter().zip(&materialized) {
+                let expected: BTreeSet<TestTriple> = materialized
+                    .iter()
+                    .filter(|(s, p, o)| {
+                        query.iter().zip([s, p, o]).all(|(term, value)| match term {
+                            Term::Constant(c) => c == value,
+                            Term::Variable(_) => true,
+                        })
+                    })
+                    .copied()
+                    .collect();
+                assert_eq!(answers, expected, "{:?}", query);
+            }
+        }
+        assert!(materialized[0].contains(&(COURSE, TYPE, CLASS)));
+        assert!(materialized[0].contains(&(X, CLASSIFIED, AGENT)));
+    }
+}
@@ -0,0 +1,340 @@
+//! `owl:sameAs` through canonical representatives.
+//!
+//! Copying every triple to every alias of its terms makes the materialization grow with the
+//! product of the sizes of the equivalence classes. Instead, the `owl:sameAs` links of the input
+//! are turned into equivalence classes (the connected components of the links, maintained
+//! incrementally), every term is replaced by the representative of its class, the smallest
+//! encoding in it, and the rules of a profile run on the rewritten triples only.
+//!
+//! The output holds the canonical triples together with one `T(alias, sameAs, representative)`
+//! triple per alias. [`Aliases`] reads the latter back to expand the output when requested.
+//!
+//! Removing a link recomputes the components it belonged to, so a class split by a deletion gets
+//! a representative per part.
+use crate::encoder::Triple;
+use crate::vocabulary::{SameAs, Vocabulary, VocabularyDeclaration, VocabularyError};
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::iterate::Iterate;
+use differential_dataflow::operators::join::Join;
+use differential_dataflow::operators::reduce::Reduce;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::trace::{cursor::Cursor, TraceReader};
+use differential_dataflow::{Collection, ExchangeData};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use timely::dataflow::Scope;
+use timely::order::PartialOrder;
+
+/// The terms the canonicalization refers to
+pub fn vocabulary() -> VocabularyDeclaration {
+    VocabularyDeclaration::new().with::<SameAs>()
+}
+
+/// The representative of every term that appears in `links`, as (term, representative): the
+/// smallest term of its connected component, propagated along the links.
+pub fn representatives<G, V>(links: &Collection<G, (V, V)>) -> Collection<G, (V, V)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+{
+    let edges = links.concat(&links.map(|(a, b)| (b, a)));
+    let nodes = edges.map(|(node, _)| (node, node));
+
+    nodes.iterate(|labels| {
+        let edges = edges.enter(&labels.scope());
+        let nodes = nodes.enter(&labels.scope());
+        labels
+            .join_map(&edges, |_, &label, &next| (next, label))
+            .concat(&nodes)
+            // The values come sorted, the first one is the smallest label
+            .reduce(|_, input, output| output.push((*input[0].0, 1)))
+    })
+}
+
+fn term<T: Triple<V>, V: Copy>(triple: &T, position: usize) -> V {
+    match position {
+        0 => *triple.s(),
+        1 => *triple.p(),
+        _ => *triple.o(),
+    }
+}
+
+fn replace<T: Triple<V>, V: Copy>(triple: &T, position: usize, value: V) -> T {
+    match position {
+        0 => T::from_spo(value, *triple.p(), *triple.o()),
+        1 => T::from_spo(*triple.s(), value, *triple.o()),
+        _ => T::from_spo(*triple.s(), *triple.p(), value),
+    }
+}
+
+/// Replaces every term of `triples` that appears in `aliases`, pairs of (alias, representative),
+/// with its representative.
+pub fn canonicalize<G, T, V>(
+    triples: &Collection<G, T>,
+    aliases: &Collection<G, (V, V)>,
+) -> Collection<G, T>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+{
+    let aliased = aliases.map(|(alias, _)| alias);
+    let mut triples = triples.clone();
+    for position in 0..3 {
+        let keyed = triples.map(move |triple| (term(&triple, position), triple));
+        let rewritten = keyed.join_map(aliases, move |_, triple, &representative| {
+            replace(triple, position, representative)
+        });
+        let untouched = keyed.antijoin(&aliased).map(|(_, triple)| triple);
+        triples = rewritten.concat(&untouched);
+    }
+    triples
+}
+
+/// Runs `materialize` on the canonical form of `data_input` and adds the alias triples to the
+/// result.
+pub fn materialize<G, T, V, F>(
+    data_input: &Collection<G, T>,
+    vocabulary: &Vocabulary<V>,
+    materialize: F,
+) -> Result<Collection<G, T>, VocabularyError>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + Hash + Copy,
+    T: ExchangeData + Hash + Triple<V>,
+    F: FnOnce(&Collection<G, T>) -> Result<Collection<G, T>, VocabularyError>,
+{
+    let same_as = vocabulary.term::<SameAs>()?;
+
+    let links = data_input
+        .filter(move |triple| *triple.p() == same_as && *triple.s() != *triple.o())
+        .map(|triple| (*triple.s(), *triple.o()));
+    let aliases = representatives(&links).filter(|(term, representative)| term != representative);
+
+    let others = data_input.filter(move |triple| *triple.p() != same_as);
+    let closure = materialize(&canonicalize(&others, &aliases))?;
+
+    Ok(closure.concat(
+        &aliases.map(move |(alias, representative)| T::from_spo(alias, same_as, representative)),
+    ))
+}
+
+/// The result of [`materialize`], on a set of triples held in memory. The classes are the
+/// connected components of the links, found by a traversal from each term.
+pub fn materialize_set<T, V, F>(
+    facts: HashSet<T>,
+    vocabulary: &Vocabulary<V>,
+    materialize: F,
+) -> Result<HashSet<T>, VocabularyError>
+where
+    V: Ord + Hash + Copy,
+    T: Eq + Hash + Triple<V>,
+    F: FnOnce(HashSet<T>) -> Result<HashSet<T>, VocabularyError>,
+{
+    let same_as = vocabulary.term::<SameAs>()?;
+
+    let mut neighbours: HashMap<V, Vec<V>> = HashMap::new();
+    for triple in &facts {
+        if *triple.p() == same_as && *triple.s() != *triple.o() {
+            neighbours.entry(*triple.s()).or_default().push(*triple.o());
+            neighbours.entry(*triple.o()).or_default().push(*triple.s());
+        }
+    }
+    // (term, representative) for every term of a class but its smallest one
+    let mut representatives: HashMap<V, V> = HashMap::new();
+    let mut visited: HashSet<V> = HashSet::new();
+    for &start in neighbours.keys() {
+        if !visited.insert(start) {
+            continue;
+        }
+        let mut class = vec![start];
+        let mut next = 0;
+        while next < class.len() {
+            for &neighbour in &neighbours[&class[next]] {
+                if visited.insert(neighbour) {
+                    class.push(neighbour);
+                }
+            }
+            next += 1;
+        }
+        let representative = *class.iter().min().expect("A class holds its first term");
+        for term in class.into_iter().filter(|term| *term != representative) {
+            representatives.insert(term, representative);
+        }
+    }
+
+    let canonical = |term: V| representatives.get(&term).copied().unwrap_or(term);
+    let others = facts
+        .iter()
+        .filter(|triple| *triple.p() != same_as)
+        .map(|triple| {
+            T::from_spo(
+                canonical(*triple.s()),
+                canonical(*triple.p()),
+                canonical(*triple.o()),
+            )
+        })
+        .collect();
+    let mut closure = materialize(others)?;
+
+    closure.extend(
+        representatives
+            .iter()
+            .map(|(alias, representative)| T::from_spo(*alias, same_as, *representative)),
+    );
+    Ok(closure)
+}
+
+/// The equivalence classes found in the output of [`materialize`], by representative.
+#[derive(Debug, Clone)]
+pub struct Aliases<V> {
+    same_as: V,
+    classes: HashMap<V, Vec<V>>,
+}
+
+impl<V: Copy + Eq + Hash> Aliases<V> {
+    /// No aliases
+    pub fn new(same_as: V) -> Self {
+        Self {
+            same_as,
+            classes: HashMap::new(),
+        }
+    }
+
+    /// Records `alias` in the class of `representative`
+    pub fn insert(&mut self, alias: V, representative: V) {
+        self.classes.entry(representative).or_default().push(alias);
+    }
+
+    /// Reads the alias triples present in `trace` at `time`, with the same contract as
+    /// `save_to_file_through_trace`.
+    pub fn from_trace<T>(
+        trace: &mut TraceAgent<OrdKeySpine<T, usize, isize>>,
+        time: usize,
+        same_as: V,
+    ) -> Self
+    where
+        T: Triple<V> + Ord + Clone + 'static,
+    {
+        let mut aliases = Self::new(same_as);
+        if let Some((mut cursor, storage)) = trace.cursor_through(&[time]) {
+            while let Some(key) = cursor.get_key(&storage) {
+                let mut count = 0;
+                cursor.map_times(&storage, |t, diff| {
+                    if t.less_equal(&(time - 1)) {
+                        count += diff;
+                    }
+                });
+                if count > 0 && *key.p() == same_as {
+                    aliases.insert(*key.s(), *key.o());
+                }
+                cursor.step_key(&storage);
+            }
+        }
+        aliases
+    }
+
+    fn class(&self, term: V) -> impl Iterator<Item = V> + '_ {
+        std::iter::once(term).chain(self.classes.get(&term).into_iter().flatten().copied())
+    }
+
+    /// Every triple that `triple`, taken from the output of [`materialize`], stands for.
+    pub fn expand<T: Triple<V>>(&self, triple: &T) -> Vec<(V, V, V)> {
+        let (s, p, o) = (*triple.s(), *triple.p(), *triple.o());
+        if p == self.same_as {
+            // T(alias, sameAs, representative) stands for the links between the alias and the
+            // rest of its class, so that each link is written once.
+            let mut expanded = vec![(o, p, s)];
+            expanded.extend(
+                self.class(o)
+                    .filter(|&other| other != s)
+                    .map(|other| (s, p, other)),
+            );
+            expanded
+        } else {
+            let mut expanded = vec![];
+            for s in self.class(s) {
+                for p in self.class(p) {
+                    for o in self.class(o) {
+                        expanded.push((s, p, o));
+                    }
+                }
+            }
+            expanded
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{materialize, vocabulary, Aliases};
+    use crate::reasoners::{rho_df, Evaluation};
+    use crate::tests::materialize_batches;
+    use crate::vocabulary::{SameAs, Vocabulary};
+
+    #[test]
+    fn canonicalizes_and_splits_classes() {
+        let declaration = vocabulary().merge(&rho_df::vocabulary());
+        let iris: Vec<String> = declaration
+            .terms()
+            .iter()
+            .map(|(_, iri)| iri.clone())
+            .collect();
+        let encoding = |iri: &str| iris.iter().position(|known| known == iri).map(|i| i as u64);
+        let vocabulary = Vocabulary::resolve(&declaration, encoding).unwrap();
+        let same_as = vocabulary.term::<SameAs>().unwrap();
+        let typ = vocabulary.get("rdf:type").unwrap();
+        let sco = vocabulary.get("rdfs:subClassOf").unwrap();
+
+        let (a, b, c, student, person) = (10, 11, 12, 20, 21);
+        let batches = vec![
+            vec![
+                ((c, same_as, b), 1),
+                ((b, same_as, a), 1),
+                ((c, typ, student), 1),
+                ((student, sco, person), 1),
+            ],
+            vec![((b, same_as, a), -1)],
+        ];
+        let results = materialize_batches(batches, move |data| {
+            materialize(data, &vocabulary, |data| {
+                rho_df::materialize(data, &vocabulary, Evaluation::default())
+            })
+            .unwrap()
+        });
+
+        assert!(results[0].contains(&(a, typ, person)));
+        assert!(results[0].contains(&(b, same_as, a)));
+        assert!(results[0].contains(&(c, same_as, a)));
+        assert!(!results[0].contains(&(c, typ, person)));
+        assert_eq!(results[0].len(), 5);
+
+        assert!(results[1].contains(&(b, typ, person)));
+        assert!(results[1].contains(&(c, same_as, b)));
+        assert!(!results[1].contains(&(b, same_as, a)));
+        assert_eq!(results[1].len(), 4);
+
+        let mut aliases = Aliases::new(same_as);
+        aliases.insert(b, a);
+        aliases.insert(c, a);
+        assert_eq!(aliases.expand(&(a, typ, person)).len(), 3);
+        let mut links = aliases.expand(&(b, same_as, a));
+        links.extend(aliases.expand(&(c, same_as, a)));
+        links.sort();
+        assert_eq!(
+            links,
+            vec![
+                (a, same_as, b),
+                (a, same_as, c),
+                (b, same_as, a),
+                (b, same_as, c),
+                (c, same_as, a),
+                (c, same_as, b)
+            ]
+        );
+    }
+}
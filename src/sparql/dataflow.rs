@@ -0,0 +1,415 @@
+use crate::datalog::builtins::{holds, Value};
+use crate::datalog::dataflow::{atom_variables, match_atom};
+use crate::datalog::{Operand, Term};
+use crate::encoder::Triple;
+use crate::model::Literal;
+use crate::sparql::query::{CompiledExpression, CompiledPattern};
+use crate::sparql::CompiledQuery;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::join::Join;
+use differential_dataflow::operators::reduce::Threshold;
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
+use std::collections::HashMap;
+use timely::dataflow::operators::ToStream;
+use timely::dataflow::Scope;
+
+/// A solution: the value of every variable of the query, by index, `None` when it is unbound
+pub(crate) type Solution<V> = Vec<Option<V>>;
+
+// The solutions of a graph pattern, with the variables that every one of them binds
+struct Solutions<G: Scope, V> {
+    certain: Vec<usize>,
+    collection: Collection<G, Solution<V>>,
+}
+
+// Two solutions are compatible when they agree on the variables they both bind, their merge
+// binds the variables of both
+fn merge<V: Clone + Eq>(left: &[Option<V>], right: &[Option<V>]) -> Option<Solution<V>> {
+    left.iter()
+        .zip(right.iter())
+        .map(|pair| match pair {
+            (Some(a), Some(b)) if a != b => None,
+            (Some(a), _) => Some(Some(a.clone())),
+            (None, b) => Some(b.clone()),
+        })
+        .collect()
+}
+
+// Keys the solutions by the values of `shared`, which they all bind
+fn keyed<G, V>(
+    solutions: &Solutions<G, V>,
+    shared: &[usize],
+) -> Collection<G, (Vec<V>, Solution<V>)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData,
+{
+    let shared = shared.to_vec();
+    solutions.collection.map(move |solution| {
+        let key = shared
+            .iter()
+            .map(|&v| solution[v].clone().expect("Shared variables are bound"))
+            .collect();
+        (key, solution)
+    })
+}
+
+fn shared(left: &[usize], right: &[usize]) -> Vec<usize> {
+    left.iter().filter(|v| right.contains(v)).copied().collect()
+}
+
+// The merges of the compatible solutions of both sides
+fn join<G, V>(
+    left: &Solutions<G, V>,
+    right: &Solutions<G, V>,
+) -> Collection<G, (Solution<V>, Solution<V>)>
+where
+    G: Scope,
+    G::Timestamp: Lattice,
+    V: ExchangeData + std::hash::Hash,
+{
+    let shared = shared(&left.certain, &right.certain);
+    keyed(left, &shared)
+        .join_map(&keyed(right, &shared), |_, l, r| {
+            merge(l, r).map(|merged| (l.clone(), merged))
+        })
+        .flat_map(|pair| pair)
+}
+
+fn value<'a, V>(
+    operand: &'a Operand<V>,
+    solution: &'a [Option<V>],
+    literals: &'a HashMap<V, Literal>,
+) -> Option<Value<'a, V>>
+where
+    V: Eq + std::hash::Hash,
+{
+    let term = match operand {
+        Operand::Literal(literal) => return Some(Value::Literal(literal)),
+        Operand::Term(Term::Variable(v)) => solution.get(*v)?.as_ref()?,
+        Operand::Term(Term::Constant(c)) => c,
+    };
+    Some(literals.get(term).map_or(Value::Term(term), Value::Literal))
+}
+
+// Whether the solution satisfies the expression. A variable past the solution only appears in
+// filters and is never bound.
+fn satisfies<V>(
+    expression: &CompiledExpression<V>,
+    solution: &[Option<V>],
+    literals: &HashMap<V, Literal>,
+) -> bool
+where
+    V: Eq + std::hash::Hash,
+{
+    match expression {
+        CompiledExpression::Or(left, right) => {
+            satisfies(left, solution, literals) || satisfies(right, solution, literals)
+        }
+        CompiledExpression::And(left, right) => {
+            satisfies(left, solution, literals) && satisfies(right, solution, literals)
+        }
+        CompiledExpression::Not(inner) => !satisfies(inner, solution, literals),
+        CompiledExpression::Bound(v) => solution.get(*v).is_some_and(Option::is_some),
+        CompiledExpression::Compare(predicate, [left, right], pattern) => {
+            match (
+                value(left, solution, literals),
+                value(right, solution, literals),
+            ) {
+                (Some(left), Some(right)) => holds(*predicate, left, right, pattern.as_ref()),
+                _ => false,
+            }
+        }
+    }
+}
+
+impl<V> CompiledQuery<V>
+where
+    V: ExchangeData + std::hash::Hash,
+{
+    fn evaluate<G, T>(
+        &self,
+        pattern: &CompiledPattern<V>,
+        facts: &Collection<G, T>,
+    ) -> Solutions<G, V>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + Triple<V>,
+    {
+        let width = self.width();
+        match pattern {
+            // The empty pattern has a single solution, which binds nothing
+            CompiledPattern::Bgp(atoms) if atoms.is_empty() => {
+                let mut scope = facts.scope();
+                let first = scope.index() == 0;
+                Solutions {
+                    certain: vec![],
+                    collection: Some((vec![None; width], Default::default(), 1))
+                        .filter(|_| first)
+                        .to_stream(&mut scope)
+                        .as_collection(),
+                }
+            }
+            CompiledPattern::Bgp(atoms) => atoms
+                .iter()
+                .map(|atom| {
+                    let atom = atom.clone();
+                    let variables = atom_variables(&atom);
+                    Solutions {
+                        certain: variables.clone(),
+                        collection: facts.flat_map(move |triple| {
+                            match_atom(&atom, &triple).map(|values| {
+                                let mut solution = vec![None; width];
+                                for (&v, value) in variables.iter().zip(values) {
+                                    solution[v] = Some(value);
+                                }
+                                solution
+                            })
+                        }),
+                    }
+                })
+                .reduce(|left, right| Solutions {
+                    certain: union(&left.certain, &right.certain),
+                    collection: join(&left, &right).map(|(_, merged)| merged),
+                })
+                .expect("The basic graph pattern is not empty"),
+            CompiledPattern::Join(left, right) => {
+                let (left, right) = (self.evaluate(left, facts), self.evaluate(right, facts));
+                Solutions {
+                    certain: union(&left.certain, &right.certain),
+                    collection: join(&left, &right).map(|(_, merged)| merged),
+                }
+            }
+            // The extended solutions of the left side that satisfy the filters of the optional
+            // group, and the ones that have no such extension
+            CompiledPattern::LeftJoin(left, right, expression) => {
+                let (left, right) = (self.evaluate(left, facts), self.evaluate(right, facts));
+                let expression = expression.clone();
+                let literals = self.literals.clone();
+                let pairs = join(&left, &right).filter(move |(_, merged)| {
+                    expression
+                        .as_ref()
+                        .is_none_or(|expression| satisfies(expression, merged, &literals))
+                });
+                let matched = pairs.map(|(solution, _)| solution).distinct();
+                let unmatched = left
+                    .collection
+                    .map(|solution| (solution, ()))
+                    .antijoin(&matched)
+                    .map(|(solution, ())| solution);
+                Solutions {
+                    certain: left.certain,
+                    collection: pairs.map(|(_, merged)| merged).concat(&unmatched),
+                }
+            }
+            CompiledPattern::Union(left, right) => {
+                let (left, right) = (self.evaluate(left, facts), self.evaluate(right, facts));
+                Solutions {
+                    certain: shared(&left.certain, &right.certain),
+                    collection: left.collection.concat(&right.collection),
+                }
+            }
+            CompiledPattern::Filter(inner, expression) => {
+                let inner = self.evaluate(inner, facts);
+                let expression = expression.clone();
+                let literals = self.literals.clone();
+                Solutions {
+                    certain: inner.certain,
+                    collection: inner
+                        .collection
+                        .filter(move |solution| satisfies(&expression, solution, &literals)),
+                }
+            }
+        }
+    }
+
+    /// The solutions of the query on `facts`, with the values of the selected variables in the
+    /// order of [`CompiledQuery::variables`]. The solutions follow the updates of the facts.
+    pub fn solutions<G, T>(&self, facts: &Collection<G, T>) -> Collection<G, Solution<V>>
+    where
+        G: Scope,
+        G::Timestamp: Lattice,
+        T: ExchangeData + Triple<V>,
+    {
+        let selected = self.variables().len();
+        let solutions = self
+            .evaluate(&self.pattern, facts)
+            .collection
+            .map(move |mut solution| {
+                solution.truncate(selected);
+                solution
+            });
+        if self.distinct() {
+            solutions.distinct()
+        } else {
+            solutions
+        }
+    }
+}
+
+fn union(left: &[usize], right: &[usize]) -> Vec<usize> {
+    let mut variables = left.to_vec();
+    variables.extend(right.iter().filter(|v| !left.contains(v)));
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Literal;
+    use crate::sparql::Query;
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::consolidate::Consolidate;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, HashMap};
+    use std::rc::Rc;
+
+    #[test]
+    fn evaluates_patterns_through_updates() {
+        let terms = [
+            "<a>",
+            "<b>",
+            "<c>",
+            "<type>",
+            "<Student>",
+            "<Professor>",
+            "<advisor>",
+            "<age>",
+            "\"17\"^^<http://www.w3.org/2001/XMLSchema#integer>",
+            "\"30\"^^<http://www.w3.org/2001/XMLSchema#integer>",
+        ];
+        let encoding: HashMap<&str, u64> = terms.iter().zip(0..).map(|(t, e)| (*t, e)).collect();
+        let e = |term: &str| encoding[term];
+        let literals: HashMap<u64, Literal> = terms
+            .iter()
+            .filter_map(|term| Literal::parse(term).map(|literal| (e(term), literal)))
+            .collect();
+        let (a, b, c) = (e("<a>"), e("<b>"), e("<c>"));
+        let (ty, student, professor) = (e("<type>"), e("<Student>"), e("<Professor>"));
+        let (advisor, age) = (e("<advisor>"), e("<age>"));
+        let (young, old) = (e(terms[8]), e(terms[9]));
+        let batches = vec![
+            vec![
+                ((a, ty, student), 1),
+                ((b, ty, student), 1),
+                ((c, ty, professor), 1),
+                ((a, advisor, c), 1),
+                ((a, age, young), 1),
+                ((b, age, old), 1),
+                ((c, age, old), 1),
+            ],
+            vec![
+                ((a, advisor, c), -1),
+                ((b, advisor, c), 1),
+                ((a, age, young), -1),
+            ],
+        ];
+
+        let solve = |query: &str| {
+            let compiled = Query::parse(query)
+                .unwrap()
+                .compile(|term| encoding.get(term).copied())
+                .unwrap()
+                .with_literals(literals.clone());
+            let batches = batches.clone();
+            timely::execute_directly(move |worker| {
+                let updates = Rc::new(RefCell::new(vec![]));
+                let sink = updates.clone();
+                let mut probe = timely::dataflow::ProbeHandle::new();
+                let mut input = worker.dataflow::<usize, _, _>(|scope| {
+                    let (input, facts) = scope.new_collection::<(u64, u64, u64), isize>();
+                    compiled
+                        .solutions(&facts)
+                        .consolidate()
+                        .inspect(move |update| sink.borrow_mut().push(update.clone()))
+                        .probe_with(&mut probe);
+                    input
+                });
+                for (time, batch) in batches.iter().enumerate() {
+                    for (triple, diff) in batch {
+                        input.update(*triple, *diff);
+                    }
+                    input.advance_to(time + 1);
+                    input.flush();
+                    while probe.less_than(input.time()) {
+                        worker.step();
+                    }
+                }
+                let updates = updates.borrow();
+                (0..batches.len())
+                    .map(|time| {
+                        let mut counts = BTreeMap::new();
+                        for (solution, t, diff) in updates.iter() {
+                            if *t <= time {
+                                *counts.entry(solution.clone()).or_insert(0) += diff;
+                            }
+                        }
+                        counts.retain(|_, count| *count != 0);
+                        counts
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let rows =
+            |rows: &[(Vec<Option<u64>>, isize)]| rows.iter().cloned().collect::<BTreeMap<_, _>>();
+
+        let students =
+            solve("SELECT ?x ?a WHERE { ?x <type> <Student> OPTIONAL { ?x <advisor> ?a } }");
+        assert_eq!(
+            students,
+            vec![
+                rows(&[(vec![Some(a), Some(c)], 1), (vec![Some(b), None], 1)]),
+                rows(&[(vec![Some(a), None], 1), (vec![Some(b), Some(c)], 1)]),
+            ]
+        );
+
+        let people = solve(
+            "SELECT ?x WHERE { { ?x <type> <Student> } UNION { ?x <type> <Professor> } \
+             ?x <age> ?n FILTER (?n > 18) }",
+        );
+        assert_eq!(
+            people,
+            vec![
+                rows(&[(vec![Some(b)], 1), (vec![Some(c)], 1)]),
+                rows(&[(vec![Some(b)], 1), (vec![Some(c)], 1)]),
+            ]
+        );
+
+        let types = solve("SELECT ?t WHERE { ?x <type> ?t }");
+        assert_eq!(
+            types[0],
+            rows(&[(vec![Some(student)], 2), (vec![Some(professor)], 1)])
+        );
+        let types = solve("SELECT DISTINCT ?t WHERE { ?x <type> ?t }");
+        assert_eq!(
+            types[0],
+            rows(&[(vec![Some(student)], 1), (vec![Some(professor)], 1)])
+        );
+
+        let unadvised = solve(
+            "SELECT ?x WHERE { ?x <type> <Student> OPTIONAL { ?x <advisor> ?a } FILTER (!bound(?a)) }",
+        );
+        assert_eq!(
+            unadvised,
+            vec![rows(&[(vec![Some(b)], 1)]), rows(&[(vec![Some(a)], 1)])]
+        );
+
+        // The filter of the optional group sees ?z, bound outside of it
+        let older_advisors = solve(
+            "SELECT ?x ?y WHERE { ?x <age> ?z \
+             OPTIONAL { ?x <advisor> ?a . ?a <age> ?y FILTER (?y > ?z) } }",
+        );
+        assert_eq!(
+            older_advisors,
+            vec![
+                rows(&[
+                    (vec![Some(a), Some(old)], 1),
+                    (vec![Some(b), None], 1),
+                    (vec![Some(c), None], 1),
+                ]),
+                rows(&[(vec![Some(b), None], 1), (vec![Some(c), None], 1)]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! SPARQL SELECT queries over the materialization.
+//!
+//! The supported subset is the SELECT form with basic graph patterns, `OPTIONAL`, `UNION`,
+//! `FILTER`, `DISTINCT` and `LIMIT`, with `PREFIX` declarations and the `a` shorthand:
+//!
+//! ```text
+//! PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
+//! PREFIX ub: <http://swat.cse.lehigh.edu/onto/univ-bench.owl#>
+//! SELECT DISTINCT ?x ?email WHERE {
+//!     { ?x a ub:FullProfessor } UNION { ?x a ub:AssociateProfessor }
+//!     ?x ub:worksFor <http://www.Department0.University0.edu> .
+//!     OPTIONAL { ?x ub:emailAddress ?email }
+//!     FILTER (!bound(?email) || contains(?email, "Professor0"))
+//! } LIMIT 10
+//! ```
+//!
+//! The filters are the comparisons and string functions of the rules, `bound` and the boolean
+//! connectives, with the same semantics: numeric literals compare by value, string literals by
+//! lexical form and any other term is only equal to itself. A comparison with an unbound
+//! variable is false. A FILTER applies to the whole group it is written in. The filters of an
+//! `OPTIONAL` group are evaluated on the solutions it extends, so they can compare the variables
+//! bound before it with the ones it binds.
+//!
+//! A parsed [`Query`] is compiled against the encoding of its constants into a
+//! [`CompiledQuery`], whose graph pattern becomes joins, antijoins and concatenations over the
+//! collection of materialized triples. Its solutions are a collection like any other, so they
+//! follow the updates of the materialization. The LIMIT is applied when the solutions are read.
+
+mod dataflow;
+
+mod parser;
+pub use parser::parse_pattern;
+pub use parser::parse_query;
+
+mod query;
+pub use query::CompiledQuery;
+pub use query::Expression;
+pub use query::GraphPattern;
+pub use query::Query;
+pub use query::QueryTerm;
+
+/// Errors raised while parsing or compiling a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SparqlError {
+    /// The text is not a valid query
+    Parse(String),
+    /// A constant of the query has no encoding
+    UnresolvedConstant(String),
+    /// A filter can't be evaluated, e.g. its regular expression is invalid
+    InvalidFilter {
+        /// The filter
+        filter: String,
+        /// What is wrong with it
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for SparqlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SparqlError::Parse(message) => write!(f, "Could not parse the query: {}", message),
+            SparqlError::UnresolvedConstant(constant) => {
+                write!(f, "Constant {} has no encoding", constant)
+            }
+            SparqlError::InvalidFilter { filter, reason } => {
+                write!(f, "Invalid filter `{}`: {}", filter, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SparqlError {}
@@ -0,0 +1,305 @@
+use crate::model::BuiltinPredicate;
+use crate::sparql::{Expression, GraphPattern, Query, QueryTerm, SparqlError};
+use std::collections::HashMap;
+
+lalrpop_mod!(sparql, "/sparql/sparql.rs");
+
+// A term as it is written, before the prefixed names are expanded
+#[derive(Debug, Clone)]
+pub enum RawTerm {
+    Variable(String),
+    Iri(String),
+    Prefixed(String),
+    // The literal and the prefixed name of its datatype, if it is written `"..."^^prefix:name`
+    Literal(String, Option<String>),
+}
+
+pub type RawTriple = [RawTerm; 3];
+
+pub enum RawExpression {
+    Or(Box<RawExpression>, Box<RawExpression>),
+    And(Box<RawExpression>, Box<RawExpression>),
+    Not(Box<RawExpression>),
+    Compare(BuiltinPredicate, RawTerm, RawTerm),
+    Bound(String),
+}
+
+// An element of a group graph pattern, in the order it is written
+pub enum RawElement {
+    Triples(Vec<RawTriple>),
+    Optional(Vec<RawElement>),
+    Union(Vec<Vec<RawElement>>),
+    Filter(RawExpression),
+}
+
+pub struct RawQuery {
+    pub prefixes: Vec<(String, String)>,
+    pub distinct: bool,
+    pub projection: Option<Vec<String>>,
+    pub pattern: Vec<RawElement>,
+    pub limit: Option<String>,
+}
+
+fn expand(prefixes: &HashMap<String, String>, name: &str) -> Result<String, SparqlError> {
+    let (prefix, local) = name.split_at(name.find(':').expect("A prefixed name has a colon") + 1);
+    let namespace = prefixes
+        .get(prefix)
+        .ok_or_else(|| SparqlError::Parse(format!("Prefix {} is not declared", prefix)))?;
+    Ok(format!("{}{}>", &namespace[..namespace.len() - 1], local))
+}
+
+fn term(prefixes: &HashMap<String, String>, term: &RawTerm) -> Result<QueryTerm, SparqlError> {
+    Ok(match term {
+        RawTerm::Variable(v) => QueryTerm::Variable(v.clone()),
+        RawTerm::Iri(iri) => QueryTerm::Constant(iri.clone()),
+        RawTerm::Prefixed(name) => QueryTerm::Constant(expand(prefixes, name)?),
+        RawTerm::Literal(literal, None) => QueryTerm::Constant(literal.clone()),
+        RawTerm::Literal(literal, Some(datatype)) => {
+            QueryTerm::Constant(format!("{}^^{}", literal, expand(prefixes, datatype)?))
+        }
+    })
+}
+
+fn expression(
+    prefixes: &HashMap<String, String>,
+    raw: &RawExpression,
+) -> Result<Expression, SparqlError> {
+    let boxed = |raw: &RawExpression| expression(prefixes, raw).map(Box::new);
+    Ok(match raw {
+        RawExpression::Or(left, right) => Expression::Or(boxed(left)?, boxed(right)?),
+        RawExpression::And(left, right) => Expression::And(boxed(left)?, boxed(right)?),
+        RawExpression::Not(inner) => Expression::Not(boxed(inner)?),
+        RawExpression::Compare(predicate, left, right) => {
+            Expression::Compare(*predicate, term(prefixes, left)?, term(prefixes, right)?)
+        }
+        RawExpression::Bound(v) => Expression::Bound(v.clone()),
+    })
+}
+
+// The elements of a group joined in order: the triples extend the basic graph pattern they
+// follow, an OPTIONAL is left-joined to what precedes it and the filters apply to the whole group
+fn group(
+    prefixes: &HashMap<String, String>,
+    elements: &[RawElement],
+) -> Result<GraphPattern, SparqlError> {
+    let (pattern, filters) = filtered_group(prefixes, elements)?;
+    Ok(filters.into_iter().fold(pattern, |pattern, filter| {
+        GraphPattern::Filter(Box::new(pattern), filter)
+    }))
+}
+
+// The pattern of a group and its filters, still apart: the filters of an OPTIONAL group are
+// evaluated on the solutions it extends
+fn filtered_group(
+    prefixes: &HashMap<String, String>,
+    elements: &[RawElement],
+) -> Result<(GraphPattern, Vec<Expression>), SparqlError> {
+    let mut pattern = GraphPattern::Bgp(vec![]);
+    let mut filters = vec![];
+    for element in elements {
+        pattern = match element {
+            RawElement::Triples(triples) => {
+                let triples = triples
+                    .iter()
+                    .map(|[s, p, o]| {
+                        Ok([term(prefixes, s)?, term(prefixes, p)?, term(prefixes, o)?])
+                    })
+                    .collect::<Result<Vec<_>, SparqlError>>()?;
+                match pattern {
+                    GraphPattern::Bgp(mut bgp) => {
+                        bgp.extend(triples);
+                        GraphPattern::Bgp(bgp)
+                    }
+                    other => {
+                        GraphPattern::Join(Box::new(other), Box::new(GraphPattern::Bgp(triples)))
+                    }
+                }
+            }
+            RawElement::Optional(optional) => {
+                let (optional, filters) = filtered_group(prefixes, optional)?;
+                let filter = filters
+                    .into_iter()
+                    .reduce(|all, filter| Expression::And(Box::new(all), Box::new(filter)));
+                GraphPattern::LeftJoin(Box::new(pattern), Box::new(optional), filter)
+            }
+            RawElement::Union(groups) => {
+                let mut alternatives = groups.iter().map(|elements| group(prefixes, elements));
+                let first = alternatives
+                    .next()
+                    .expect("A union has at least one group")?;
+                let union = alternatives.try_fold(first, |union, alternative| {
+                    Ok::<_, SparqlError>(GraphPattern::Union(
+                        Box::new(union),
+                        Box::new(alternative?),
+                    ))
+                })?;
+                match pattern {
+                    GraphPattern::Bgp(bgp) if bgp.is_empty() => union,
+                    other => GraphPattern::Join(Box::new(other), Box::new(union)),
+                }
+            }
+            RawElement::Filter(filter) => {
+                filters.push(expression(prefixes, filter)?);
+                pattern
+            }
+        };
+    }
+    Ok((pattern, filters))
+}
+
+fn declare(declarations: Vec<(String, String)>) -> Result<HashMap<String, String>, SparqlError> {
+    let mut prefixes = HashMap::new();
+    for (prefix, namespace) in declarations {
+        if !prefix.ends_with(':') {
+            return Err(SparqlError::Parse(format!(
+                "{} is not a prefix, it should end with ':'",
+                prefix
+            )));
+        }
+        prefixes.insert(prefix, namespace);
+    }
+    Ok(prefixes)
+}
+
+/// Parses a SELECT query, see the module documentation for the supported subset of SPARQL
+pub fn parse_query(input: &str) -> Result<Query, SparqlError> {
+    let raw = sparql::QueryParser::new()
+        .parse(input)
+        .map_err(|e| SparqlError::Parse(e.to_string()))?;
+
+    let prefixes = declare(raw.prefixes)?;
+    let pattern = group(&prefixes, &raw.pattern)?;
+    let limit = raw
+        .limit
+        .map(|limit| {
+            limit
+                .parse()
+                .map_err(|_| SparqlError::Parse(format!("{} is not a valid limit", limit)))
+        })
+        .transpose()?;
+    let variables = match raw.projection {
+        Some(variables) => variables,
+        None => pattern.variables(),
+    };
+    Ok(Query::new(variables, raw.distinct, pattern, limit))
+}
+
+/// Parses a basic graph pattern, written as the group of a WHERE clause or as a single triple
+/// pattern without braces, after its PREFIX declarations. The query selects all of its variables.
+pub fn parse_pattern(input: &str) -> Result<Query, SparqlError> {
+    let (declarations, elements) = sparql::PatternParser::new()
+        .parse(input)
+        .map_err(|e| SparqlError::Parse(e.to_string()))?;
+    let prefixes = declare(declarations)?;
+    match group(&prefixes, &elements)? {
+        pattern @ GraphPattern::Bgp(_) => Ok(Query::new(pattern.variables(), false, pattern, None)),
+        _ => Err(SparqlError::Parse(String::from(
+            "Only triple patterns are allowed in a basic graph pattern",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pattern, parse_query};
+    use crate::model::{BuiltinPredicate, RDF_TYPE, XSD_INTEGER};
+    use crate::sparql::{Expression, GraphPattern, QueryTerm};
+
+    #[test]
+    fn parses_lubm_queries() {
+        let query = parse_query(
+            "
+            PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
+            PREFIX ub: <http://swat.cse.lehigh.edu/onto/univ-bench.owl#>
+            SELECT ?X
+            WHERE
+            {?X rdf:type ub:GraduateStudent .
+              ?X ub:takesCourse
+            <http://www.Department0.University0.edu/GraduateCourse0>}
+            ",
+        )
+        .unwrap();
+        assert_eq!(query.variables(), ["X"]);
+        assert!(!query.distinct());
+        match query.pattern() {
+            GraphPattern::Bgp(triples) => {
+                assert_eq!(triples.len(), 2);
+                assert_eq!(triples[0][1], QueryTerm::Constant(RDF_TYPE.to_string()));
+                assert_eq!(
+                    triples[0][2],
+                    QueryTerm::Constant(
+                        "<http://swat.cse.lehigh.edu/onto/univ-bench.owl#GraduateStudent>"
+                            .to_string()
+                    )
+                );
+            }
+            other => panic!("Expected a basic graph pattern, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_optional_union_filters_and_modifiers() {
+        let query = parse_query(
+            "
+            prefix : <http://example.org/>
+            select distinct * where {
+                { ?x a :Student } union { ?x a :Professor } .
+                ?x :age ?age ; :name ?name .
+                optional { ?x :advisor ?a }
+                filter (?age >= 18 && !bound(?a) || regex(?name, \"^S\"))
+            } limit 10
+            ",
+        )
+        .unwrap();
+        assert_eq!(query.variables(), ["x", "age", "name", "a"]);
+        assert!(query.distinct());
+        assert_eq!(query.limit(), Some(10));
+        let (pattern, filter) = match query.pattern() {
+            GraphPattern::Filter(pattern, filter) => (pattern, filter),
+            other => panic!("Expected a filter, got {:?}", other),
+        };
+        assert!(matches!(
+            &**pattern,
+            GraphPattern::LeftJoin(left, _, None) if matches!(&**left, GraphPattern::Join(union, bgp)
+                if matches!(&**union, GraphPattern::Union(_, _))
+                    && matches!(&**bgp, GraphPattern::Bgp(triples) if triples.len() == 2))
+        ));
+        match filter {
+            Expression::Or(left, _) => assert!(matches!(
+                &**left,
+                Expression::And(comparison, _) if **comparison == Expression::Compare(
+                    BuiltinPredicate::GreaterOrEqual,
+                    QueryTerm::Variable("age".to_string()),
+                    QueryTerm::Constant(format!("\"18\"^^{}", XSD_INTEGER)),
+                )
+            )),
+            other => panic!("Expected a disjunction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        assert!(parse_query("SELECT ?x WHERE { ?x ex:p ?y }").is_err());
+        assert!(parse_query("SELECT ?x WHERE { ?x <http://a> }").is_err());
+        assert!(parse_query("SELECT WHERE { ?x <http://a> ?y }").is_err());
+        assert!(parse_query("SELECT ?x { ?x <http://a> ?y } LIMIT -1").is_err());
+    }
+
+    #[test]
+    fn parses_basic_graph_patterns() {
+        let pattern = parse_pattern(
+            "
+            PREFIX ub: <http://swat.cse.lehigh.edu/onto/univ-bench.owl#>
+            { ?x a ub:GraduateStudent ; ub:takesCourse ?c . ?p ub:teacherOf ?c }
+            ",
+        )
+        .unwrap();
+        assert_eq!(pattern.variables(), ["x", "c", "p"]);
+        assert!(matches!(pattern.pattern(), GraphPattern::Bgp(triples) if triples.len() == 3));
+
+        let pattern = parse_pattern("?x <http://knows> <http://bob> .").unwrap();
+        assert_eq!(pattern.variables(), ["x"]);
+        assert!(parse_pattern("{ ?x <http://a> ?y OPTIONAL { ?y <http://b> ?z } }").is_err());
+        assert!(parse_pattern("SELECT ?x WHERE { ?x <http://a> ?y }").is_err());
+    }
+}
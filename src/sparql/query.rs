@@ -0,0 +1,365 @@
+use crate::datalog::{Operand, Term};
+use crate::model::{BuiltinPredicate, Literal};
+use crate::sparql::{parse_query, SparqlError};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A term of a query: a variable, or a constant written as in the N-Triples data once its
+/// prefixed name is expanded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTerm {
+    /// Name of the variable, without `?`
+    Variable(String),
+    /// IRI or literal
+    Constant(String),
+}
+
+/// The expression of a FILTER
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    /// `||`
+    Or(Box<Expression>, Box<Expression>),
+    /// `&&`
+    And(Box<Expression>, Box<Expression>),
+    /// `!`
+    Not(Box<Expression>),
+    /// A comparison or one of the string functions, with the same semantics as in the rules
+    Compare(BuiltinPredicate, QueryTerm, QueryTerm),
+    /// `bound(?v)`
+    Bound(String),
+}
+
+/// The graph pattern of a query, as the SPARQL algebra builds it from the groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphPattern {
+    /// A basic graph pattern: triple patterns joined on their shared variables
+    Bgp(Vec<[QueryTerm; 3]>),
+    /// Two patterns joined on their shared variables
+    Join(Box<GraphPattern>, Box<GraphPattern>),
+    /// The solutions of the first pattern, extended by the compatible ones of the second that
+    /// satisfy the expression when there are some: `OPTIONAL`, with the filters of its group
+    LeftJoin(Box<GraphPattern>, Box<GraphPattern>, Option<Expression>),
+    /// The solutions of both patterns
+    Union(Box<GraphPattern>, Box<GraphPattern>),
+    /// The solutions of the pattern that satisfy the expression
+    Filter(Box<GraphPattern>, Expression),
+}
+
+impl GraphPattern {
+    /// The variables of the triple patterns, in order of first appearance
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables = vec![];
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables(&self, variables: &mut Vec<String>) {
+        match self {
+            GraphPattern::Bgp(triples) => {
+                for term in triples.iter().flatten() {
+                    if let QueryTerm::Variable(v) = term {
+                        if !variables.contains(v) {
+                            variables.push(v.clone());
+                        }
+                    }
+                }
+            }
+            GraphPattern::Join(left, right)
+            | GraphPattern::LeftJoin(left, right, _)
+            | GraphPattern::Union(left, right) => {
+                left.collect_variables(variables);
+                right.collect_variables(variables);
+            }
+            GraphPattern::Filter(pattern, _) => pattern.collect_variables(variables),
+        }
+    }
+}
+
+/// A parsed SELECT query, whose constants are still the terms written in the query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    variables: Vec<String>,
+    distinct: bool,
+    pattern: GraphPattern,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub(crate) fn new(
+        variables: Vec<String>,
+        distinct: bool,
+        pattern: GraphPattern,
+        limit: Option<usize>,
+    ) -> Self {
+        Self {
+            variables,
+            distinct,
+            pattern,
+            limit,
+        }
+    }
+
+    /// Parses a query, see the module documentation for the supported subset of SPARQL
+    pub fn parse(input: &str) -> Result<Self, SparqlError> {
+        parse_query(input)
+    }
+
+    /// Reads and parses the query of a file
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, SparqlError> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .map_err(|e| SparqlError::Parse(format!("Could not read {}: {}", path.display(), e)))?;
+        Self::parse(&input)
+    }
+
+    /// The selected variables, all the variables of the pattern for `SELECT *`
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Whether the duplicated solutions are removed
+    pub fn distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// The graph pattern of the WHERE clause
+    pub fn pattern(&self) -> &GraphPattern {
+        &self.pattern
+    }
+
+    /// The largest number of solutions to read
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Translates the constants of the query in the encoded domain through `resolve`. The
+    /// filters know no literal of the data until [`CompiledQuery::with_literals`] is called.
+    pub fn compile<V, F>(&self, mut resolve: F) -> Result<CompiledQuery<V>, SparqlError>
+    where
+        F: FnMut(&str) -> Option<V>,
+    {
+        // The selected variables come first, so that a solution starts with its projection
+        let mut variables = self.variables.clone();
+        for variable in self.pattern.variables() {
+            if !variables.contains(&variable) {
+                variables.push(variable);
+            }
+        }
+        let pattern = compile_pattern(&self.pattern, &variables, &mut resolve)?;
+        Ok(CompiledQuery {
+            selected: self.variables.len(),
+            variables,
+            distinct: self.distinct,
+            limit: self.limit,
+            pattern,
+            literals: Arc::new(HashMap::new()),
+        })
+    }
+}
+
+// A variable that only appears in a filter or in the projection is never bound, it is given an
+// index all the same
+fn index(variables: &mut Vec<String>, variable: &str) -> usize {
+    match variables.iter().position(|v| v == variable) {
+        Some(i) => i,
+        None => {
+            variables.push(variable.to_string());
+            variables.len() - 1
+        }
+    }
+}
+
+fn compile_term<V, F>(
+    term: &QueryTerm,
+    variables: &[String],
+    resolve: &mut F,
+) -> Result<Term<V>, SparqlError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    match term {
+        QueryTerm::Variable(v) => Ok(Term::Variable(
+            variables
+                .iter()
+                .position(|x| x == v)
+                .expect("The variables of the pattern are indexed"),
+        )),
+        QueryTerm::Constant(c) => resolve(c)
+            .map(Term::Constant)
+            .ok_or_else(|| SparqlError::UnresolvedConstant(c.clone())),
+    }
+}
+
+fn compile_pattern<V, F>(
+    pattern: &GraphPattern,
+    variables: &[String],
+    resolve: &mut F,
+) -> Result<CompiledPattern<V>, SparqlError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    let mut boxed =
+        |pattern: &GraphPattern| compile_pattern(pattern, variables, resolve).map(Box::new);
+    Ok(match pattern {
+        GraphPattern::Bgp(triples) => CompiledPattern::Bgp(
+            triples
+                .iter()
+                .map(|[s, p, o]| {
+                    Ok([
+                        compile_term(s, variables, resolve)?,
+                        compile_term(p, variables, resolve)?,
+                        compile_term(o, variables, resolve)?,
+                    ])
+                })
+                .collect::<Result<_, SparqlError>>()?,
+        ),
+        GraphPattern::Join(left, right) => {
+            let left = boxed(left)?;
+            CompiledPattern::Join(left, boxed(right)?)
+        }
+        GraphPattern::LeftJoin(left, right, expression) => {
+            let left = boxed(left)?;
+            let right = boxed(right)?;
+            let mut variables = variables.to_vec();
+            let expression = expression
+                .as_ref()
+                .map(|expression| compile_expression(expression, &mut variables, resolve))
+                .transpose()?;
+            CompiledPattern::LeftJoin(left, right, expression)
+        }
+        GraphPattern::Union(left, right) => {
+            let left = boxed(left)?;
+            CompiledPattern::Union(left, boxed(right)?)
+        }
+        GraphPattern::Filter(inner, expression) => {
+            let inner = boxed(inner)?;
+            let mut variables = variables.to_vec();
+            CompiledPattern::Filter(
+                inner,
+                compile_expression(expression, &mut variables, resolve)?,
+            )
+        }
+    })
+}
+
+fn compile_expression<V, F>(
+    expression: &Expression,
+    variables: &mut Vec<String>,
+    resolve: &mut F,
+) -> Result<CompiledExpression<V>, SparqlError>
+where
+    F: FnMut(&str) -> Option<V>,
+{
+    let mut boxed =
+        |expression: &Expression| compile_expression(expression, variables, resolve).map(Box::new);
+    Ok(match expression {
+        Expression::Or(left, right) => {
+            let left = boxed(left)?;
+            CompiledExpression::Or(left, boxed(right)?)
+        }
+        Expression::And(left, right) => {
+            let left = boxed(left)?;
+            CompiledExpression::And(left, boxed(right)?)
+        }
+        Expression::Not(inner) => CompiledExpression::Not(boxed(inner)?),
+        Expression::Bound(v) => CompiledExpression::Bound(index(variables, v)),
+        Expression::Compare(predicate, left, right) => {
+            let mut operand = |term: &QueryTerm| match term {
+                QueryTerm::Variable(v) => Ok(Operand::Term(Term::Variable(index(variables, v)))),
+                QueryTerm::Constant(c) => match Literal::parse(c) {
+                    Some(literal) => Ok(Operand::Literal(literal)),
+                    None => resolve(c)
+                        .map(|c| Operand::Term(Term::Constant(c)))
+                        .ok_or_else(|| SparqlError::UnresolvedConstant(c.clone())),
+                },
+            };
+            let arguments = [operand(left)?, operand(right)?];
+            let pattern = match (predicate, &arguments[1]) {
+                (BuiltinPredicate::Regex, Operand::Literal(pattern)) => Some(
+                    Regex::new(&pattern.lexical).map_err(|e| SparqlError::InvalidFilter {
+                        filter: format!("{:?}", expression),
+                        reason: e.to_string(),
+                    })?,
+                ),
+                (BuiltinPredicate::Regex, _) => {
+                    return Err(SparqlError::InvalidFilter {
+                        filter: format!("{:?}", expression),
+                        reason: String::from("the pattern of a regex must be a literal"),
+                    })
+                }
+                _ => None,
+            };
+            CompiledExpression::Compare(*predicate, arguments, pattern)
+        }
+    })
+}
+
+/// A graph pattern whose variables are replaced by their index in the solutions and constants
+/// by their encoding
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledPattern<V> {
+    Bgp(Vec<[Term<V>; 3]>),
+    Join(Box<CompiledPattern<V>>, Box<CompiledPattern<V>>),
+    LeftJoin(
+        Box<CompiledPattern<V>>,
+        Box<CompiledPattern<V>>,
+        Option<CompiledExpression<V>>,
+    ),
+    Union(Box<CompiledPattern<V>>, Box<CompiledPattern<V>>),
+    Filter(Box<CompiledPattern<V>>, CompiledExpression<V>),
+}
+
+/// A filter expression whose variables are replaced by their index in the solutions. A
+/// comparison carries the compiled pattern of a regex.
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledExpression<V> {
+    Or(Box<CompiledExpression<V>>, Box<CompiledExpression<V>>),
+    And(Box<CompiledExpression<V>>, Box<CompiledExpression<V>>),
+    Not(Box<CompiledExpression<V>>),
+    Compare(BuiltinPredicate, [Operand<V>; 2], Option<Regex>),
+    Bound(usize),
+}
+
+/// A query whose constants are encoded, ready to be evaluated on a collection of encoded
+/// triples
+#[derive(Debug, Clone)]
+pub struct CompiledQuery<V> {
+    // Every variable of the query, the selected ones first
+    variables: Vec<String>,
+    selected: usize,
+    distinct: bool,
+    limit: Option<usize>,
+    pub(crate) pattern: CompiledPattern<V>,
+    // The literals of the data, by encoding, for the filters
+    pub(crate) literals: Arc<HashMap<V, Literal>>,
+}
+
+impl<V> CompiledQuery<V> {
+    /// Gives the filters the value of the literals of the data, by encoding. An encoding that is
+    /// not among them is compared as an opaque term.
+    pub fn with_literals(mut self, literals: HashMap<V, Literal>) -> Self {
+        self.literals = Arc::new(literals);
+        self
+    }
+
+    /// The selected variables, in the order of the values of a solution
+    pub fn variables(&self) -> &[String] {
+        &self.variables[..self.selected]
+    }
+
+    /// Whether the duplicated solutions are removed
+    pub fn distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// The largest number of solutions to read
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    // Number of values of a solution before the projection
+    pub(crate) fn width(&self) -> usize {
+        self.variables.len()
+    }
+}
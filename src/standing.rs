@@ -0,0 +1,245 @@
+//! Standing queries, whose changes of answer are reported after every update.
+//!
+//! A standing query is a basic graph pattern, or a single triple pattern, registered on a worker
+//! whose dataflow maintains a materialization. Its solutions are computed in a dataflow of their
+//! own that imports the arrangement of the materialization, so a query can be registered at any
+//! time, and they are gathered on the first worker. After every update, the bindings that start
+//! or stop being solutions at its timestamp are delivered as deltas, `+1` for a new binding and
+//! `-1` for a retracted one. The first delivery holds the answer at the time of the registration.
+use crate::encoder::{BiMapTrait, Triple};
+use crate::sparql::{parse_pattern, CompiledQuery, SparqlError};
+use differential_dataflow::operators::arrange::TraceAgent;
+use differential_dataflow::operators::consolidate::Consolidate;
+use differential_dataflow::trace::implementations::ord::OrdKeySpine;
+use differential_dataflow::{AsCollection, ExchangeData};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::dataflow::operators::Exchange;
+use timely::dataflow::ProbeHandle;
+use timely::worker::Worker;
+
+/// A change of the answer of a standing query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta<V> {
+    /// Timestamp of the change: 0 for the full materialization, `i` for the `i`-th update
+    pub time: usize,
+    /// The values of the variables of the query, in the order of
+    /// [`StandingQuery::variables`]
+    pub binding: Vec<V>,
+    /// `+1` when the binding becomes a solution, `-1` when it stops being one
+    pub diff: isize,
+}
+
+impl<V: std::hash::Hash + Eq + std::fmt::Debug> Delta<V> {
+    /// The binding with the terms of the dictionary
+    pub fn decode<'m, K, M>(&self, map: &'m M) -> Vec<&'m K>
+    where
+        K: Eq + std::hash::Hash + std::fmt::Debug,
+        M: BiMapTrait<K, V>,
+    {
+        self.binding
+            .iter()
+            .map(|value| map.get_left(value).expect("Could not find the term"))
+            .collect()
+    }
+}
+
+/// A basic graph pattern whose answer is followed through the updates
+#[derive(Debug, Clone)]
+pub struct StandingQuery<V> {
+    name: String,
+    query: CompiledQuery<V>,
+}
+
+impl<V> StandingQuery<V>
+where
+    V: ExchangeData + std::hash::Hash,
+{
+    /// Parses a basic graph pattern or a triple pattern, see [`parse_pattern`], and encodes its
+    /// constants through `resolve`
+    pub fn parse<F>(name: &str, pattern: &str, resolve: F) -> Result<Self, SparqlError>
+    where
+        F: FnMut(&str) -> Option<V>,
+    {
+        Ok(StandingQuery {
+            name: name.to_string(),
+            query: parse_pattern(pattern)?.compile(resolve)?,
+        })
+    }
+
+    /// The name the query was registered with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The variables of the query, in order of first appearance
+    pub fn variables(&self) -> &[String] {
+        self.query.variables()
+    }
+
+    /// Follows the answer of the query on the triples of `trace`, in a new dataflow of `worker`.
+    /// `probe` is advanced along with the solutions, so that the worker stepped until the
+    /// probe of the materialization passes an update has its deltas. Every worker must register
+    /// the same queries, in the same order.
+    pub fn register<A, T>(
+        &self,
+        worker: &mut Worker<A>,
+        trace: &mut TraceAgent<OrdKeySpine<T, usize, isize>>,
+        probe: &mut ProbeHandle<usize>,
+    ) -> Deltas<V>
+    where
+        A: Allocate,
+        T: ExchangeData + Triple<V>,
+    {
+        let pending = Rc::new(RefCell::new(vec![]));
+        let sink = pending.clone();
+        worker.dataflow::<usize, _, _>(|scope| {
+            let facts = trace
+                .import(scope)
+                .as_collection(|triple, _| triple.clone());
+            self.query
+                .solutions(&facts)
+                .consolidate()
+                .inner
+                .exchange(|_| 0)
+                .as_collection()
+                .inspect(move |update| sink.borrow_mut().push(update.clone()))
+                .probe_with(probe);
+        });
+        Deltas { pending }
+    }
+}
+
+/// The changes of the answer of a registered query that have not been delivered yet. They are
+/// all held by the first worker.
+pub struct Deltas<V> {
+    pending: Rc<RefCell<Vec<Change<V>>>>,
+}
+
+// A solution with its timestamp and multiplicity, as it leaves the dataflow
+type Change<V> = (Vec<Option<V>>, usize, isize);
+
+impl<V: Ord + Clone> Deltas<V> {
+    /// Hands the changes at the timestamps before `time` to `callback`, by timestamp and then by
+    /// binding. The changes of a binding at a timestamp are summed up, so a binding is either
+    /// added or retracted, as many times as it is derived.
+    pub fn deliver<F>(&mut self, time: usize, mut callback: F)
+    where
+        F: FnMut(&Delta<V>),
+    {
+        let mut changes = BTreeMap::new();
+        self.pending.borrow_mut().retain(|(solution, t, diff)| {
+            if *t < time {
+                *changes.entry((*t, solution.clone())).or_insert(0) += diff;
+            }
+            *t >= time
+        });
+        for ((time, solution), diff) in changes {
+            if diff != 0 {
+                callback(&Delta {
+                    time,
+                    binding: solution
+                        .into_iter()
+                        .map(|value| value.expect("A basic graph pattern binds all its variables"))
+                        .collect(),
+                    diff,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delta, StandingQuery};
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::arrange::ArrangeBySelf;
+    use std::collections::HashMap;
+
+    #[test]
+    fn delivers_the_changes_of_the_answer_after_every_update() {
+        let terms = [
+            "<a>",
+            "<b>",
+            "<c>",
+            "<type>",
+            "<Student>",
+            "<takes>",
+            "<teaches>",
+            "<p>",
+        ];
+        let encoding: HashMap<&str, u64> = terms.iter().zip(0..).map(|(t, e)| (*t, e)).collect();
+        let e = |term: &str| encoding[term];
+        let (a, b, c, ty, student) = (e("<a>"), e("<b>"), e("<c>"), e("<type>"), e("<Student>"));
+        let (takes, teaches, p) = (e("<takes>"), e("<teaches>"), e("<p>"));
+        let batches = [
+            vec![
+                ((a, ty, student), 1),
+                ((a, takes, c), 1),
+                ((p, teaches, c), 1),
+            ],
+            vec![((b, ty, student), 1), ((b, takes, c), 1)],
+            vec![((a, takes, c), -1)],
+            vec![((p, teaches, c), -1)],
+        ];
+        let courses = StandingQuery::parse(
+            "courses",
+            "{ ?x <type> <Student> . ?x <takes> ?c . <p> <teaches> ?c }",
+            |term| encoding.get(term).copied(),
+        )
+        .unwrap();
+        let students = StandingQuery::parse("students", "?x <type> <Student>", |term| {
+            encoding.get(term).copied()
+        })
+        .unwrap();
+        assert_eq!(courses.variables(), ["x", "c"]);
+
+        let delivered = timely::execute_directly(move |worker| {
+            let mut probe = timely::dataflow::ProbeHandle::new();
+            let (mut input, mut trace) = worker.dataflow::<usize, _, _>(|scope| {
+                let (input, triples) = scope.new_collection::<(u64, u64, u64), isize>();
+                (input, triples.arrange_by_self().trace)
+            });
+            let mut deltas = [
+                courses.register(worker, &mut trace, &mut probe),
+                students.register(worker, &mut trace, &mut probe),
+            ];
+            let mut delivered = vec![vec![], vec![]];
+            for (time, batch) in batches.iter().enumerate() {
+                for (triple, diff) in batch {
+                    input.update(*triple, *diff);
+                }
+                input.advance_to(time + 1);
+                input.flush();
+                while probe.less_than(input.time()) {
+                    worker.step();
+                }
+                for (deltas, delivered) in deltas.iter_mut().zip(delivered.iter_mut()) {
+                    deltas.deliver(time + 1, |delta| delivered.push(delta.clone()));
+                }
+            }
+            delivered
+        });
+
+        let delta = |time, binding, diff| Delta {
+            time,
+            binding,
+            diff,
+        };
+        assert_eq!(
+            delivered[0],
+            vec![
+                delta(0, vec![a, c], 1),
+                delta(1, vec![b, c], 1),
+                delta(2, vec![a, c], -1),
+                delta(3, vec![b, c], -1),
+            ]
+        );
+        assert_eq!(
+            delivered[1],
+            vec![delta(0, vec![a], 1), delta(1, vec![b], 1)]
+        );
+    }
+}
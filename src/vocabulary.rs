@@ -0,0 +1,388 @@
+//! Vocabulary registry.
+//!
+//! A rule set declares the vocabulary it needs with a [`VocabularyDeclaration`]. Before the
+//! dataflow starts, `run_materialization` resolves every declared IRI through the encoder into a
+//! [`Vocabulary`], which the rules then query by name or by typed key:
+//!
+//! ```ignore
+//! let sco = vocabulary.get("rdfs:subClassOf")?;
+//! let sco = vocabulary.term::<SubClassOf>()?;
+//! ```
+//!
+//! A declaration can also ask for the terms of the data to be indexed, for the rules that depend
+//! on the shape of a term rather than on the triples it appears in: the datatype of the literals
+//! and the container membership properties.
+use crate::model::{
+    is_container_membership_property, Literal, OWL_CARDINALITY, OWL_INTERSECTION_OF,
+    OWL_MAX_CARDINALITY, OWL_NOTHING, OWL_ON_PROPERTY, OWL_PROPERTY_CHAIN_AXIOM, OWL_SAME_AS,
+    RDFS_CLASS, RDFS_CONTAINER_MEMBERSHIP_PROPERTY, RDFS_DATATYPE, RDFS_DOMAIN, RDFS_LITERAL,
+    RDFS_MEMBER, RDFS_RANGE, RDFS_RESOURCE, RDFS_SUB_CLASS_OF, RDFS_SUB_PROPERTY_OF, RDF_FIRST,
+    RDF_NIL, RDF_PROPERTY, RDF_REST, RDF_TYPE,
+};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A typed key of the vocabulary
+pub trait VocabularyKey {
+    /// Name the term is registered under
+    const NAME: &'static str;
+    /// IRI of the term, as it appears in the N-Triples data
+    const IRI: &'static str;
+}
+
+macro_rules! vocabulary_keys {
+    ($($(#[$doc:meta])* $key:ident => $name:expr, $iri:expr;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            pub struct $key;
+
+            impl VocabularyKey for $key {
+                const NAME: &'static str = $name;
+                const IRI: &'static str = $iri;
+            }
+        )*
+    };
+}
+
+vocabulary_keys! {
+    /// rdfs:subClassOf
+    SubClassOf => "rdfs:subClassOf", RDFS_SUB_CLASS_OF;
+    /// rdfs:subPropertyOf
+    SubPropertyOf => "rdfs:subPropertyOf", RDFS_SUB_PROPERTY_OF;
+    /// rdf:type
+    Type => "rdf:type", RDF_TYPE;
+    /// rdfs:domain
+    Domain => "rdfs:domain", RDFS_DOMAIN;
+    /// rdfs:range
+    Range => "rdfs:range", RDFS_RANGE;
+    /// rdfs:Resource
+    Resource => "rdfs:Resource", RDFS_RESOURCE;
+    /// rdfs:Class
+    Class => "rdfs:Class", RDFS_CLASS;
+    /// rdf:Property
+    Property => "rdf:Property", RDF_PROPERTY;
+    /// rdfs:Literal
+    LiteralClass => "rdfs:Literal", RDFS_LITERAL;
+    /// rdfs:Datatype
+    Datatype => "rdfs:Datatype", RDFS_DATATYPE;
+    /// rdfs:ContainerMembershipProperty
+    ContainerMembershipProperty => "rdfs:ContainerMembershipProperty", RDFS_CONTAINER_MEMBERSHIP_PROPERTY;
+    /// rdfs:member
+    Member => "rdfs:member", RDFS_MEMBER;
+    /// rdf:first
+    First => "rdf:first", RDF_FIRST;
+    /// rdf:rest
+    Rest => "rdf:rest", RDF_REST;
+    /// rdf:nil
+    Nil => "rdf:nil", RDF_NIL;
+    /// owl:intersectionOf
+    IntersectionOf => "owl:intersectionOf", OWL_INTERSECTION_OF;
+    /// owl:propertyChainAxiom
+    PropertyChainAxiom => "owl:propertyChainAxiom", OWL_PROPERTY_CHAIN_AXIOM;
+    /// owl:sameAs
+    SameAs => "owl:sameAs", OWL_SAME_AS;
+    /// owl:onProperty
+    OnProperty => "owl:onProperty", OWL_ON_PROPERTY;
+    /// owl:maxCardinality
+    MaxCardinality => "owl:maxCardinality", OWL_MAX_CARDINALITY;
+    /// owl:cardinality
+    Cardinality => "owl:cardinality", OWL_CARDINALITY;
+    /// owl:Nothing
+    Nothing => "owl:Nothing", OWL_NOTHING;
+}
+
+/// Errors raised while declaring, resolving or querying a vocabulary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VocabularyError {
+    /// The name was not declared by the rule set
+    Unknown(String),
+    /// The same name was declared with two different IRIs
+    Conflict {
+        /// The name declared twice
+        name: String,
+        /// IRI of the first declaration
+        first: String,
+        /// IRI of the second declaration
+        second: String,
+    },
+    /// The encoder could not provide an encoding for the IRI
+    Unresolved {
+        /// Name of the term
+        name: String,
+        /// IRI of the term
+        iri: String,
+    },
+}
+
+impl std::fmt::Display for VocabularyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VocabularyError::Unknown(name) => {
+                write!(f, "`{}` is not part of the declared vocabulary", name)
+            }
+            VocabularyError::Conflict {
+                name,
+                first,
+                second,
+            } => write!(f, "`{}` is declared as both {} and {}", name, first, second),
+            VocabularyError::Unresolved { name, iri } => {
+                write!(f, "Could not encode `{}` ({})", name, iri)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VocabularyError {}
+
+/// The terms a rule set needs, as pairs of name and IRI.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VocabularyDeclaration {
+    terms: Vec<(String, String)>,
+    index_terms: bool,
+}
+
+impl VocabularyDeclaration {
+    /// An empty declaration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the term `iri` under `name`
+    pub fn with_iri<N: Into<String>, I: Into<String>>(mut self, name: N, iri: I) -> Self {
+        self.terms.push((name.into(), iri.into()));
+        self
+    }
+
+    /// Declares a typed key
+    pub fn with<K: VocabularyKey>(self) -> Self {
+        self.with_iri(K::NAME, K::IRI)
+    }
+
+    /// Asks for the terms of the data to be indexed, see [`Vocabulary::index_terms`]
+    pub fn indexing_terms(mut self) -> Self {
+        self.index_terms = true;
+        self
+    }
+
+    /// Declares all the terms of `other` as well, e.g. when a profile extends another one
+    pub fn merge(mut self, other: &VocabularyDeclaration) -> Self {
+        self.terms.extend(other.terms.iter().cloned());
+        self.index_terms |= other.index_terms;
+        self
+    }
+
+    /// Whether the terms of the data have to be indexed
+    pub fn indexes_terms(&self) -> bool {
+        self.index_terms
+    }
+
+    /// The declared pairs of name and IRI
+    pub fn terms(&self) -> &[(String, String)] {
+        &self.terms
+    }
+}
+
+/// A vocabulary whose terms are encoded.
+#[derive(Debug, Clone)]
+pub struct Vocabulary<V> {
+    terms: HashMap<String, (String, V)>,
+    // Filled by `index_terms`
+    literals: HashMap<V, (Literal, V)>,
+    container_memberships: Vec<V>,
+    // Filled by `record_properties`
+    properties: Vec<V>,
+}
+
+impl<V: Copy> Vocabulary<V> {
+    /// Encodes every term of the declaration with `encode`, which receives the IRI of the term.
+    pub fn resolve<F>(
+        declaration: &VocabularyDeclaration,
+        mut encode: F,
+    ) -> Result<Self, VocabularyError>
+    where
+        F: FnMut(&str) -> Option<V>,
+    {
+        let mut terms: HashMap<String, (String, V)> = HashMap::new();
+        for (name, iri) in declaration.terms() {
+            if let Some((first, _)) = terms.get(name) {
+                if first != iri {
+                    return Err(VocabularyError::Conflict {
+                        name: name.clone(),
+                        first: first.clone(),
+                        second: iri.clone(),
+                    });
+                }
+                continue;
+            }
+            let encoded = encode(iri).ok_or_else(|| VocabularyError::Unresolved {
+                name: name.clone(),
+                iri: iri.clone(),
+            })?;
+            terms.insert(name.clone(), (iri.clone(), encoded));
+        }
+        Ok(Self {
+            terms,
+            literals: HashMap::new(),
+            container_memberships: vec![],
+            properties: vec![],
+        })
+    }
+
+    /// The encoding of the term declared under `name`
+    pub fn get(&self, name: &str) -> Result<V, VocabularyError> {
+        self.terms
+            .get(name)
+            .map(|(_, encoded)| *encoded)
+            .ok_or_else(|| VocabularyError::Unknown(name.to_string()))
+    }
+
+    /// The encoding of a typed key
+    pub fn term<K: VocabularyKey>(&self) -> Result<V, VocabularyError> {
+        self.get(K::NAME)
+    }
+
+    /// The IRI of the term declared under `name`
+    pub fn iri(&self, name: &str) -> Result<&str, VocabularyError> {
+        self.terms
+            .get(name)
+            .map(|(iri, _)| iri.as_str())
+            .ok_or_else(|| VocabularyError::Unknown(name.to_string()))
+    }
+}
+
+impl<V: Copy + Eq + Hash> Vocabulary<V> {
+    /// Records the literals and the container membership properties among `terms`, the pairs of
+    /// term and encoding known to the encoder. The datatypes of the literals are encoded with
+    /// `encode`.
+    pub fn index_terms<I, S, F>(&mut self, terms: I, mut encode: F) -> Result<(), VocabularyError>
+    where
+        I: IntoIterator<Item = (S, V)>,
+        S: AsRef<str>,
+        F: FnMut(&str) -> Option<V>,
+    {
+        for (term, encoded) in terms {
+            let term = term.as_ref();
+            if let Some(literal) = Literal::parse(term) {
+                let datatype =
+                    encode(&literal.datatype).ok_or_else(|| VocabularyError::Unresolved {
+                        name: term.to_string(),
+                        iri: literal.datatype.clone(),
+                    })?;
+                self.literals.insert(encoded, (literal, datatype));
+            } else if is_container_membership_property(term) {
+                self.container_memberships.push(encoded);
+            }
+        }
+        Ok(())
+    }
+
+    /// The literal encoded as `term`, if it is one
+    pub fn literal(&self, term: V) -> Option<&Literal> {
+        self.literals.get(&term).map(|(literal, _)| literal)
+    }
+
+    /// Every indexed literal, by encoding
+    pub fn literals(&self) -> HashMap<V, Literal> {
+        self.literals
+            .iter()
+            .map(|(encoded, (literal, _))| (*encoded, literal.clone()))
+            .collect()
+    }
+
+    /// The encoding of the datatype of every indexed literal, by literal
+    pub fn literal_datatypes(&self) -> HashMap<V, V> {
+        self.literals
+            .iter()
+            .map(|(literal, (_, datatype))| (*literal, *datatype))
+            .collect()
+    }
+
+    /// The container membership properties found among the indexed terms
+    pub fn container_membership_properties(&self) -> &[V] {
+        &self.container_memberships
+    }
+
+    /// Records the encodings the encoder found as predicates, the properties of the data
+    pub fn record_properties<I: IntoIterator<Item = V>>(&mut self, properties: I) {
+        for property in properties {
+            if !self.properties.contains(&property) {
+                self.properties.push(property);
+            }
+        }
+    }
+
+    /// The properties recorded with `record_properties`
+    pub fn properties(&self) -> &[V] {
+        &self.properties
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_by_name_and_key() {
+        let declaration = VocabularyDeclaration::new()
+            .with::<SubClassOf>()
+            .with::<Type>()
+            .with_iri("owl:sameAs", "<http://www.w3.org/2002/07/owl#sameAs>");
+        let vocabulary = Vocabulary::resolve(&declaration, |iri| Some(iri.len() as u64)).unwrap();
+
+        assert_eq!(
+            vocabulary.term::<SubClassOf>(),
+            Ok(RDFS_SUB_CLASS_OF.len() as u64)
+        );
+        assert_eq!(vocabulary.get("owl:sameAs"), Ok(38));
+        assert_eq!(
+            vocabulary.term::<Range>(),
+            Err(VocabularyError::Unknown(String::from("rdfs:range")))
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_and_unresolved_terms() {
+        let conflicting = VocabularyDeclaration::new()
+            .with::<Type>()
+            .with_iri("rdf:type", "<http://example.org/type>");
+        assert!(matches!(
+            Vocabulary::resolve(&conflicting, |_| Some(0)),
+            Err(VocabularyError::Conflict { .. })
+        ));
+
+        let declaration = VocabularyDeclaration::new().with::<Domain>();
+        assert!(matches!(
+            Vocabulary::<u64>::resolve(&declaration, |_| None),
+            Err(VocabularyError::Unresolved { .. })
+        ));
+    }
+
+    #[test]
+    fn indexes_literals_and_container_membership_properties() {
+        let declaration = VocabularyDeclaration::new().indexing_terms();
+        let mut vocabulary = Vocabulary::resolve(&declaration, |_| None).unwrap();
+        let terms = vec![
+            ("<http://example.org/a>", 1),
+            ("\"a\"", 2),
+            ("\"1\"^^<http://www.w3.org/2001/XMLSchema#integer>", 3),
+            ("\"chat\"@fr", 4),
+            ("<http://www.w3.org/1999/02/22-rdf-syntax-ns#_2>", 5),
+            ("<http://www.w3.org/1999/02/22-rdf-syntax-ns#_0>", 6),
+        ];
+        vocabulary
+            .index_terms(terms, |iri| Some(iri.len() as u64))
+            .unwrap();
+
+        assert_eq!(vocabulary.container_membership_properties(), &[5]);
+        assert_eq!(vocabulary.literal(3).unwrap().lexical, "1");
+        assert_eq!(
+            vocabulary.literal(4).unwrap().language.as_deref(),
+            Some("fr")
+        );
+        assert_eq!(vocabulary.literal(1), None);
+        let datatypes = vocabulary.literal_datatypes();
+        assert_eq!(datatypes.len(), 3);
+        assert_eq!(datatypes[&2], crate::model::XSD_STRING.len() as u64);
+    }
+}